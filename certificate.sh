@@ -19,8 +19,10 @@ authorityKeyIdentifier=keyid:always,issuer
 ")
 openssl genrsa -out server.key 2048
 openssl genrsa -out client.key 2048
+openssl genrsa -out dealer.key 2048
 openssl req -new -key server.key -out server.csr -subj "/C=US/ST=California/L=San Francisco/O=YourOrg/OU=YourOrgUnit/CN=server"
 openssl req -new -key client.key -out client.csr -subj "/C=US/ST=California/L=San Francisco/O=YourOrg/OU=YourOrgUnit/CN=client"
+openssl req -new -key dealer.key -out dealer.csr -subj "/C=US/ST=California/L=San Francisco/O=YourOrg/OU=YourOrgUnit/CN=dealer"
 openssl x509 -req -in server.csr -CA ca.crt -CAkey ca.key -CAcreateserial -out server.crt -days 365 -extensions v3_req -extfile <(printf "[ v3_req ]
 basicConstraints = CA:FALSE
 keyUsage = nonRepudiation, digitalSignature, keyEncipherment
@@ -34,3 +36,12 @@ openssl x509 -req -in client.csr -CA ca.crt -CAkey ca.key -CAcreateserial -out c
 basicConstraints = CA:FALSE
 keyUsage = nonRepudiation, digitalSignature, keyEncipherment
 ")
+openssl x509 -req -in dealer.csr -CA ca.crt -CAkey ca.key -CAcreateserial -out dealer.crt -days 365 -extensions v3_req -extfile <(printf "[ v3_req ]
+basicConstraints = CA:FALSE
+keyUsage = nonRepudiation, digitalSignature, keyEncipherment
+subjectAltName = @alt_names
+
+[alt_names]
+DNS.1 = localhost
+IP.1 = 127.0.0.1
+")
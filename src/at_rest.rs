@@ -0,0 +1,280 @@
+// Encryption of the files the server stores, datasets and models, with AES-256-GCM
+// under a master key. The master key is read, hex encoded, from the
+// CRYPI_MASTER_KEY environment variable, or else from the file named by
+// CRYPI_MASTER_KEY_FILE, master.key by default, which is generated if missing.
+// An encrypted file holds the nonce followed by the ciphertext and the tag, its
+// name being authenticated with it.
+
+use crate::csv_file::{self, Dataset};
+use ndarray::Array2;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+pub const MASTER_KEY_ENV: &str = "CRYPI_MASTER_KEY";
+pub const MASTER_KEY_FILE_ENV: &str = "CRYPI_MASTER_KEY_FILE";
+pub const MASTER_KEY_FILE: &str = "master.key";
+
+#[derive(Clone)]
+pub struct MasterKey {
+    key: [u8; 32],
+}
+
+// The key never shows in the logs
+impl std::fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("MasterKey(..)")
+    }
+}
+
+fn from_hex(text: &str) -> Result<[u8; 32], Box<dyn Error>> {
+    let text = text.trim();
+    if text.len() != 64 || !text.is_ascii() {
+        return Err("The master key must be 64 hex digits".into());
+    }
+    let mut key = [0u8; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[2 * i..2 * i + 2], 16)?;
+    }
+    Ok(key)
+}
+
+// Authenticated data of a file: its name, so that files cannot be swapped
+fn aad(path: &Path) -> Vec<u8> {
+    path.file_name()
+        .map(|name| name.to_string_lossy().as_bytes().to_vec())
+        .unwrap_or_default()
+}
+
+impl MasterKey {
+    pub fn load() -> Result<MasterKey, Box<dyn Error>> {
+        if let Ok(hex) = std::env::var(MASTER_KEY_ENV) {
+            return Ok(MasterKey {
+                key: from_hex(&hex)?,
+            });
+        }
+
+        let path = std::env::var(MASTER_KEY_FILE_ENV).unwrap_or(MASTER_KEY_FILE.to_string());
+        if !Path::new(&path).exists() {
+            let mut key = [0u8; 32];
+            SystemRandom::new()
+                .fill(&mut key)
+                .map_err(|_| "Failed to generate the master key")?;
+            let hex: String = key.iter().map(|b| format!("{:02x}", b)).collect();
+            write_private(Path::new(&path), hex.as_bytes())?;
+            println!("New master key written to {}", path);
+            return Ok(MasterKey { key });
+        }
+        Ok(MasterKey {
+            key: from_hex(&std::fs::read_to_string(&path)?)?,
+        })
+    }
+
+    fn cipher(&self) -> LessSafeKey {
+        LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &self.key).expect("AES-256 key"))
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| "Failed to generate a nonce")?;
+
+        let mut in_out = plaintext.to_vec();
+        self.cipher()
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(aad),
+                &mut in_out,
+            )
+            .map_err(|_| "Encryption failed")?;
+        Ok([nonce.as_slice(), &in_out].concat())
+    }
+
+    pub fn decrypt(&self, ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        if ciphertext.len() < NONCE_LEN {
+            return Err("The encrypted file is truncated".into());
+        }
+        let (nonce, sealed) = ciphertext.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| "Invalid nonce")?;
+        let mut in_out = sealed.to_vec();
+        let plaintext = self
+            .cipher()
+            .open_in_place(nonce, Aad::from(aad), &mut in_out)
+            .map_err(|_| "Decryption failed, the file was modified or the key is wrong")?;
+        Ok(plaintext.to_vec())
+    }
+
+    pub fn write(&self, path: &Path, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, self.encrypt(contents, &aad(path))?)?;
+        Ok(())
+    }
+
+    pub fn read(&self, path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.decrypt(&std::fs::read(path)?, &aad(path))
+    }
+}
+
+fn encrypted_schema_path(path: &str) -> PathBuf {
+    let stem = path.strip_suffix(".csv").unwrap_or(path);
+    PathBuf::from(format!("{}.schema.json", stem))
+}
+
+// Write the cells to a CSV file and the schema next to it, both encrypted with
+// the master key of the server
+pub fn write_encrypted_csv(
+    dataset: &Dataset,
+    path: &str,
+    key: &MasterKey,
+) -> Result<(), Box<dyn Error>> {
+    key.write(Path::new(path), &csv_file::csv_bytes(dataset)?)?;
+    let schema = serde_json::to_vec_pretty(&dataset.schema)?;
+    key.write(&encrypted_schema_path(path), &schema)?;
+    Ok(())
+}
+
+// Read a CSV file written by write_encrypted_csv
+pub fn read_encrypted_csv(path: &str, key: &MasterKey) -> Result<Dataset, Box<dyn Error>> {
+    let schema = serde_json::from_slice(&key.read(&encrypted_schema_path(path))?)?;
+    csv_file::parse_csv(&key.read(Path::new(path))?, |_, _| Ok(schema))
+}
+
+// Write the coefficients of a model, encrypted with the master key of the server
+pub fn write_encrypted_array2(
+    array: &Array2<f64>,
+    filename: &str,
+    key: &MasterKey,
+) -> Result<(), Box<dyn Error>> {
+    let content: String = array
+        .rows()
+        .into_iter()
+        .map(|row| {
+            let values: Vec<String> = row.iter().map(|value| value.to_string()).collect();
+            format!("{}\n", values.join(" "))
+        })
+        .collect();
+    key.write(Path::new(filename), content.as_bytes())
+}
+
+// Read coefficients written by write_encrypted_array2
+pub fn read_encrypted_array2(
+    filename: &str,
+    key: &MasterKey,
+) -> Result<Array2<f64>, Box<dyn Error>> {
+    csv_file::parse_array2(&String::from_utf8(key.read(Path::new(filename))?)?)
+}
+
+// Write a file only the owner of the server process can read
+fn write_private(path: &Path, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+    use std::io::Write;
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(contents)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::object_id;
+
+    fn key() -> MasterKey {
+        MasterKey { key: [7u8; 32] }
+    }
+
+    // Fresh directory under the temporary directory, removed by the test
+    fn scratch() -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("crypi-at-rest-{}", object_id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn round_trip() {
+        let key = key();
+        let ciphertext = key.encrypt(b"age,chd\n52,0\n", b"train.csv").unwrap();
+        assert_eq!(ciphertext.len(), NONCE_LEN + 13 + 16);
+        assert_eq!(
+            key.decrypt(&ciphertext, b"train.csv").unwrap(),
+            b"age,chd\n52,0\n"
+        );
+        // Fresh nonce for every encryption
+        assert_ne!(
+            key.encrypt(b"age,chd\n52,0\n", b"train.csv").unwrap(),
+            ciphertext
+        );
+    }
+
+    #[test]
+    fn tampering_is_rejected() {
+        let key = key();
+        let ciphertext = key.encrypt(b"age,chd\n52,0\n", b"train.csv").unwrap();
+        // Nonce, ciphertext and tag
+        for i in [0, NONCE_LEN, ciphertext.len() - 1] {
+            let mut tampered = ciphertext.clone();
+            tampered[i] ^= 1;
+            assert!(key.decrypt(&tampered, b"train.csv").is_err());
+        }
+        assert!(key
+            .decrypt(&ciphertext[..ciphertext.len() - 1], b"train.csv")
+            .is_err());
+        assert!(key
+            .decrypt(&ciphertext[..NONCE_LEN - 1], b"train.csv")
+            .is_err());
+        // Another file name or key
+        assert!(key.decrypt(&ciphertext, b"test.csv").is_err());
+        let other = MasterKey { key: [8u8; 32] };
+        assert!(other.decrypt(&ciphertext, b"train.csv").is_err());
+    }
+
+    #[test]
+    fn swapped_files_are_rejected() {
+        let directory = scratch();
+        let key = key();
+        let (train, test) = (directory.join("train.csv"), directory.join("test.csv"));
+        key.write(&train, b"1,0\n").unwrap();
+        key.write(&test, b"2,1\n").unwrap();
+        assert_eq!(key.read(&train).unwrap(), b"1,0\n");
+        std::fs::rename(&train, &test).unwrap();
+        assert!(key.read(&test).is_err());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn encrypted_csv_and_coefficients() {
+        let directory = scratch();
+        let key = key();
+        let path = directory.join("train.csv").to_string_lossy().to_string();
+        let dataset =
+            csv_file::parse_csv(b"age,smoking,chd\n52,never,0\n61,current,1\n", |h, r| {
+                Ok(crate::schema::Schema::infer(h, r, true))
+            })
+            .unwrap();
+        write_encrypted_csv(&dataset, &path, &key).unwrap();
+        assert!(!std::fs::read(&path).unwrap().starts_with(b"age"));
+        let read = read_encrypted_csv(&path, &key).unwrap();
+        assert_eq!(read.header, dataset.header);
+        assert_eq!(read.rows, dataset.rows);
+        assert_eq!(read.schema, dataset.schema);
+
+        let path = directory.join("model.txt").to_string_lossy().to_string();
+        let coefficients = ndarray::array![[0.5, -1.25], [2.0, 0.0]];
+        write_encrypted_array2(&coefficients, &path, &key).unwrap();
+        assert_eq!(read_encrypted_array2(&path, &key).unwrap(), coefficients);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn hex_keys() {
+        let hex = "00ff".repeat(16);
+        assert_eq!(from_hex(&hex).unwrap()[..2], [0x00, 0xff]);
+        assert!(from_hex("00ff").is_err());
+        assert!(from_hex(&"zz".repeat(32)).is_err());
+    }
+}
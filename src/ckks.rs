@@ -0,0 +1,640 @@
+// Approximate homomorphic encryption on reals (CKKS) over R_Q = Z_Q[X]/(X^N + 1).
+//
+// A vector of up to N/2 reals is packed in the slots of one plaintext polynomial
+// through the inverse canonical embedding, scaled and rounded. The client
+// encrypts with its secret key s as (c0, c1) = (m + e - a * s, a) and decrypts
+// with c0 + c1 * s = m + e. Adding ciphertexts and multiplying them by integer
+// constants acts slot by slot, which is enough to compute X . theta for a whole
+// block of rows when every feature column is packed in one ciphertext.
+//
+// Q is the product of two 64-bit primes which have 2N-th roots of unity for the
+// negacyclic NTT, every coefficient being kept as its residues modulo both of
+// them. With N = 8192 and a 128-bit Q, the ring gives about 128 bits of security.
+//
+// On its own, X . theta would give the model away: its c1 is a combination of
+// the client's c1 with the coefficients, and the error of its decryption the same
+// combination of the client's errors. The server adds a fresh encryption of zero
+// under the client's public key, which makes c1 uniform, and floods c0 with a
+// noise 2^STATISTICAL_BITS times larger than that combination of the errors. A
+// client following the protocol then learns the logits of its rows and nothing
+// else: the remaining leakage is that of the predictions themselves, every row
+// being a query of the model.
+
+use crate::mpc::random_u64s;
+use num_complex::Complex64;
+use std::f64::consts::PI;
+
+pub const DEGREE: usize = 8192;
+pub const SLOTS: usize = DEGREE / 2;
+// Primes of the residues, both 1 mod 2N. Polynomials hold the DEGREE residues
+// modulo the first prime, then the DEGREE residues modulo the second one.
+pub const MODULI: [u64; 2] = [0xffff_ffff_0000_0001, 0xffff_ffff_fffe_4001];
+// Scales of the features and of the coefficients, the logits having both
+pub const FEATURE_SCALE_BITS: u32 = 70;
+pub const WEIGHT_SCALE_BITS: u32 = 20;
+pub const LOGIT_SCALE_BITS: u32 = FEATURE_SCALE_BITS + WEIGHT_SCALE_BITS;
+// Largest feature the client encrypts, and largest sum of the absolute values of
+// the coefficients the server evaluates: the logits, about 2^122 once scaled,
+// and the flooding noise then stay below Q / 2
+pub const MAX_FEATURE: f64 = 65536.0;
+pub const MAX_WEIGHT_SUM: f64 = 65536.0;
+
+// Quadratic non-residue modulo both primes, whose powers give the roots of unity
+const NON_RESIDUE: u64 = 7;
+// The errors follow a centered binomial distribution of standard deviation 3.24,
+// bounded by ERROR_ETA
+const ERROR_ETA: u32 = 21;
+// Statistical distance of 2^-STATISTICAL_BITS between the flooded results of two
+// models with the same logits
+const STATISTICAL_BITS: u32 = 40;
+
+#[derive(Debug, Clone)]
+pub struct Ciphertext {
+    pub c0: Vec<u64>,
+    pub c1: Vec<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SecretKey {
+    s: Vec<u64>,
+}
+
+// Encryption of zero under s: b + a * s = e
+#[derive(Debug, Clone)]
+pub struct PublicKey {
+    pub b: Vec<u64>,
+    pub a: Vec<u64>,
+}
+
+fn add_mod(a: u64, b: u64, q: u64) -> u64 {
+    let (sum, carry) = a.overflowing_add(b);
+    if carry || sum >= q {
+        sum.wrapping_sub(q)
+    } else {
+        sum
+    }
+}
+
+fn sub_mod(a: u64, b: u64, q: u64) -> u64 {
+    if a >= b {
+        a - b
+    } else {
+        a.wrapping_sub(b).wrapping_add(q)
+    }
+}
+
+fn mul_mod(a: u64, b: u64, q: u64) -> u64 {
+    ((a as u128 * b as u128) % q as u128) as u64
+}
+
+fn pow_mod(mut base: u64, mut exponent: u64, q: u64) -> u64 {
+    let mut result = 1;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = mul_mod(result, base, q);
+        }
+        base = mul_mod(base, base, q);
+        exponent >>= 1;
+    }
+    result
+}
+
+// Polynomial of the given signed coefficients
+fn from_signed(coefficients: &[i128]) -> Vec<u64> {
+    MODULI
+        .iter()
+        .flat_map(|&q| {
+            coefficients
+                .iter()
+                .map(move |c| c.rem_euclid(q as i128) as u64)
+        })
+        .collect()
+}
+
+// Representatives in (-Q/2, Q/2] of the coefficients of a polynomial, by the CRT
+fn to_signed(poly: &[u64]) -> Vec<i128> {
+    let [q0, q1] = MODULI;
+    let q0_inv = pow_mod(q0 % q1, q1 - 2, q1);
+    let modulus = q0 as u128 * q1 as u128;
+    let (r0, r1) = poly.split_at(DEGREE);
+    r0.iter()
+        .zip(r1.iter())
+        .map(|(&r0, &r1)| {
+            let t = mul_mod(sub_mod(r1, r0 % q1, q1), q0_inv, q1);
+            let value = r0 as u128 + q0 as u128 * t as u128;
+            if value > modulus / 2 {
+                -((modulus - value) as i128)
+            } else {
+                value as i128
+            }
+        })
+        .collect()
+}
+
+// Coefficient-wise operation on two polynomials
+fn zip_with(a: &[u64], b: &[u64], op: fn(u64, u64, u64) -> u64) -> Vec<u64> {
+    a.chunks_exact(DEGREE)
+        .zip(b.chunks_exact(DEGREE))
+        .zip(MODULI.iter())
+        .flat_map(|((a, b), &q)| a.iter().zip(b.iter()).map(move |(x, y)| op(*x, *y, q)))
+        .collect()
+}
+
+// In-place cyclic NTT of size DEGREE modulo q with the root of unity omega
+fn ntt(values: &mut [u64], omega: u64, q: u64) {
+    let n = values.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            values.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let step = pow_mod(omega, (n / len) as u64, q);
+        for block in values.chunks_exact_mut(len) {
+            let mut w = 1;
+            let (low, high) = block.split_at_mut(len / 2);
+            for (u, v) in low.iter_mut().zip(high.iter_mut()) {
+                let t = mul_mod(*v, w, q);
+                *v = sub_mod(*u, t, q);
+                *u = add_mod(*u, t, q);
+                w = mul_mod(w, step, q);
+            }
+        }
+        len <<= 1;
+    }
+}
+
+// Product in R_q for a single prime: the inputs are twisted by the powers of a
+// primitive 2N-th root psi so that the cyclic convolution computes the reduction
+// modulo X^N + 1
+fn poly_mul_mod(a: &[u64], b: &[u64], q: u64) -> Vec<u64> {
+    let n = DEGREE as u64;
+    let psi = pow_mod(NON_RESIDUE, (q - 1) / (2 * n), q);
+    let psi_inv = pow_mod(psi, q - 2, q);
+    let omega = mul_mod(psi, psi, q);
+    let omega_inv = mul_mod(psi_inv, psi_inv, q);
+    let n_inv = pow_mod(n, q - 2, q);
+
+    let twist = |poly: &[u64]| {
+        let mut power = 1;
+        poly.iter()
+            .map(|&c| {
+                let twisted = mul_mod(c, power, q);
+                power = mul_mod(power, psi, q);
+                twisted
+            })
+            .collect::<Vec<u64>>()
+    };
+    let mut fa = twist(a);
+    let mut fb = twist(b);
+    ntt(&mut fa, omega, q);
+    ntt(&mut fb, omega, q);
+    for (x, y) in fa.iter_mut().zip(fb.iter()) {
+        *x = mul_mod(*x, *y, q);
+    }
+    ntt(&mut fa, omega_inv, q);
+
+    let mut power = n_inv;
+    fa.iter()
+        .map(|&c| {
+            let untwisted = mul_mod(c, power, q);
+            power = mul_mod(power, psi_inv, q);
+            untwisted
+        })
+        .collect()
+}
+
+// Product in R_Q, prime by prime
+fn poly_mul(a: &[u64], b: &[u64]) -> Vec<u64> {
+    a.chunks_exact(DEGREE)
+        .zip(b.chunks_exact(DEGREE))
+        .zip(MODULI.iter())
+        .flat_map(|((a, b), &q)| poly_mul_mod(a, b, q))
+        .collect()
+}
+
+// Powers of the primitive 2N-th complex root of unity and rotation group 5^j mod 2N
+fn embedding_tables() -> (Vec<Complex64>, Vec<usize>) {
+    let m = 2 * DEGREE;
+    let roots = (0..=m)
+        .map(|k| Complex64::from_polar(1.0, 2.0 * PI * k as f64 / m as f64))
+        .collect();
+    let mut rotations = Vec::with_capacity(SLOTS);
+    let mut power = 1;
+    for _ in 0..SLOTS {
+        rotations.push(power);
+        power = power * 5 % m;
+    }
+    (roots, rotations)
+}
+
+fn bit_reverse(values: &mut [Complex64]) {
+    let n = values.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            values.swap(i, j);
+        }
+    }
+}
+
+// Evaluate a plaintext at the roots zeta^(5^j) of X^N + 1, from its coefficients
+// packed as values[i] = m[i] + i * m[i + N/2]
+fn embed(values: &mut [Complex64]) {
+    let (roots, rotations) = embedding_tables();
+    let m = 2 * DEGREE;
+    bit_reverse(values);
+    let mut len = 2;
+    while len <= SLOTS {
+        let half = len / 2;
+        let quarter = len * 4;
+        let gap = m / quarter;
+        for block in values.chunks_exact_mut(len) {
+            for j in 0..half {
+                let u = block[j];
+                let v = block[j + half] * roots[(rotations[j] % quarter) * gap];
+                block[j] = u + v;
+                block[j + half] = u - v;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+// Inverse of embed
+fn embed_inverse(values: &mut [Complex64]) {
+    let (roots, rotations) = embedding_tables();
+    let m = 2 * DEGREE;
+    let mut len = SLOTS;
+    while len >= 2 {
+        let half = len / 2;
+        let quarter = len * 4;
+        let gap = m / quarter;
+        for block in values.chunks_exact_mut(len) {
+            for j in 0..half {
+                let u = block[j] + block[j + half];
+                let v =
+                    (block[j] - block[j + half]) * roots[(quarter - rotations[j] % quarter) * gap];
+                block[j] = u;
+                block[j + half] = v;
+            }
+        }
+        len >>= 1;
+    }
+    bit_reverse(values);
+    for value in values.iter_mut() {
+        *value /= SLOTS as f64;
+    }
+}
+
+// Plaintext polynomial whose slots hold the values scaled by 2^FEATURE_SCALE_BITS
+pub fn encode(values: &[f64]) -> Vec<u64> {
+    assert!(values.len() <= SLOTS, "Too many values for one plaintext");
+    assert!(
+        values.iter().all(|v| v.abs() <= MAX_FEATURE),
+        "Value too large for a plaintext"
+    );
+    let mut slots = vec![Complex64::new(0.0, 0.0); SLOTS];
+    for (slot, value) in slots.iter_mut().zip(values.iter()) {
+        *slot = Complex64::new(*value, 0.0);
+    }
+    embed_inverse(&mut slots);
+
+    let scale = 2f64.powi(FEATURE_SCALE_BITS as i32);
+    let mut coefficients = vec![0i128; DEGREE];
+    for (i, slot) in slots.iter().enumerate() {
+        coefficients[i] = (slot.re * scale).round() as i128;
+        coefficients[i + SLOTS] = (slot.im * scale).round() as i128;
+    }
+    from_signed(&coefficients)
+}
+
+// Real parts of the slots of a plaintext polynomial with `scale_bits` of scale
+pub fn decode(poly: &[u64], scale_bits: u32) -> Vec<f64> {
+    let scale = 2f64.powi(scale_bits as i32);
+    let coefficients = to_signed(poly);
+    let mut slots: Vec<Complex64> = (0..SLOTS)
+        .map(|i| {
+            Complex64::new(
+                coefficients[i] as f64 / scale,
+                coefficients[i + SLOTS] as f64 / scale,
+            )
+        })
+        .collect();
+    embed(&mut slots);
+    slots.iter().map(|slot| slot.re).collect()
+}
+
+fn uniform_poly() -> Vec<u64> {
+    let mut poly = Vec::with_capacity(MODULI.len() * DEGREE);
+    for &q in MODULI.iter() {
+        let end = poly.len() + DEGREE;
+        while poly.len() < end {
+            let missing = end - poly.len();
+            poly.extend(random_u64s(missing).into_iter().filter(|&c| c < q));
+        }
+    }
+    poly
+}
+
+fn error_poly() -> Vec<u64> {
+    let mask = (1u64 << ERROR_ETA) - 1;
+    let coefficients: Vec<i128> = random_u64s(DEGREE)
+        .iter()
+        .map(|w| {
+            let positive = (w & mask).count_ones() as i128;
+            let negative = ((w >> ERROR_ETA) & mask).count_ones() as i128;
+            positive - negative
+        })
+        .collect();
+    from_signed(&coefficients)
+}
+
+// Coefficients uniform in {-1, 0, 1}
+fn ternary_poly() -> Vec<u64> {
+    let coefficients: Vec<i128> = random_u64s(DEGREE)
+        .iter()
+        .map(|w| (w % 3) as i128 - 1)
+        .collect();
+    from_signed(&coefficients)
+}
+
+// Coefficients uniform in [-bound, bound]
+fn flooding_poly(bound: u128) -> Vec<u64> {
+    let words = random_u64s(2 * DEGREE);
+    let coefficients: Vec<i128> = words
+        .chunks_exact(2)
+        .map(|w| {
+            let value = (w[0] as u128) << 64 | w[1] as u128;
+            (value % (2 * bound + 1)) as i128 - bound as i128
+        })
+        .collect();
+    from_signed(&coefficients)
+}
+
+fn is_valid_poly(poly: &[u64]) -> bool {
+    poly.len() == MODULI.len() * DEGREE
+        && poly
+            .chunks_exact(DEGREE)
+            .zip(MODULI.iter())
+            .all(|(residues, &q)| residues.iter().all(|&r| r < q))
+}
+
+impl SecretKey {
+    pub fn generate() -> Self {
+        SecretKey { s: ternary_poly() }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        let a = uniform_poly();
+        let e = error_poly();
+        let b = zip_with(&e, &poly_mul(&a, &self.s), sub_mod);
+        PublicKey { b, a }
+    }
+
+    pub fn encrypt(&self, values: &[f64]) -> Ciphertext {
+        let m = encode(values);
+        let a = uniform_poly();
+        let e = error_poly();
+        let c0 = zip_with(&zip_with(&m, &e, add_mod), &poly_mul(&a, &self.s), sub_mod);
+        Ciphertext { c0, c1: a }
+    }
+
+    // Plaintext polynomial of a ciphertext, with its error
+    fn decrypt_poly(&self, ciphertext: &Ciphertext) -> Vec<u64> {
+        zip_with(&ciphertext.c0, &poly_mul(&ciphertext.c1, &self.s), add_mod)
+    }
+
+    // The first `count` slots of a ciphertext with `scale_bits` of scale
+    pub fn decrypt(&self, ciphertext: &Ciphertext, scale_bits: u32, count: usize) -> Vec<f64> {
+        let mut values = decode(&self.decrypt_poly(ciphertext), scale_bits);
+        values.truncate(count);
+        values
+    }
+}
+
+impl PublicKey {
+    pub fn is_valid(&self) -> bool {
+        is_valid_poly(&self.b) && is_valid_poly(&self.a)
+    }
+
+    // Fresh encryption of zero: (v * b + e0, v * a + e1) decrypts to
+    // v * e + e0 + e1 * s
+    fn encrypt_zero(&self) -> Ciphertext {
+        let v = ternary_poly();
+        Ciphertext {
+            c0: zip_with(&poly_mul(&v, &self.b), &error_poly(), add_mod),
+            c1: zip_with(&poly_mul(&v, &self.a), &error_poly(), add_mod),
+        }
+    }
+}
+
+impl Ciphertext {
+    // Both polynomials hold the residues of DEGREE coefficients modulo every prime
+    pub fn is_valid(&self) -> bool {
+        is_valid_poly(&self.c0) && is_valid_poly(&self.c1)
+    }
+
+    fn zero() -> Self {
+        Ciphertext {
+            c0: vec![0; MODULI.len() * DEGREE],
+            c1: vec![0; MODULI.len() * DEGREE],
+        }
+    }
+
+    fn add(&mut self, other: &Ciphertext) {
+        self.c0 = zip_with(&self.c0, &other.c0, add_mod);
+        self.c1 = zip_with(&self.c1, &other.c1, add_mod);
+    }
+
+    // self += k * other, slot by slot
+    fn add_scaled(&mut self, other: &Ciphertext, k: i64) {
+        let k = from_signed(&[k as i128]);
+        for (poly, other) in [(&mut self.c0, &other.c0), (&mut self.c1, &other.c1)] {
+            for ((x, y), (&k, &q)) in poly
+                .chunks_exact_mut(DEGREE)
+                .zip(other.chunks_exact(DEGREE))
+                .zip(k.iter().zip(MODULI.iter()))
+            {
+                for (x, y) in x.iter_mut().zip(y.iter()) {
+                    *x = add_mod(*x, mul_mod(*y, k, q), q);
+                }
+            }
+        }
+    }
+}
+
+// Whether the coefficients are small enough for dot
+pub fn fits(theta: &[f64]) -> bool {
+    theta.iter().all(|t| t.is_finite())
+        && theta.iter().map(|t| t.abs()).sum::<f64>() <= MAX_WEIGHT_SUM
+}
+
+// Encrypted logits of a block of rows from its encrypted feature columns, with
+// theta[0] as the intercept when theta has one more coefficient than there are
+// columns. The result has a scale of 2^LOGIT_SCALE_BITS, and is rerandomised and
+// flooded under the client's public key so that it only reveals the logits.
+pub fn dot(columns: &[Ciphertext], theta: &[f64], key: &PublicKey) -> Ciphertext {
+    let (intercept, weights) = if theta.len() == columns.len() + 1 {
+        (theta[0], &theta[1..])
+    } else {
+        (0.0, theta)
+    };
+
+    let weight_scale = 2f64.powi(WEIGHT_SCALE_BITS as i32);
+    let weights: Vec<i64> = weights
+        .iter()
+        .map(|w| (w * weight_scale).round() as i64)
+        .collect();
+    let mut total = Ciphertext::zero();
+    for (column, weight) in columns.iter().zip(weights.iter()) {
+        total.add_scaled(column, *weight);
+    }
+
+    // A constant polynomial holds the same value in every slot
+    let intercept = (intercept * 2f64.powi(LOGIT_SCALE_BITS as i32)).round() as i128;
+    for ((residues, constant), &q) in total
+        .c0
+        .chunks_exact_mut(DEGREE)
+        .zip(from_signed(&[intercept]))
+        .zip(MODULI.iter())
+    {
+        residues[0] = add_mod(residues[0], constant, q);
+    }
+
+    // The error of the result is the combination of the client's errors with the
+    // weights, bounded by ERROR_ETA times the sum of their absolute values
+    total.add(&key.encrypt_zero());
+    let error_bound = weights
+        .iter()
+        .map(|w| w.unsigned_abs() as u128)
+        .sum::<u128>()
+        * ERROR_ETA as u128;
+    let noise = flooding_poly(error_bound << STATISTICAL_BITS);
+    total.c0 = zip_with(&total.c0, &noise, add_mod);
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(rows: usize, seed: f64) -> Vec<f64> {
+        (0..rows).map(|i| (i as f64 * seed).sin() * 10.0).collect()
+    }
+
+    #[test]
+    fn signed_coefficients_round_trip() {
+        let mut coefficients = vec![0i128; DEGREE];
+        coefficients[..5].copy_from_slice(&[1, -1, 1 << 100, -(1 << 120), 123_456_789]);
+        assert_eq!(to_signed(&from_signed(&coefficients)), coefficients);
+    }
+
+    #[test]
+    fn negacyclic_product() {
+        // X^(N-1) * X = X^N = -1
+        let mut a = vec![0i128; DEGREE];
+        let mut b = vec![0i128; DEGREE];
+        a[DEGREE - 1] = 1;
+        b[1] = 1;
+        let product = to_signed(&poly_mul(&from_signed(&a), &from_signed(&b)));
+        let mut expected = vec![0i128; DEGREE];
+        expected[0] = -1;
+        assert_eq!(product, expected);
+    }
+
+    #[test]
+    fn encoding_round_trip() {
+        let values = column(SLOTS, 0.37);
+        let decoded = decode(&encode(&values), FEATURE_SCALE_BITS);
+        for (x, y) in values.iter().zip(decoded.iter()) {
+            assert!((x - y).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn encryption_round_trip() {
+        let key = SecretKey::generate();
+        let values = column(100, 0.11);
+        let ciphertext = key.encrypt(&values);
+        assert!(ciphertext.is_valid());
+        let decrypted = key.decrypt(&ciphertext, FEATURE_SCALE_BITS, values.len());
+        for (x, y) in values.iter().zip(decrypted.iter()) {
+            assert!((x - y).abs() < 1e-9);
+        }
+
+        let public = key.public_key();
+        assert!(public.is_valid());
+        let zero = public.encrypt_zero();
+        for value in key.decrypt(&zero, FEATURE_SCALE_BITS, SLOTS) {
+            assert!(value.abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn encrypted_logits() {
+        let key = SecretKey::generate();
+        let rows = 50;
+        let X: Vec<Vec<f64>> = (0..3).map(|j| column(rows, 0.3 + j as f64)).collect();
+        let columns: Vec<Ciphertext> = X.iter().map(|c| key.encrypt(c)).collect();
+        let theta = [0.5, -1.25, 2.0, 0.75];
+
+        let logits = dot(&columns, &theta, &key.public_key());
+        let decrypted = key.decrypt(&logits, LOGIT_SCALE_BITS, rows);
+        for (i, logit) in decrypted.iter().enumerate() {
+            let expected = theta[0] + (0..3).map(|j| theta[j + 1] * X[j][i]).sum::<f64>();
+            assert!((logit - expected).abs() < 1e-4, "{} != {}", logit, expected);
+        }
+    }
+
+    #[test]
+    fn logits_are_rerandomised_and_flooded() {
+        let key = SecretKey::generate();
+        let columns: Vec<Ciphertext> = (0..2).map(|j| key.encrypt(&column(10, j as f64))).collect();
+        let theta = [3.0, -2.0];
+        let logits = dot(&columns, &theta, &key.public_key());
+
+        // The combination the client could invert to recover theta
+        let mut combination = Ciphertext::zero();
+        for (column, weight) in columns.iter().zip(theta.iter()) {
+            combination.add_scaled(
+                column,
+                (weight * 2f64.powi(WEIGHT_SCALE_BITS as i32)) as i64,
+            );
+        }
+        assert_ne!(logits.c1, combination.c1);
+
+        // The difference of the errors is the noise, much larger than the errors
+        let noise = to_signed(&zip_with(
+            &key.decrypt_poly(&logits),
+            &key.decrypt_poly(&combination),
+            sub_mod,
+        ));
+        let bound = (5 << WEIGHT_SCALE_BITS) * ERROR_ETA as i128;
+        let largest = noise.iter().map(|n| n.abs()).max().unwrap();
+        assert!(largest > bound << (STATISTICAL_BITS - 1));
+        assert!(largest <= (bound << STATISTICAL_BITS) + (1 << 20));
+    }
+
+    #[test]
+    fn coefficient_bounds() {
+        assert!(fits(&[1.0, -2.0, 3.0]));
+        assert!(!fits(&[MAX_WEIGHT_SUM, 1.0]));
+        assert!(!fits(&[f64::NAN]));
+    }
+}
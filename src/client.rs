@@ -1,238 +1,782 @@
-use file::file_client::FileClient;
-use file::FileFinished;
-use file::FileRequest;
-use file::FileTransfer;
-use file::RequestPrediction;
-use file::RequestTraining;
-
-use http::uri::Uri;
-
-use prost::encoding::bool;
-use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity};
-
-use ring::digest::{Context, SHA256};
-
-use hmac::{Hmac, Mac};
-use sha2::Sha256;
-
-mod csv_file;
-
-pub mod file {
-    tonic::include_proto!("file");
-}
-
-// Create alias for HMAC-SHA256
-type HmacSha256 = Hmac<Sha256>;
-
-// Function to upload file to server
-async fn upload_file(
-    client: &mut FileClient<Channel>,
-    file_path: &str,
-    train: u32,
-    hmac: &mut HmacSha256,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let filepath = std::path::Path::new(&file_path);
-    // Keep only the filename
-    let filename_ = filepath.file_name().unwrap().to_str().unwrap().to_string();
-    println!("Filename: {}", filename_);
-
-    let mut serialized_data: Vec<u8> = Vec::new();
-
-    // If the train variable is equal to 1, then the file is a training file
-    // and the server will save it in the training folder
-    if train == 1 || train == 2 {
-        if !filename_.ends_with(".csv") {
-            println!("Training file must be a .csv file!");
-            return Ok(());
-        }
-        // Read the file
-        let content = csv_file::read_csv_file(file_path.to_string())?;
-        // Serialize the records using bincode
-        serialized_data = bincode::serialize(&content)?;
-    }
-    if train == 3 {
-        if !filename_.ends_with(".txt") {
-            println!("Prediction file must be a .txt file!");
-            return Ok(());
-        }
-        // Read the file
-        let content = csv_file::read_file_to_array1(&file_path.to_string())?;
-        // Serialize the records using bincode
-        serialized_data = bincode::serialize(&content)?;
-    }
-
-    // Split the file into chunks of data to send
-    let chunks = serialized_data
-        .chunks(1024)
-        .map(|chunk| chunk.to_vec())
-        .collect::<Vec<_>>();
-
-    let request = tonic::Request::new(FileRequest {
-        filename: filename_.to_string(),
-        train: train == 1,
-        coefs: filename_.ends_with(".txt"),
-    });
-
-    let mut response = client.priming_send(request).await?;
-
-    match response.into_inner().message.as_str() {
-        "OK" => (),
-        _ => {
-            println!("Error during the priming of the transfer");
-            return Ok(());
-        }
-    }
-
-    println!("Uploading {} to the server...", filename_);
-
-    for chunk in chunks {
-        // Calculate the SHA-256 hash of the chunk
-        let mut context = Context::new(&SHA256);
-        context.update(&chunk);
-        let chunk_hash = context.finish();
-
-        let request = tonic::Request::new(FileTransfer {
-            filename: filename_.clone(),
-            content: chunk,
-            hash: chunk_hash.as_ref().to_vec(), // Include the hash in the FileTransfer message
-        });
-
-        response = client.send_file(request).await?;
-
-        match response.into_inner().message.as_str() {
-            "OK" => (),
-            _ => {
-                println!("File upload failed!");
-                return Ok(());
-            }
-        }
-    }
-
-    // Compute the HMAC hash of the entire serialized file
-    hmac.update(&serialized_data);
-    let hmac_hash = hmac.clone().finalize().into_bytes().to_vec();
-
-    let newrequest = tonic::Request::new(FileFinished {
-        filename: filename_.to_string(),
-        hmac_hash: hmac_hash,
-    });
-
-    println!("Finishing the transfer...");
-
-    client.finish_transfer(newrequest).await?;
-
-    println!("File uploaded successfully!");
-    Ok(())
-}
-
-async fn start_prediction(
-    client: &mut FileClient<Channel>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let request = tonic::Request::new(RequestPrediction { predict: true });
-
-    let response = client.launch_prediction(request).await?;
-
-    // Print the response
-    println!("RESPONSE={:?}", response);
-
-    Ok(())
-}
-
-async fn start_training(
-    client: &mut FileClient<Channel>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Launching training...");
-
-    let request = tonic::Request::new(RequestTraining { train: true });
-
-    let response = client.launch_training(request).await?;
-
-    // Print the response
-    println!("RESPONSE={:?}", response);
-
-    Ok(())
-}
-
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Get the args passed to the program
-    let args: Vec<String> = std::env::args().collect();
-
-    if args.len() != 3 {
-        println!("Usage: {} <host> <port>", args[0]);
-        return Ok(());
-    }
-
-    let host = &args[1];
-    let port = &args[2];
-
-    let server_root_ca_cert = std::fs::read_to_string("ca.crt")?;
-    let server_root_ca_cert = Certificate::from_pem(server_root_ca_cert);
-    let client_cert = std::fs::read_to_string("client.crt")?;
-    let client_key = std::fs::read_to_string("client.key")?;
-    let client_identity = Identity::from_pem(client_cert, client_key);
-
-    let tls = ClientTlsConfig::new()
-        .domain_name("localhost")
-        .ca_certificate(server_root_ca_cert)
-        .identity(client_identity);
-
-    let uri: Uri = format!("http://{}:{}", host, port).parse()?;
-    let channel = Channel::builder(uri).tls_config(tls)?.connect().await?;
-
-    let mut client = FileClient::new(channel);
-
-    let mut choice = String::new();
-    // Put the number 0 in choice to enter the loop
-    choice.push('0');
-
-    loop {
-        // Ask the user what does he want to do with the server
-        println!("What do you want to do?");
-        println!("1. Upload a file for training");
-        println!("2. Upload a file for prediction");
-        println!("3. Upload a trained model");
-        println!("4. Launch the training");
-        println!("5. Launch the prediction");
-        println!("6. Exit");
-
-        std::io::stdin().read_line(&mut choice)?;
-
-        match choice.trim().parse::<u32>() {
-            Ok(1 | 2 | 3) => {
-                let mut hmac =
-                    HmacSha256::new_from_slice(b"secret").expect("HMAC can take key of any size");
-                // Ask the user for the name of the file to upload
-                println!("Enter the path of the file to upload:");
-                let mut filepath = String::new();
-                std::io::stdin().read_line(&mut filepath)?;
-                // Upload a file for training
-                upload_file(
-                    &mut client,
-                    filepath.trim(),
-                    choice.trim().parse::<u32>().unwrap(),
-                    &mut hmac,
-                )
-                .await?;
-            }
-            Ok(4) => {
-                // Launch the training
-                start_training(&mut client).await?;
-            }
-            Ok(5) => {
-                // Launch the prediction
-                start_prediction(&mut client).await?;
-            }
-            Ok(6) => {
-                // Exit the program
-                println!("Exiting...");
-                return Ok(());
-            }
-            _ => {
-                println!("Invalid choice!");
-            }
-        }
-        // clear the choice variable
-        choice.clear();
-    }
-}
+use clap::{Parser, Subcommand, ValueEnum};
+use crypi::{
+    ClassWeights, ClientConfig, ClientError, ConfusionMatrix, CrossValidation, CrypiClient,
+    Hyperparameters, Metrics, Multiclass, Optimizer, Prediction, Protocol, Resampling,
+    ResamplingMethod, Scaling, SplitOptions, ThresholdMetric, UploadKind,
+};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// Client of the server. Without a command, the client shows an interactive menu.
+#[derive(Parser)]
+#[command(name = "client")]
+struct Args {
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+    #[arg(long, default_value_t = 50051)]
+    port: u16,
+    /// Trusted dealer providing the triples of the secure computations, as host:port
+    #[arg(long)]
+    dealer: Option<String>,
+    /// Certificate of the CA of the server
+    #[arg(long, default_value = "ca.crt")]
+    ca: PathBuf,
+    #[arg(long, default_value = "client.crt")]
+    cert: PathBuf,
+    #[arg(long, default_value = "client.key")]
+    key: PathBuf,
+    /// File keeping the session between runs, so that the uploads of a command are
+    /// seen by the next ones. Created if missing, removed by the close command.
+    #[arg(long)]
+    session: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Upload a training dataset (.csv)
+    UploadTrain { file: String },
+    /// Upload a dataset to predict on (.csv)
+    UploadPredict {
+        file: String,
+        /// The last column of the file is its target, which is not a feature
+        #[arg(long)]
+        labeled: bool,
+    },
+    /// Upload the coefficients of a model (.txt)
+    UploadModel { file: String },
+    /// Train a model on the uploaded training dataset
+    Train {
+        #[arg(long, value_enum, default_value_t = ScalingArg::ZScore)]
+        scaling: ScalingArg,
+        #[command(flatten)]
+        hyperparameters: HyperparameterArgs,
+        #[command(flatten)]
+        split: SplitArgs,
+    },
+    /// Evaluate the training by k-fold cross-validation, without storing a model
+    CrossValidate {
+        #[arg(long, default_value_t = 5)]
+        folds: usize,
+        #[arg(long, value_enum, default_value_t = ScalingArg::ZScore)]
+        scaling: ScalingArg,
+        #[command(flatten)]
+        hyperparameters: HyperparameterArgs,
+        #[command(flatten)]
+        split: SplitArgs,
+    },
+    /// Predict on the uploaded dataset, or privately on a local file
+    Predict {
+        /// ID of the stored model, the uploaded coefficients otherwise
+        #[arg(long)]
+        model: Option<String>,
+        /// Local file to predict on without sending it to the server
+        #[arg(long)]
+        local: Option<String>,
+        /// The last column of the local file is its target, which is not a feature
+        #[arg(long)]
+        labeled: bool,
+        /// Protocol of the prediction on a local file
+        #[arg(long, value_enum, default_value_t = ProtocolArg::SecretSharing)]
+        protocol: ProtocolArg,
+        /// CSV file to write the predictions to, instead of printing them
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Close the session kept in the session file
+    Close,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ScalingArg {
+    ZScore,
+    MinMax,
+}
+
+impl From<ScalingArg> for Scaling {
+    fn from(scaling: ScalingArg) -> Self {
+        match scaling {
+            ScalingArg::ZScore => Scaling::ZScore,
+            ScalingArg::MinMax => Scaling::MinMax,
+        }
+    }
+}
+
+#[derive(clap::Args)]
+struct HyperparameterArgs {
+    #[arg(long, default_value_t = 0.01)]
+    learning_rate: f64,
+    /// Passes over the training set
+    #[arg(long, default_value_t = 1000)]
+    epochs: usize,
+    /// Rows of every gradient step, 0 for the whole training set
+    #[arg(long, default_value_t = 0)]
+    batch_size: usize,
+    #[arg(long, value_enum, default_value_t = OptimizerArg::Sgd)]
+    optimizer: OptimizerArg,
+    /// Strength of the L1 penalty
+    #[arg(long, default_value_t = 0.0)]
+    l1: f64,
+    /// Strength of the L2 penalty, an elastic net with --l1
+    #[arg(long, default_value_t = 0.0)]
+    l2: f64,
+    /// Train a model without intercept
+    #[arg(long)]
+    no_intercept: bool,
+    /// Stop once an epoch improves the loss by less, 0 to run every epoch
+    #[arg(long, default_value_t = 0.0)]
+    tolerance: f64,
+    /// Seed of the order of the rows in the mini-batches
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Weights of the classes in the loss: uniform, balanced, or <negative>,<positive>
+    #[arg(long, default_value = "uniform", value_parser = parse_class_weights)]
+    class_weights: ClassWeights,
+    /// Resampling of the train set so that the classes have as many rows
+    #[arg(long, value_enum, default_value_t = ResampleArg::None)]
+    resample: ResampleArg,
+    /// Neighbors SMOTE interpolates towards
+    #[arg(long, default_value_t = 5)]
+    smote_neighbors: usize,
+    /// Seed of the resampling
+    #[arg(long, default_value_t = 0)]
+    resample_seed: u64,
+    /// Tune the decision threshold to maximize a metric on the train set, instead of 0.5
+    #[arg(long, value_enum)]
+    tune_threshold: Option<ThresholdArg>,
+    /// Model of the classes: binary, or multiclass on a target of class indices or
+    /// categories
+    #[arg(long, value_enum, default_value_t = MulticlassArg::Binary)]
+    multiclass: MulticlassArg,
+}
+
+fn parse_class_weights(value: &str) -> Result<ClassWeights, String> {
+    match value {
+        "uniform" => Ok(ClassWeights::Uniform),
+        "balanced" => Ok(ClassWeights::Balanced),
+        _ => {
+            let weights = value
+                .split_once(',')
+                .and_then(|(n, p)| Some((n.trim().parse().ok()?, p.trim().parse().ok()?)));
+            match weights {
+                Some((negative, positive)) => Ok(ClassWeights::Custom { negative, positive }),
+                None => Err("expected uniform, balanced or <negative>,<positive>".to_string()),
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ResampleArg {
+    None,
+    Oversample,
+    Undersample,
+    Smote,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum MulticlassArg {
+    Binary,
+    Softmax,
+    OneVsRest,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ThresholdArg {
+    F1,
+    Youden,
+    Accuracy,
+}
+
+impl From<HyperparameterArgs> for Hyperparameters {
+    fn from(args: HyperparameterArgs) -> Self {
+        Hyperparameters {
+            learning_rate: args.learning_rate,
+            epochs: args.epochs,
+            batch_size: args.batch_size,
+            optimizer: args.optimizer.into(),
+            l1: args.l1,
+            l2: args.l2,
+            intercept: !args.no_intercept,
+            tolerance: args.tolerance,
+            seed: args.seed,
+            class_weights: args.class_weights,
+            resampling: Resampling {
+                method: match args.resample {
+                    ResampleArg::None => ResamplingMethod::None,
+                    ResampleArg::Oversample => ResamplingMethod::Oversample,
+                    ResampleArg::Undersample => ResamplingMethod::Undersample,
+                    ResampleArg::Smote => ResamplingMethod::Smote,
+                },
+                neighbors: args.smote_neighbors,
+                seed: args.resample_seed,
+            },
+            threshold_metric: args.tune_threshold.map(|metric| match metric {
+                ThresholdArg::F1 => ThresholdMetric::F1,
+                ThresholdArg::Youden => ThresholdMetric::Youden,
+                ThresholdArg::Accuracy => ThresholdMetric::Accuracy,
+            }),
+            multiclass: match args.multiclass {
+                MulticlassArg::Binary => Multiclass::Binary,
+                MulticlassArg::Softmax => Multiclass::Softmax,
+                MulticlassArg::OneVsRest => Multiclass::OneVsRest,
+            },
+        }
+    }
+}
+
+#[derive(clap::Args)]
+struct SplitArgs {
+    /// Fraction of the rows held out to evaluate the model
+    #[arg(long, default_value_t = 0.2)]
+    test_fraction: f64,
+    /// Shuffle the rows before the split, instead of taking every n-th row
+    #[arg(long)]
+    shuffle: bool,
+    /// Keep the proportion of every class in the train and test sets
+    #[arg(long)]
+    stratify: bool,
+    /// Seed of the shuffle
+    #[arg(long, default_value_t = 0)]
+    split_seed: u64,
+}
+
+impl From<SplitArgs> for SplitOptions {
+    fn from(args: SplitArgs) -> Self {
+        SplitOptions {
+            test_fraction: args.test_fraction,
+            shuffle: args.shuffle,
+            stratify: args.stratify,
+            seed: args.split_seed,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OptimizerArg {
+    Sgd,
+    Momentum,
+    Adam,
+    Lbfgs,
+}
+
+impl From<OptimizerArg> for Optimizer {
+    fn from(optimizer: OptimizerArg) -> Self {
+        match optimizer {
+            OptimizerArg::Sgd => Optimizer::Sgd,
+            OptimizerArg::Momentum => Optimizer::Momentum,
+            OptimizerArg::Adam => Optimizer::Adam,
+            OptimizerArg::Lbfgs => Optimizer::Lbfgs,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ProtocolArg {
+    SecretSharing,
+    GarbledCircuit,
+    Paillier,
+    Ckks,
+}
+
+impl From<ProtocolArg> for Protocol {
+    fn from(protocol: ProtocolArg) -> Self {
+        match protocol {
+            ProtocolArg::SecretSharing => Protocol::SecretSharing,
+            ProtocolArg::GarbledCircuit => Protocol::GarbledCircuit,
+            ProtocolArg::Paillier => Protocol::Paillier,
+            ProtocolArg::Ckks => Protocol::Ckks,
+        }
+    }
+}
+
+// Session kept in a file between runs of the client, so that separate commands
+// share their uploads. The file holds the session key, only its owner can read it.
+#[derive(Serialize, Deserialize)]
+struct SavedSession {
+    session_id: String,
+    // Hex encoded
+    key: String,
+}
+
+impl SavedSession {
+    fn load(path: &Path) -> Result<(String, Vec<u8>), Box<dyn Error>> {
+        let saved: SavedSession = serde_json::from_slice(&std::fs::read(path)?)?;
+        if !saved.key.len().is_multiple_of(2) || !saved.key.is_ascii() {
+            return Err(format!("Invalid session key in {}", path.display()).into());
+        }
+        let key = (0..saved.key.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&saved.key[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()?;
+        Ok((saved.session_id, key))
+    }
+
+    fn save(path: &Path, client: &CrypiClient) -> Result<(), Box<dyn Error>> {
+        let saved = SavedSession {
+            session_id: client.session_id().to_string(),
+            key: client
+                .session_key()
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+        };
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options
+            .open(path)?
+            .write_all(&serde_json::to_vec(&saved)?)?;
+        Ok(())
+    }
+}
+
+// Connect to the server, resuming the saved session if it is still open on the
+// server
+async fn connect(args: &Args) -> Result<CrypiClient, Box<dyn Error>> {
+    let config = ClientConfig {
+        host: args.host.clone(),
+        port: args.port,
+        dealer: args.dealer.clone(),
+        ca: args.ca.clone(),
+        cert: args.cert.clone(),
+        key: args.key.clone(),
+    };
+
+    if let Some(path) = args.session.as_deref().filter(|path| path.exists()) {
+        let (session_id, key) = SavedSession::load(path)?;
+        match CrypiClient::resume(&config, &session_id, &key).await {
+            Ok(client) => return Ok(client),
+            Err(e) if e.is_session_expired() => {
+                println!("The saved session expired, opening a new one")
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(CrypiClient::connect(&config).await?)
+}
+
+// Keep the session for the next run if there is a session file, close it otherwise
+async fn finish(client: CrypiClient, session: Option<&Path>) -> Result<(), Box<dyn Error>> {
+    match session {
+        Some(path) => SavedSession::save(path, &client),
+        None => Ok(client.close().await?),
+    }
+}
+
+async fn upload(client: &mut CrypiClient, file: &str, kind: UploadKind) -> Result<(), ClientError> {
+    println!("Uploading {} to the server...", file);
+    let object_id = client.upload(file, kind).await?;
+    println!("File uploaded successfully, stored as {}", object_id);
+    Ok(())
+}
+
+fn print_cross_validation(cv: &CrossValidation) {
+    let area = |area: Option<f64>| match area {
+        Some(area) => format!("{:.4}", area),
+        None => "   -  ".to_string(),
+    };
+    println!("fold  rows  accuracy precision recall  F1     ROC-AUC PR-AUC log-loss Brier");
+    for (k, fold) in cv.folds.iter().enumerate() {
+        let m = &fold.metrics;
+        println!(
+            "{:>4} {:>5}  {:.4}   {:.4}    {:.4}  {:.4} {}  {} {:.4}   {:.4}",
+            k + 1,
+            fold.test_rows,
+            m.accuracy,
+            m.precision,
+            m.recall,
+            m.f1,
+            area(m.roc_auc),
+            area(m.pr_auc),
+            m.log_loss,
+            m.brier
+        );
+    }
+    for (name, m) in [("mean", &cv.mean), ("std", &cv.std)] {
+        println!(
+            "{:>4} {:>5}  {:.4}   {:.4}    {:.4}  {:.4} {}  {} {:.4}   {:.4}",
+            name,
+            "",
+            m.accuracy,
+            m.precision,
+            m.recall,
+            m.f1,
+            area(m.roc_auc),
+            area(m.pr_auc),
+            m.log_loss,
+            m.brier
+        );
+    }
+}
+
+fn print_metrics(metrics: &Metrics) {
+    let area = |area: Option<f64>| match area {
+        Some(area) => format!("{:.4}", area),
+        None => "undefined".to_string(),
+    };
+    println!("Metrics on the test split:");
+    println!("  accuracy  {:.4}", metrics.accuracy);
+    println!("  precision {:.4}", metrics.precision);
+    println!("  recall    {:.4}", metrics.recall);
+    println!("  F1        {:.4}", metrics.f1);
+    println!("  ROC-AUC   {}", area(metrics.roc_auc));
+    println!("  PR-AUC    {}", area(metrics.pr_auc));
+    println!("  log-loss  {:.4}", metrics.log_loss);
+    println!("  Brier     {:.4}", metrics.brier);
+    println!("  threshold {:.4}", metrics.threshold);
+    // Multiclass models have no confusion matrix
+    let c = metrics.confusion;
+    if c == ConfusionMatrix::default() {
+        return;
+    }
+    println!("Confusion matrix (rows: actual, columns: predicted):");
+    println!("            0      1");
+    println!("  0  {:>6} {:>6}", c.true_negatives, c.false_positives);
+    println!("  1  {:>6} {:>6}", c.false_negatives, c.true_positives);
+}
+
+async fn train(
+    client: &mut CrypiClient,
+    scaling: Scaling,
+    params: &Hyperparameters,
+    split: &SplitOptions,
+) -> Result<(), ClientError> {
+    println!("Launching training...");
+    let model = client.train(scaling, params, split).await?;
+    println!("Trained in {} epochs", model.epochs);
+    print_metrics(&model.metrics);
+    println!(
+        "The model is stored on the server with the ID {}",
+        model.model_id
+    );
+    Ok(())
+}
+
+// Predict on the uploaded dataset, or on a local file, labeled or not, with the
+// given protocol
+async fn predict(
+    client: &mut CrypiClient,
+    local_file: Option<(&str, bool)>,
+    protocol: Protocol,
+    model_id: &str,
+) -> Result<Vec<Prediction>, ClientError> {
+    let model_id = Some(model_id).filter(|id| !id.is_empty());
+    match local_file {
+        Some((file, labeled)) => {
+            println!("Running the private prediction...");
+            client
+                .predict_local(file, labeled, protocol, model_id)
+                .await
+        }
+        None => client.predict(model_id).await,
+    }
+}
+
+// The probabilities of every class are shown for multiclass models only
+fn print_predictions(predictions: &[Prediction]) {
+    for (row, prediction) in predictions.iter().enumerate() {
+        let mut line = format!(
+            "Row {}: label={} probability={:.4}",
+            row, prediction.label, prediction.probability
+        );
+        if prediction.class_probabilities.len() > 2 {
+            let classes: Vec<String> = prediction
+                .class_probabilities
+                .iter()
+                .map(|p| format!("{:.4}", p))
+                .collect();
+            line.push_str(&format!(" classes=[{}]", classes.join(", ")));
+        }
+        println!("{}", line);
+    }
+}
+
+// One column per class after the label and the probability
+fn write_predictions(path: &Path, predictions: &[Prediction]) -> Result<(), Box<dyn Error>> {
+    let classes = predictions
+        .first()
+        .map_or(0, |prediction| prediction.class_probabilities.len());
+    let mut writer = csv::Writer::from_path(path)?;
+    let mut header = vec![
+        "row".to_string(),
+        "label".to_string(),
+        "probability".to_string(),
+    ];
+    header.extend((0..classes).map(|k| format!("probability_{}", k)));
+    writer.write_record(&header)?;
+    for (row, prediction) in predictions.iter().enumerate() {
+        let mut record = vec![
+            row.to_string(),
+            prediction.label.to_string(),
+            prediction.probability.to_string(),
+        ];
+        record.extend(prediction.class_probabilities.iter().map(|p| p.to_string()));
+        writer.write_record(&record)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+async fn run_command(client: &mut CrypiClient, command: Command) -> Result<(), Box<dyn Error>> {
+    match command {
+        Command::UploadTrain { file } => upload(client, &file, UploadKind::Training).await?,
+        Command::UploadPredict { file, labeled } => {
+            let kind = match labeled {
+                true => UploadKind::LabeledPrediction,
+                false => UploadKind::Prediction,
+            };
+            upload(client, &file, kind).await?
+        }
+        Command::UploadModel { file } => upload(client, &file, UploadKind::Model).await?,
+        Command::Train {
+            scaling,
+            hyperparameters,
+            split,
+        } => {
+            train(
+                client,
+                scaling.into(),
+                &hyperparameters.into(),
+                &split.into(),
+            )
+            .await?
+        }
+        Command::CrossValidate {
+            folds,
+            scaling,
+            hyperparameters,
+            split,
+        } => {
+            println!("Launching the cross-validation...");
+            let cv = client
+                .cross_validate(
+                    scaling.into(),
+                    &hyperparameters.into(),
+                    &split.into(),
+                    folds,
+                )
+                .await?;
+            print_cross_validation(&cv);
+        }
+        Command::Predict {
+            model,
+            local,
+            labeled,
+            protocol,
+            out,
+        } => {
+            let model_id = model.unwrap_or_default();
+            let local = local.as_deref().map(|file| (file, labeled));
+            let predictions = predict(client, local, protocol.into(), &model_id).await?;
+            match out {
+                Some(path) => {
+                    write_predictions(&path, &predictions)?;
+                    println!(
+                        "{} predictions written to {}",
+                        predictions.len(),
+                        path.display()
+                    );
+                }
+                None => print_predictions(&predictions),
+            }
+        }
+        Command::Close => {}
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    let mut client = connect(&args).await?;
+
+    match args.command {
+        Some(Command::Close) => {
+            client.close().await?;
+            if let Some(path) = &args.session {
+                let _ = std::fs::remove_file(path);
+            }
+            Ok(())
+        }
+        Some(command) => {
+            let result = run_command(&mut client, command).await;
+            finish(client, args.session.as_deref()).await?;
+            result
+        }
+        None => {
+            menu(&mut client).await?;
+            finish(client, args.session.as_deref()).await
+        }
+    }
+}
+
+// Print the predictions, or why there are none, the menu going on
+fn show_predictions(result: Result<Vec<Prediction>, ClientError>) {
+    match result {
+        Ok(predictions) => print_predictions(&predictions),
+        Err(e) => println!("{}", e),
+    }
+}
+
+// ID of the stored model to predict with, empty for the uploaded coefficients
+fn read_model_id() -> std::io::Result<String> {
+    println!("Enter the ID of the stored model (leave empty to use the uploaded coefficients):");
+    let mut model_id = String::new();
+    std::io::stdin().read_line(&mut model_id)?;
+    Ok(model_id.trim().to_string())
+}
+
+// Whether the last column of a file to predict on is its target
+fn read_labeled() -> std::io::Result<bool> {
+    println!("Is the last column of the file its target? (y/N)");
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim().eq_ignore_ascii_case("y"))
+}
+
+// Interactive menu, until the user exits
+async fn menu(client: &mut CrypiClient) -> Result<(), Box<dyn Error>> {
+    // Key of the secure training opened by this client, if any
+    let mut training_key = None;
+
+    let mut choice = String::new();
+
+    loop {
+        // Ask the user what does he want to do with the server
+        println!("What do you want to do?");
+        println!("1. Upload a file for training");
+        println!("2. Upload a file for prediction");
+        println!("3. Upload a trained model");
+        println!("4. Launch the training");
+        println!("5. Launch the prediction on the uploaded file");
+        println!("6. Launch the private prediction on a local file");
+        println!("7. Open a secure training as the model owner");
+        println!("8. Upload secret shares of a training file");
+        println!("9. Launch the secure training");
+        println!("10. Exit");
+
+        choice.clear();
+        std::io::stdin().read_line(&mut choice)?;
+
+        match choice.trim().parse::<u32>() {
+            Ok(n @ 1..=3) => {
+                // Ask the user for the name of the file to upload
+                println!("Enter the path of the file to upload:");
+                let mut filepath = String::new();
+                std::io::stdin().read_line(&mut filepath)?;
+                let kind = match n {
+                    1 => UploadKind::Training,
+                    2 if read_labeled()? => UploadKind::LabeledPrediction,
+                    2 => UploadKind::Prediction,
+                    _ => UploadKind::Model,
+                };
+                upload(client, filepath.trim(), kind).await?;
+            }
+            Ok(4) => {
+                // Launch the training
+                println!("Choose the feature scaling:");
+                println!("1. Z-score");
+                println!("2. Min-max");
+                let mut scaling = String::new();
+                std::io::stdin().read_line(&mut scaling)?;
+                let scaling = match scaling.trim().parse::<u32>() {
+                    Ok(1) => Some(Scaling::ZScore),
+                    Ok(2) => Some(Scaling::MinMax),
+                    _ => None,
+                };
+                let result = match scaling {
+                    Some(scaling) => {
+                        let params = Hyperparameters::default();
+                        train(client, scaling, &params, &SplitOptions::default()).await
+                    }
+                    None => Err(ClientError::InvalidData("Invalid choice!".to_string())),
+                };
+                if let Err(e) = result {
+                    println!("{}", e);
+                }
+            }
+            Ok(5) => {
+                // Launch the prediction
+                let model_id = read_model_id()?;
+                let result = predict(client, None, Protocol::SecretSharing, &model_id).await;
+                show_predictions(result);
+            }
+            Ok(6) => {
+                let model_id = read_model_id()?;
+                println!("Enter the path of the file to predict on:");
+                let mut filepath = String::new();
+                std::io::stdin().read_line(&mut filepath)?;
+                let labeled = read_labeled()?;
+                println!("Choose the protocol:");
+                println!("1. Secret sharing");
+                println!("2. Secret sharing with garbled-circuit comparisons");
+                println!("3. Paillier encryption");
+                println!("4. CKKS encryption");
+                let mut protocol = String::new();
+                std::io::stdin().read_line(&mut protocol)?;
+                let protocol = match protocol.trim().parse::<u32>() {
+                    Ok(1) => Protocol::SecretSharing,
+                    Ok(2) => Protocol::GarbledCircuit,
+                    Ok(3) => Protocol::Paillier,
+                    Ok(4) => Protocol::Ckks,
+                    _ => {
+                        println!("Invalid choice!");
+                        continue;
+                    }
+                };
+                let local = Some((filepath.trim(), labeled));
+                let result = predict(client, local, protocol, &model_id).await;
+                show_predictions(result);
+            }
+            Ok(7) => {
+                println!("Generating the training key...");
+                training_key = Some(client.open_secure_training().await?);
+                println!("Secure training opened, waiting for the data owners' shares");
+            }
+            Ok(8) => {
+                println!("Enter the path of the training file to share:");
+                let mut filepath = String::new();
+                std::io::stdin().read_line(&mut filepath)?;
+                let rows = client.upload_training_shares(filepath.trim()).await?;
+                println!("Shares of {} rows uploaded", rows);
+            }
+            Ok(9) => match &training_key {
+                Some(key) => {
+                    println!("Enter the path where to save the model:");
+                    let mut filepath = String::new();
+                    std::io::stdin().read_line(&mut filepath)?;
+                    println!("Running the secure training...");
+                    let params = Hyperparameters::default();
+                    match client.secure_training(key, &params).await {
+                        Ok(theta) => {
+                            let mut file = std::fs::File::create(filepath.trim())?;
+                            for coefficient in theta.iter() {
+                                writeln!(file, "{}", coefficient)?;
+                            }
+                            println!("Model saved to {}", filepath.trim());
+                        }
+                        Err(ClientError::Refused(message)) => println!("{}", message),
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+                None => println!("Open a secure training first!"),
+            },
+            Ok(10) => {
+                // Exit the program
+                println!("Exiting...");
+                return Ok(());
+            }
+            _ => {
+                println!("Invalid choice!");
+            }
+        }
+    }
+}
@@ -0,0 +1,1100 @@
+// Client of the server, for the client binary and for the services embedding it.
+// A CrypiClient holds a session on the server: the files it uploads are only seen
+// by the training and the predictions it launches.
+
+use crate::file::file_client::FileClient;
+use crate::file::{
+    CkksCiphertext, CkksQuery, ComparisonProtocol, KeyExchange, MpcMessage, PaillierQuery,
+    PredictionMode, RequestCrossValidation, RequestPrediction, RequestSecureTraining,
+    RequestTraining, ResponsePrediction, Scaling, SessionRequest, TrainingKeyRequest,
+    TrainingShares, TripleSource, UploadChunk, UploadStatusRequest,
+};
+use crate::key_exchange::{KeyAgreement, SessionKey, Transcript, KEY_LIFETIME};
+use crate::metrics::{CrossValidation, Metrics, ThresholdMetric};
+use crate::normalize::Imputer;
+use crate::resample::ResamplingMethod;
+use crate::schema::Schema;
+use crate::split::SplitOptions;
+use crate::training::{ClassWeights, Hyperparameters, Multiclass, Optimizer};
+use crate::{ckks, csv_file, file, he, mpc, normalize, training, triples};
+
+use http::uri::Uri;
+use ring::digest::{Context, SHA256};
+use std::path::{Path, PathBuf};
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::service::{interceptor::InterceptedService, Interceptor};
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity};
+use tonic::Status;
+
+#[derive(Debug)]
+pub enum ClientError {
+    // Reading a local file
+    Io(std::io::Error),
+    // Invalid certificate, key or address
+    Config(String),
+    // Connecting to the server or the dealer
+    Transport(tonic::transport::Error),
+    // A call to the server failed
+    Status(Box<Status>),
+    // The server refused the request, for the given reason
+    Refused(String),
+    // A local file, or a response of the server, is invalid
+    InvalidData(String),
+    // A key agreement or a secure computation failed
+    Computation(String),
+    // The upload did not complete after the given number of streams
+    UploadIncomplete(usize),
+}
+
+impl std::fmt::Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientError::Io(e) => write!(f, "{}", e),
+            ClientError::Config(message) => write!(f, "Invalid configuration: {}", message),
+            ClientError::Transport(e) => write!(f, "Connection failed: {}", e),
+            ClientError::Status(status) => {
+                write!(f, "{:?}: {}", status.code(), status.message())
+            }
+            ClientError::Refused(message) => write!(f, "{}", message),
+            ClientError::InvalidData(message) => write!(f, "{}", message),
+            ClientError::Computation(message) => {
+                write!(f, "Secure computation failed: {}", message)
+            }
+            ClientError::UploadIncomplete(attempts) => {
+                write!(f, "The upload failed after {} attempts", attempts)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Io(e) => Some(e),
+            ClientError::Transport(e) => Some(e),
+            ClientError::Status(status) => Some(status.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ClientError {
+    fn from(e: std::io::Error) -> Self {
+        ClientError::Io(e)
+    }
+}
+
+impl From<tonic::transport::Error> for ClientError {
+    fn from(e: tonic::transport::Error) -> Self {
+        ClientError::Transport(e)
+    }
+}
+
+impl From<Status> for ClientError {
+    fn from(status: Status) -> Self {
+        ClientError::Status(Box::new(status))
+    }
+}
+
+impl ClientError {
+    // Whether the server no longer knows the session, which expired or was closed
+    pub fn is_session_expired(&self) -> bool {
+        matches!(self, ClientError::Status(status) if status.code() == tonic::Code::Unauthenticated)
+    }
+}
+
+fn invalid_data(e: impl std::fmt::Display) -> ClientError {
+    ClientError::InvalidData(e.to_string())
+}
+
+fn computation(e: impl std::fmt::Display) -> ClientError {
+    ClientError::Computation(e.to_string())
+}
+
+// The server reports the requests it refuses in the message of the response
+fn refused(message: String) -> Result<(), ClientError> {
+    if message.is_empty() {
+        Ok(())
+    } else {
+        Err(ClientError::Refused(message))
+    }
+}
+
+// Address of the server and of the dealer, and the files of the TLS identity of
+// the client
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    pub host: String,
+    pub port: u16,
+    // Trusted dealer providing the triples of the secure computations, as
+    // host:port. Without a dealer, the triples are generated with the server
+    // through oblivious transfers.
+    pub dealer: Option<String>,
+    // Certificate of the CA of the server
+    pub ca: PathBuf,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            host: "127.0.0.1".to_string(),
+            port: 50051,
+            dealer: None,
+            ca: PathBuf::from("ca.crt"),
+            cert: PathBuf::from("client.crt"),
+            key: PathBuf::from("client.key"),
+        }
+    }
+}
+
+// Kind of an uploaded file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UploadKind {
+    // Training dataset, .csv
+    Training,
+    // Dataset to predict on, .csv
+    Prediction,
+    // Dataset to predict on whose last column is the target, which is not a
+    // feature, .csv. Only needed without a schema file.
+    LabeledPrediction,
+    // Coefficients of a model, .txt
+    Model,
+}
+
+// Protocol of a prediction on a local file, which never leaves the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    // Secret sharing, the comparisons computed with an adder
+    SecretSharing,
+    // Secret sharing, the comparisons computed with garbled circuits
+    GarbledCircuit,
+    // The dataset encrypted with a fresh Paillier key
+    Paillier,
+    // The dataset encrypted with a fresh CKKS key
+    Ckks,
+}
+
+// Label and probability predicted for a row: the probability of the positive class
+// of a binary model, of the predicted class of a multiclass model
+#[derive(Debug, Clone, PartialEq)]
+pub struct Prediction {
+    pub label: u32,
+    pub probability: f64,
+    // Probability of every class, the classes 0 and 1 of a binary model
+    pub class_probabilities: Vec<f64>,
+}
+
+impl Prediction {
+    fn binary(label: u32, probability: f64) -> Self {
+        Prediction {
+            label,
+            probability,
+            class_probabilities: vec![1.0 - probability, probability],
+        }
+    }
+}
+
+// Model trained and stored by the server
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrainedModel {
+    pub model_id: String,
+    // Accuracy on the test split of the training dataset
+    pub accuracy: f32,
+    // Epochs the training ran before it converged
+    pub epochs: u32,
+    // Evaluation on the test split
+    pub metrics: Metrics,
+}
+
+// Paillier key of a secure training opened by the client, the model owner
+#[derive(Debug, Clone)]
+pub struct TrainingKey(he::PrivateKey);
+
+// Metadata of every call but OpenSession carrying the ID of its session
+pub const SESSION_HEADER: &str = "session-id";
+
+// Client of the server, within a session
+type ServerClient = FileClient<InterceptedService<Channel, SessionInterceptor>>;
+
+// Adds the ID of the session to the metadata of every call
+#[derive(Clone)]
+struct SessionInterceptor {
+    session_id: MetadataValue<Ascii>,
+}
+
+impl Interceptor for SessionInterceptor {
+    fn call(&mut self, mut request: tonic::Request<()>) -> Result<tonic::Request<()>, Status> {
+        request
+            .metadata_mut()
+            .insert(SESSION_HEADER, self.session_id.clone());
+        Ok(request)
+    }
+}
+
+// Every call but OpenSession belongs to the session
+fn session_client(channel: Channel, session_id: &str) -> Result<ServerClient, ClientError> {
+    let interceptor = SessionInterceptor {
+        session_id: MetadataValue::try_from(session_id)
+            .map_err(|_| invalid_data(format!("Invalid session ID {:?}", session_id)))?,
+    };
+    Ok(FileClient::with_interceptor(channel, interceptor))
+}
+
+// Transport of the data owner party, every exchange is a MpcExchange call
+struct GrpcTransport {
+    client: ServerClient,
+}
+
+#[tonic::async_trait]
+impl mpc::Transport for GrpcTransport {
+    async fn exchange(&mut self, outgoing: Vec<u64>) -> mpc::MpcResult<Vec<u64>> {
+        let request = tonic::Request::new(MpcMessage { values: outgoing });
+        let response = self.client.mpc_exchange(request).await?;
+        Ok(response.into_inner().values)
+    }
+}
+
+// Session key agreed on with the server, and the certificate it is bound to
+struct SessionKeys {
+    key: SessionKey,
+    certificate: Vec<u8>,
+}
+
+impl SessionKeys {
+    // Open a session on the server, returning its ID
+    async fn open(
+        channel: Channel,
+        certificate: Vec<u8>,
+    ) -> Result<(String, SessionKeys), ClientError> {
+        let agreement = KeyAgreement::client().map_err(computation)?;
+        let request = tonic::Request::new(KeyExchange {
+            public_key: agreement.public_key.clone(),
+        });
+        let session = FileClient::new(channel)
+            .open_session(request)
+            .await?
+            .into_inner();
+        let key = agreement
+            .agree(&session.public_key, &certificate, None)
+            .map_err(computation)?;
+        Ok((session.session_id, SessionKeys { key, certificate }))
+    }
+
+    // Agree on a new session key, chained to the current one
+    async fn rotate(&mut self, client: &mut ServerClient) -> Result<(), ClientError> {
+        let agreement = KeyAgreement::client().map_err(computation)?;
+        let request = tonic::Request::new(KeyExchange {
+            public_key: agreement.public_key.clone(),
+        });
+        let response = client.rotate_session_key(request).await?.into_inner();
+        self.key = agreement
+            .agree(&response.public_key, &self.certificate, Some(&self.key))
+            .map_err(computation)?;
+        Ok(())
+    }
+
+    // Session key, rotated first if the server would soon refuse it
+    async fn current(&mut self, client: &mut ServerClient) -> Result<&SessionKey, ClientError> {
+        if self.key.age() > KEY_LIFETIME / 2 {
+            self.rotate(client).await?;
+        }
+        Ok(&self.key)
+    }
+}
+
+// Size of the chunks of an upload
+const CHUNK_SIZE: usize = 64 * 1024;
+// Streams of an upload before giving up, every stream resuming where the server
+// stopped receiving
+const UPLOAD_ATTEMPTS: usize = 5;
+
+// Chunks of `data` from `offset` on, which is a multiple of the chunk size. A fully
+// received file gives a single empty chunk, so that it still gets checked.
+fn upload_chunks(template: &UploadChunk, data: &[u8], offset: usize) -> Vec<UploadChunk> {
+    let mut chunks = Vec::new();
+    let mut start = offset;
+    loop {
+        let end = (start + CHUNK_SIZE).min(data.len());
+        let content = data[start..end].to_vec();
+
+        // Calculate the SHA-256 hash of the chunk
+        let mut context = Context::new(&SHA256);
+        context.update(&content);
+        let chunk_hash = context.finish();
+
+        chunks.push(UploadChunk {
+            offset: start as u64,
+            content,
+            hash: chunk_hash.as_ref().to_vec(),
+            ..template.clone()
+        });
+        if end == data.len() {
+            break;
+        }
+        start = end;
+    }
+    chunks
+}
+
+// Errors after which an upload can go on: the stream broke, or a chunk was
+// corrupted or sent at the wrong offset
+fn resumable(status: &Status) -> bool {
+    matches!(
+        status.code(),
+        tonic::Code::Unknown
+            | tonic::Code::Unavailable
+            | tonic::Code::Cancelled
+            | tonic::Code::DeadlineExceeded
+            | tonic::Code::Aborted
+            | tonic::Code::DataLoss
+            | tonic::Code::OutOfRange
+    )
+}
+
+// Feature matrix of a local dataset to predict on, with the feature layout of the
+// model if it has one, with the schema and the imputer of the dataset otherwise.
+// The last column is left out as the target if `labeled`, and the features of a
+// model without layout must match its coefficients.
+fn local_features(
+    path: &Path,
+    labeled: bool,
+    layout: &ResponsePrediction,
+) -> Result<ndarray::Array2<f64>, ClientError> {
+    let content = csv_file::read_csv_file(path.to_string_lossy().to_string(), labeled)
+        .map_err(invalid_data)?;
+    if layout.schema.is_empty() {
+        let X = normalize::prediction_features(&content, &content.schema, None)
+            .map_err(invalid_data)?;
+        let coefficients = layout.coefficients as usize;
+        if X.ncols() != coefficients && X.ncols() + 1 != coefficients {
+            return Err(invalid_data(format!(
+                "The file has {} features but the model has {} coefficients{}",
+                X.ncols(),
+                coefficients,
+                if labeled {
+                    ""
+                } else {
+                    ", is its last column the target?"
+                }
+            )));
+        }
+        return Ok(X);
+    }
+
+    let schema: Schema = serde_json::from_str(&layout.schema).map_err(invalid_data)?;
+    let imputer: Option<Imputer> = match layout.imputer.as_str() {
+        "" => None,
+        imputer => Some(serde_json::from_str(imputer).map_err(invalid_data)?),
+    };
+    normalize::prediction_features(&content, &schema, imputer.as_ref()).map_err(invalid_data)
+}
+
+// Predictions of the probabilities of the positive class, with the decision
+// threshold of the model
+fn labeled(probabilities: &ndarray::Array1<f64>, threshold: f64) -> Vec<Prediction> {
+    probabilities
+        .iter()
+        .map(|&probability| Prediction::binary((probability >= threshold) as u32, probability))
+        .collect()
+}
+
+pub struct CrypiClient {
+    client: ServerClient,
+    keys: SessionKeys,
+    session_id: String,
+    dealer: Option<Channel>,
+}
+
+// Channels to the server and to the dealer if any, and the DER of the client
+// certificate
+async fn connect_channels(
+    config: &ClientConfig,
+) -> Result<(Channel, Option<Channel>, Vec<u8>), ClientError> {
+    let server_root_ca_cert = Certificate::from_pem(std::fs::read_to_string(&config.ca)?);
+    let client_cert = std::fs::read_to_string(&config.cert)?;
+    let client_cert_der = rustls_pemfile::certs(&mut client_cert.as_bytes())?
+        .into_iter()
+        .next()
+        .ok_or_else(|| {
+            ClientError::Config(format!("{} holds no certificate", config.cert.display()))
+        })?;
+    let client_key = std::fs::read_to_string(&config.key)?;
+    let client_identity = Identity::from_pem(client_cert, client_key);
+
+    let tls = ClientTlsConfig::new()
+        .domain_name("localhost")
+        .ca_certificate(server_root_ca_cert)
+        .identity(client_identity);
+
+    let uri: Uri = format!("http://{}:{}", config.host, config.port)
+        .parse()
+        .map_err(|e| ClientError::Config(format!("Invalid server address: {}", e)))?;
+    let channel = Channel::builder(uri)
+        .tls_config(tls.clone())?
+        .connect()
+        .await?;
+
+    let mut dealer = None;
+    if let Some(address) = &config.dealer {
+        let uri: Uri = format!("http://{}", address)
+            .parse()
+            .map_err(|e| ClientError::Config(format!("Invalid dealer address: {}", e)))?;
+        dealer = Some(Channel::builder(uri).tls_config(tls)?.connect().await?);
+    }
+    Ok((channel, dealer, client_cert_der))
+}
+
+// Training request with the given options
+fn training_request(
+    scaling: Scaling,
+    params: &Hyperparameters,
+    split: &SplitOptions,
+) -> Result<RequestTraining, ClientError> {
+    let optimizer = match params.optimizer {
+        Optimizer::Sgd => file::Optimizer::Sgd,
+        Optimizer::Momentum => file::Optimizer::Momentum,
+        Optimizer::Adam => file::Optimizer::Adam,
+        Optimizer::Lbfgs => file::Optimizer::Lbfgs,
+    };
+    Ok(RequestTraining {
+        train: true,
+        scaling: scaling as i32,
+        learning_rate: params.learning_rate,
+        epochs: u32::try_from(params.epochs)
+            .map_err(|_| ClientError::Config("Too many epochs".to_string()))?,
+        batch_size: u32::try_from(params.batch_size)
+            .map_err(|_| ClientError::Config("The batch size is too large".to_string()))?,
+        optimizer: optimizer as i32,
+        l1: params.l1,
+        l2: params.l2,
+        no_intercept: !params.intercept,
+        tolerance: params.tolerance,
+        seed: params.seed,
+        class_weights: Some(match params.class_weights {
+            ClassWeights::Uniform => file::ClassWeights::default(),
+            ClassWeights::Balanced => file::ClassWeights {
+                balanced: true,
+                ..Default::default()
+            },
+            ClassWeights::Custom { negative, positive } => file::ClassWeights {
+                balanced: false,
+                negative,
+                positive,
+            },
+        }),
+        resampling: Some(file::Resampling {
+            method: match params.resampling.method {
+                ResamplingMethod::None => file::ResamplingMethod::NoResampling,
+                ResamplingMethod::Oversample => file::ResamplingMethod::Oversample,
+                ResamplingMethod::Undersample => file::ResamplingMethod::Undersample,
+                ResamplingMethod::Smote => file::ResamplingMethod::Smote,
+            } as i32,
+            neighbors: u32::try_from(params.resampling.neighbors)
+                .map_err(|_| ClientError::Config("Too many SMOTE neighbors".to_string()))?,
+            seed: params.resampling.seed,
+        }),
+        threshold_metric: match params.threshold_metric {
+            None => file::ThresholdMetric::FixedThreshold,
+            Some(ThresholdMetric::F1) => file::ThresholdMetric::F1,
+            Some(ThresholdMetric::Youden) => file::ThresholdMetric::Youden,
+            Some(ThresholdMetric::Accuracy) => file::ThresholdMetric::Accuracy,
+        } as i32,
+        multiclass: match params.multiclass {
+            Multiclass::Binary => file::Multiclass::Binary,
+            Multiclass::Softmax => file::Multiclass::Softmax,
+            Multiclass::OneVsRest => file::Multiclass::OneVsRest,
+        } as i32,
+        split: Some(file::Split {
+            test_fraction: split.test_fraction,
+            shuffle: split.shuffle,
+            stratify: split.stratify,
+            seed: split.seed,
+        }),
+    })
+}
+
+impl CrypiClient {
+    // Connect to the server, and to the dealer if any, and open a new session
+    pub async fn connect(config: &ClientConfig) -> Result<CrypiClient, ClientError> {
+        let (channel, dealer, certificate) = connect_channels(config).await?;
+        let (session_id, keys) = SessionKeys::open(channel.clone(), certificate).await?;
+        Ok(CrypiClient {
+            client: session_client(channel, &session_id)?,
+            keys,
+            session_id,
+            dealer,
+        })
+    }
+
+    // Connect to the server and resume a session opened by another client with
+    // the same certificate, from its ID and its key. The key is rotated first,
+    // which fails with a session expired error if the server closed the session.
+    pub async fn resume(
+        config: &ClientConfig,
+        session_id: &str,
+        session_key: &[u8],
+    ) -> Result<CrypiClient, ClientError> {
+        let (channel, dealer, certificate) = connect_channels(config).await?;
+        let mut client = session_client(channel, session_id)?;
+        let mut keys = SessionKeys {
+            key: SessionKey::resume(session_key.to_vec()),
+            certificate,
+        };
+        keys.rotate(&mut client).await?;
+        Ok(CrypiClient {
+            client,
+            keys,
+            session_id: session_id.to_string(),
+            dealer,
+        })
+    }
+
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    // Current key of the session, to resume it later
+    pub fn session_key(&self) -> &[u8] {
+        self.keys.key.secret()
+    }
+
+    // Close the session, the server deleting its files
+    pub async fn close(mut self) -> Result<(), ClientError> {
+        self.client.close_session(SessionRequest {}).await?;
+        Ok(())
+    }
+
+    // Upload a file in chunks, resuming the upload where the server stopped
+    // receiving when the stream breaks. Returns the ID the server stored it under.
+    pub async fn upload(
+        &mut self,
+        path: impl AsRef<Path>,
+        kind: UploadKind,
+    ) -> Result<String, ClientError> {
+        let path = path.as_ref();
+        // Keep only the filename
+        let filename = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .ok_or_else(|| invalid_data(format!("{} is not a file", path.display())))?;
+
+        let serialized_data = match kind {
+            UploadKind::Training | UploadKind::Prediction | UploadKind::LabeledPrediction => {
+                if !filename.ends_with(".csv") {
+                    return Err(invalid_data("Datasets must be .csv files"));
+                }
+                // The cells and the schema of the file
+                let has_target = kind != UploadKind::Prediction;
+                let content =
+                    csv_file::read_csv_file(path.to_string_lossy().to_string(), has_target)
+                        .map_err(invalid_data)?;
+                bincode::serialize(&content).map_err(invalid_data)?
+            }
+            UploadKind::Model => {
+                if !filename.ends_with(".txt") {
+                    return Err(invalid_data("Models must be .txt files"));
+                }
+                let content =
+                    csv_file::read_file_to_array2(&path.to_string_lossy()).map_err(invalid_data)?;
+                bincode::serialize(&content).map_err(invalid_data)?
+            }
+        };
+
+        let upload_id: String = mpc::random_u64s(2)
+            .iter()
+            .map(|w| format!("{:016x}", w))
+            .collect();
+        let template = UploadChunk {
+            upload_id: upload_id.clone(),
+            filename,
+            train: kind == UploadKind::Training,
+            coefs: kind == UploadKind::Model,
+            size: serialized_data.len() as u64,
+            ..Default::default()
+        };
+
+        // HMAC of the transcript of the chunks, which keep their boundaries when the
+        // upload resumes, under the key of the upload
+        let mut transcript = Transcript::new(&template);
+        for chunk in upload_chunks(&template, &serialized_data, 0).iter() {
+            transcript.add(chunk);
+        }
+        let transfer_key = self
+            .keys
+            .current(&mut self.client)
+            .await?
+            .transfer_key(&upload_id);
+        let hmac_hash = transcript.mac(&transfer_key);
+
+        let mut offset = 0;
+        for _ in 0..UPLOAD_ATTEMPTS {
+            let mut chunks = upload_chunks(&template, &serialized_data, offset);
+            if let Some(last) = chunks.last_mut() {
+                last.hmac_hash = hmac_hash.clone();
+            }
+            match self.client.upload_file(tokio_stream::iter(chunks)).await {
+                Ok(response) if response.get_ref().complete => {
+                    return Ok(response.into_inner().object_id)
+                }
+                Ok(response) => offset = response.into_inner().received as usize,
+                Err(e) if resumable(&e) => {
+                    // Resume from the last chunk the server received, if it received any
+                    let request = tonic::Request::new(UploadStatusRequest {
+                        upload_id: upload_id.clone(),
+                    });
+                    offset = match self.client.query_upload_status(request).await {
+                        Ok(response) => response.into_inner().received as usize,
+                        Err(e) if e.code() == tonic::Code::NotFound => 0,
+                        Err(e) => return Err(e.into()),
+                    };
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Err(ClientError::UploadIncomplete(UPLOAD_ATTEMPTS))
+    }
+
+    // Train a model on the uploaded training dataset, which the server stores,
+    // evaluated on the test set of `split`
+    pub async fn train(
+        &mut self,
+        scaling: Scaling,
+        params: &Hyperparameters,
+        split: &SplitOptions,
+    ) -> Result<TrainedModel, ClientError> {
+        let request = tonic::Request::new(training_request(scaling, params, split)?);
+        let response = self.client.launch_training(request).await?.into_inner();
+        refused(response.message)?;
+        Ok(TrainedModel {
+            model_id: response.model_id,
+            accuracy: response.accuracy,
+            epochs: response.epochs,
+            metrics: response.metrics.unwrap_or_default().into(),
+        })
+    }
+
+    // Evaluate the training on `folds` folds of the uploaded training dataset, the
+    // folds being shuffled and stratified as `split` says
+    pub async fn cross_validate(
+        &mut self,
+        scaling: Scaling,
+        params: &Hyperparameters,
+        split: &SplitOptions,
+        folds: usize,
+    ) -> Result<CrossValidation, ClientError> {
+        let request = tonic::Request::new(RequestCrossValidation {
+            training: Some(training_request(scaling, params, split)?),
+            folds: u32::try_from(folds)
+                .map_err(|_| ClientError::Config("Too many folds".to_string()))?,
+        });
+        let response = self.client.cross_validate(request).await?.into_inner();
+        refused(response.message.clone())?;
+        Ok(response.into())
+    }
+
+    // Predict on the uploaded dataset with the stored model `model_id`, or with the
+    // uploaded coefficients
+    pub async fn predict(
+        &mut self,
+        model_id: Option<&str>,
+    ) -> Result<Vec<Prediction>, ClientError> {
+        let request = tonic::Request::new(RequestPrediction {
+            predict: true,
+            model_id: model_id.unwrap_or_default().to_string(),
+            ..Default::default()
+        });
+        let response = self.client.launch_prediction(request).await?.into_inner();
+        refused(response.message)?;
+
+        let classes = (response.classes as usize).max(1);
+        let rows = response.labels.len();
+        if response.class_probabilities.len() != rows * classes {
+            return Err(invalid_data(format!(
+                "Expected {} class probabilities, got {}",
+                rows * classes,
+                response.class_probabilities.len()
+            )));
+        }
+        Ok(response
+            .labels
+            .iter()
+            .zip(response.probabilities.iter())
+            .zip(response.class_probabilities.chunks(classes))
+            .map(|((&label, &probability), class_probabilities)| Prediction {
+                label,
+                probability,
+                class_probabilities: class_probabilities.to_vec(),
+            })
+            .collect())
+    }
+
+    // Predict on a local file, which never leaves the client, with the stored
+    // model `model_id`, or with the uploaded coefficients. The last column of a
+    // `labeled` file is its target, which is not a feature.
+    pub async fn predict_local(
+        &mut self,
+        path: impl AsRef<Path>,
+        labeled: bool,
+        protocol: Protocol,
+        model_id: Option<&str>,
+    ) -> Result<Vec<Prediction>, ClientError> {
+        let model_id = model_id.unwrap_or_default();
+        let request = tonic::Request::new(RequestPrediction {
+            predict: false,
+            model_id: model_id.to_string(),
+            ..Default::default()
+        });
+        let layout = self.client.launch_prediction(request).await?.into_inner();
+        refused(layout.message.clone())?;
+        let X = local_features(path.as_ref(), labeled, &layout)?;
+        match protocol {
+            Protocol::SecretSharing => {
+                self.secret_shared_prediction(X, mpc::Comparisons::Adder, model_id)
+                    .await
+            }
+            Protocol::GarbledCircuit => {
+                self.secret_shared_prediction(X, mpc::Comparisons::GarbledCircuit, model_id)
+                    .await
+            }
+            Protocol::Paillier => self.paillier_prediction(X, model_id).await,
+            Protocol::Ckks => self.ckks_prediction(X, model_id).await,
+        }
+    }
+
+    // The server only sees shares of the features and returns its shares of the
+    // result, which only the client reconstructs
+    async fn secret_shared_prediction(
+        &mut self,
+        mut X: ndarray::Array2<f64>,
+        comparisons: mpc::Comparisons,
+        model_id: &str,
+    ) -> Result<Vec<Prediction>, ClientError> {
+        // Without a dealer, the triples are generated with the server through OTs
+        let source = match self.dealer {
+            Some(_) => TripleSource::Dealer,
+            None => TripleSource::ObliviousTransfer,
+        };
+        let protocol = match comparisons {
+            mpc::Comparisons::Adder => ComparisonProtocol::Adder,
+            mpc::Comparisons::GarbledCircuit => ComparisonProtocol::GarbledCircuit,
+        };
+
+        let request = tonic::Request::new(RequestPrediction {
+            predict: true,
+            mode: PredictionMode::SecretShared as i32,
+            rows: X.nrows() as u32,
+            features: X.ncols() as u32,
+            triples: source as i32,
+            comparisons: protocol as i32,
+            model_id: model_id.to_string(),
+            ..Default::default()
+        });
+        let response = self.client.launch_prediction(request).await?.into_inner();
+        refused(response.message)?;
+
+        // A model with one more coefficient than there are features carries an intercept
+        if response.coefficients as usize == X.ncols() + 1 {
+            X = normalize::add_intercept(&X);
+        }
+
+        let triples: Box<dyn mpc::TripleProvider> = match &self.dealer {
+            Some(channel) => Box::new(triples::DealerProvider::new(
+                channel.clone(),
+                response.computation_id,
+            )),
+            None => Box::<triples::OtProvider>::default(),
+        };
+        let mut party = mpc::Party::new(
+            0,
+            GrpcTransport {
+                client: self.client.clone(),
+            },
+            triples,
+        );
+        party.comparisons = comparisons;
+        let (rows, cols) = X.dim();
+        let x: Vec<f64> = X.iter().copied().collect();
+        let z0 = mpc::linear_data_owner(&mut party, &x, rows, cols)
+            .await
+            .map_err(computation)?;
+        let (probabilities, labels) = mpc::classify(&mut party, &z0, response.threshold)
+            .await
+            .map_err(computation)?;
+        let mut outputs = probabilities;
+        outputs.extend(labels);
+        let outputs = party
+            .reveal_to(0, &outputs)
+            .await
+            .map_err(computation)?
+            .ok_or_else(|| computation("The result was not revealed to the client"))?;
+
+        let (probabilities, labels) = outputs.split_at(rows);
+        Ok(probabilities
+            .iter()
+            .zip(labels.iter())
+            .map(|(&probability, &label)| {
+                Prediction::binary(label as u32, mpc::decode(probability))
+            })
+            .collect())
+    }
+
+    // The dataset is encrypted with a fresh Paillier key: the server computes the
+    // encrypted logits in a single round trip, the client decrypts them and applies
+    // the sigmoid itself
+    async fn paillier_prediction(
+        &mut self,
+        X: ndarray::Array2<f64>,
+        model_id: &str,
+    ) -> Result<Vec<Prediction>, ClientError> {
+        let key = he::keygen(he::KEY_BITS);
+        let public = &key.public;
+
+        let ciphertexts = X
+            .iter()
+            .map(|&x| public.encrypt(&public.encode(x)).to_bytes_be())
+            .collect();
+
+        let request = tonic::Request::new(RequestPrediction {
+            predict: true,
+            mode: PredictionMode::Paillier as i32,
+            rows: X.nrows() as u32,
+            features: X.ncols() as u32,
+            paillier: Some(PaillierQuery {
+                modulus: public.n.to_bytes_be(),
+                ciphertexts,
+            }),
+            model_id: model_id.to_string(),
+            ..Default::default()
+        });
+        let response = self.client.launch_prediction(request).await?.into_inner();
+        refused(response.message)?;
+
+        // The logits carry the fractional bits of both the features and the coefficients
+        let logits: ndarray::Array1<f64> = response
+            .ciphertexts
+            .iter()
+            .map(|c| key.decrypt_fixed(&num_bigint::BigUint::from_bytes_be(c), 2 * mpc::FRAC_BITS))
+            .collect();
+        Ok(labeled(&training::sigmoid(&logits), response.threshold))
+    }
+
+    // The dataset is encrypted with a fresh CKKS key: every feature column of a block
+    // of 4096 rows is packed in one ciphertext, so that the server computes the
+    // logits of the whole block at once
+    async fn ckks_prediction(
+        &mut self,
+        X: ndarray::Array2<f64>,
+        model_id: &str,
+    ) -> Result<Vec<Prediction>, ClientError> {
+        if X.iter().any(|x| x.is_nan() || x.abs() > ckks::MAX_FEATURE) {
+            return Err(invalid_data(format!(
+                "CKKS encrypts features of at most {} in absolute value",
+                ckks::MAX_FEATURE
+            )));
+        }
+        let key = ckks::SecretKey::generate();
+        let public = key.public_key();
+
+        let mut columns = Vec::new();
+        for block in X.axis_chunks_iter(ndarray::Axis(0), ckks::SLOTS) {
+            for column in block.columns() {
+                let ciphertext = key.encrypt(&column.to_vec());
+                columns.push(CkksCiphertext {
+                    c0: ciphertext.c0,
+                    c1: ciphertext.c1,
+                });
+            }
+        }
+
+        let request = tonic::Request::new(RequestPrediction {
+            predict: true,
+            mode: PredictionMode::Ckks as i32,
+            rows: X.nrows() as u32,
+            features: X.ncols() as u32,
+            ckks: Some(CkksQuery {
+                columns,
+                public_key: Some(CkksCiphertext {
+                    c0: public.b,
+                    c1: public.a,
+                }),
+            }),
+            model_id: model_id.to_string(),
+            ..Default::default()
+        });
+        let response = self.client.launch_prediction(request).await?.into_inner();
+        refused(response.message)?;
+
+        if response.logits.len() != X.nrows().div_ceil(ckks::SLOTS) {
+            return Err(invalid_data(
+                "Unexpected number of CKKS ciphertexts in the response",
+            ));
+        }
+
+        let mut logits = Vec::with_capacity(X.nrows());
+        for (block, ciphertext) in response.logits.into_iter().enumerate() {
+            let count = ckks::SLOTS.min(X.nrows() - block * ckks::SLOTS);
+            let ciphertext = ckks::Ciphertext {
+                c0: ciphertext.c0,
+                c1: ciphertext.c1,
+            };
+            if !ciphertext.is_valid() {
+                return Err(invalid_data("Invalid CKKS ciphertext in the response"));
+            }
+            logits.extend(key.decrypt(&ciphertext, ckks::LOGIT_SCALE_BITS, count));
+        }
+        Ok(labeled(
+            &training::sigmoid(&ndarray::Array1::from(logits)),
+            response.threshold,
+        ))
+    }
+
+    // Open a secure training of which this client is the model owner: the data
+    // owners encrypt the seeds of our shares of their rows with the returned key
+    pub async fn open_secure_training(&mut self) -> Result<TrainingKey, ClientError> {
+        let key = he::keygen(he::KEY_BITS);
+        let request = tonic::Request::new(crate::file::TrainingKey {
+            modulus: key.public.n.to_bytes_be(),
+        });
+        self.client.open_secure_training(request).await?;
+        Ok(TrainingKey(key))
+    }
+
+    // Secret-share a local training file between the server and the model owner.
+    // The model owner's shares are expanded from a random seed, which is sent to the
+    // server encrypted with the model owner's key. Returns the number of rows shared.
+    pub async fn upload_training_shares(
+        &mut self,
+        path: impl AsRef<Path>,
+    ) -> Result<usize, ClientError> {
+        let modulus = self
+            .client
+            .get_training_key(tonic::Request::new(TrainingKeyRequest {}))
+            .await?
+            .into_inner()
+            .modulus;
+        let key = he::PublicKey::new(num_bigint::BigUint::from_bytes_be(&modulus));
+
+        let content = csv_file::read_csv_file(path.as_ref().to_string_lossy().to_string(), true)
+            .map_err(invalid_data)?;
+        let (X, y) = normalize::training_data(&content).map_err(invalid_data)?;
+        if X.iter().any(|v| v.abs() >= mpc::MAX_TRAINING_FEATURE) {
+            return Err(invalid_data(format!(
+                "The features of a secure training must be below {} in absolute value",
+                mpc::MAX_TRAINING_FEATURE
+            )));
+        }
+        let (rows, features) = X.dim();
+        let encoded: Vec<u64> = X
+            .rows()
+            .into_iter()
+            .zip(y.iter())
+            .flat_map(|(row, target)| {
+                row.iter()
+                    .chain(std::iter::once(target))
+                    .map(|&v| mpc::encode(v))
+                    .collect::<Vec<u64>>()
+            })
+            .collect();
+
+        let seed: [u8; 32] = mpc::random_u64s(4)
+            .iter()
+            .flat_map(|w| w.to_le_bytes())
+            .collect::<Vec<u8>>()
+            .try_into()
+            .unwrap();
+        let share0 = mpc::prg(&seed, 0, encoded.len());
+        let encrypted_seed = key.encrypt(&num_bigint::BigUint::from_bytes_be(&seed));
+
+        let request = tonic::Request::new(TrainingShares {
+            rows: rows as u32,
+            features: features as u32,
+            shares: mpc::sub(&encoded, &share0),
+            encrypted_seed: encrypted_seed.to_bytes_be(),
+        });
+        self.client.send_training_shares(request).await?;
+        Ok(rows)
+    }
+
+    // Run the model owner side of the secure training, returning the coefficients
+    // on the unscaled features, the intercept first, which only this client learns.
+    // Only the learning rate, the epochs and the intercept of `params` apply.
+    pub async fn secure_training(
+        &mut self,
+        key: &TrainingKey,
+        params: &Hyperparameters,
+    ) -> Result<Vec<f64>, ClientError> {
+        let source = match self.dealer {
+            Some(_) => TripleSource::Dealer,
+            None => TripleSource::ObliviousTransfer,
+        };
+        let request = tonic::Request::new(RequestSecureTraining {
+            triples: source as i32,
+            learning_rate: params.learning_rate,
+            epochs: u32::try_from(params.epochs)
+                .map_err(|_| ClientError::Config("Too many epochs".to_string()))?,
+            no_intercept: !params.intercept,
+        });
+        let response = self
+            .client
+            .launch_secure_training(request)
+            .await?
+            .into_inner();
+        refused(response.message)?;
+
+        // Our shares of the rows of every data owner, from their seeds
+        let features = response.features as usize;
+        let mut x0 = Vec::new();
+        let mut y0 = Vec::new();
+        for contribution in response.contributions.iter() {
+            let seed = key.0.decrypt(&num_bigint::BigUint::from_bytes_be(
+                &contribution.encrypted_seed,
+            ));
+            let seed = seed.to_bytes_be();
+            if seed.len() > 32 {
+                return Err(invalid_data("Invalid training seed"));
+            }
+            let mut padded = [0u8; 32];
+            padded[32 - seed.len()..].copy_from_slice(&seed);
+
+            let shares = mpc::prg(&padded, 0, contribution.rows as usize * (features + 1));
+            for row in shares.chunks(features + 1) {
+                x0.extend_from_slice(&row[..features]);
+                y0.push(row[features]);
+            }
+        }
+        let rows = y0.len();
+
+        let triples: Box<dyn mpc::TripleProvider> = match &self.dealer {
+            Some(channel) => Box::new(triples::DealerProvider::new(
+                channel.clone(),
+                response.computation_id,
+            )),
+            None => Box::<triples::OtProvider>::default(),
+        };
+        let mut party = mpc::Party::new(
+            0,
+            GrpcTransport {
+                client: self.client.clone(),
+            },
+            triples,
+        );
+        let (theta, scaler) =
+            mpc::scaled_logistic_regression(&mut party, &x0, &y0, rows, features, params)
+                .await
+                .map_err(computation)?;
+        let theta = party
+            .reveal_to(0, &theta)
+            .await
+            .map_err(computation)?
+            .ok_or_else(|| computation("The model was not revealed to the client"))?;
+
+        let theta: ndarray::Array1<f64> = theta
+            .iter()
+            .map(|&coefficient| mpc::decode(coefficient))
+            .collect();
+        Ok(scaler.unscale(&theta).to_vec())
+    }
+}
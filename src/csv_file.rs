@@ -1,92 +1,153 @@
-use ndarray::Array1;
-use serde::{Deserialize, Serialize};
-use std::error::Error;
-use std::fs::File;
-use std::io::BufRead;
-use std::io::BufReader;
-use std::io::BufWriter;
-use std::io::Read;
-use std::io::Write;
-use std::path::Path;
-
-use csv::ReaderBuilder;
-use csv::WriterBuilder;
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Record {
-    pub male: u32,
-    pub age: u32,
-    pub currentSmoker: u32,
-    pub cigsPerDay: f64,
-    pub BPMeds: f64,
-    pub prevalentStroke: u32,
-    pub prevalentHyp: u32,
-    pub diabetes: u32,
-    pub totChol: f64,
-    pub sysBP: f64,
-    pub diaBP: f64,
-    pub BMI: f64,
-    pub heartRate: f64,
-    pub glucose: f64,
-    pub TenYearCHD: u32,
-}
-
-pub fn read_csv_file(path: String) -> Result<Vec<Record>, Box<dyn Error>> {
-    let mut file = File::open(path)?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
-
-    let mut reader = ReaderBuilder::new().from_reader(contents.as_bytes());
-
-    // Put everything into an array of Records
-    let mut records: Vec<Record> = Vec::new();
-    for result in reader.deserialize() {
-        let record: Record = result?;
-        records.push(record);
-    }
-
-    return Ok(records);
-}
-
-pub fn write_csv_file(records: Vec<Record>, path: &str) -> Result<(), Box<dyn Error>> {
-    let file = File::create(path)?;
-    let mut writer = WriterBuilder::new().from_writer(file);
-
-    // Write the records
-    for record in records {
-        writer.serialize(&record)?;
-    }
-
-    // Flush the writer to ensure all data is written to the file
-    writer.flush()?;
-
-    Ok(())
-}
-
-pub fn write_array1_to_file(array: &Array1<f64>, filename: &str) -> Result<(), Box<dyn Error>> {
-    let path = Path::new(filename);
-    let file = File::create(&path)?;
-    let mut writer = BufWriter::new(file);
-
-    for value in array.iter() {
-        writer.write_all(format!("{}\n", value).as_bytes())?;
-    }
-
-    Ok(())
-}
-
-pub fn read_file_to_array1(filename: &str) -> Result<Array1<f64>, Box<dyn std::error::Error>> {
-    let path = Path::new(filename);
-    let file = File::open(&path)?;
-    let reader = BufReader::new(file);
-
-    let mut data = Vec::new();
-
-    for line in reader.lines() {
-        let line = line?;
-        let value: f64 = line.trim().parse()?;
-        data.push(value);
-    }
-
-    Ok(Array1::from(data))
-}
+use crate::schema::Schema;
+use ndarray::Array2;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::PathBuf;
+
+use csv::ReaderBuilder;
+use csv::WriterBuilder;
+
+// Cells of a CSV file and the schema they follow
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Dataset {
+    pub schema: Schema,
+    pub header: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Dataset {
+    // Index of a column in the rows
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.header.iter().position(|h| h == name)
+    }
+
+    // Every row must have one cell per column of the header
+    pub fn check_rows(&self) -> Result<(), Box<dyn Error>> {
+        let width = self.header.len();
+        match self.rows.iter().position(|row| row.len() != width) {
+            Some(i) => Err(format!(
+                "Row {} has {} cells, the header has {} columns",
+                i + 1,
+                self.rows[i].len(),
+                width
+            )
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    // The schema must be consistent, every column of the schema but the target
+    // must be in the file, and every cell must match its column
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        self.schema.check()?;
+        self.check_rows()?;
+        for column in self.schema.columns.iter() {
+            let j = match self.position(&column.name) {
+                Some(j) => j,
+                None if column.name == self.schema.target => continue,
+                None => return Err(format!("The column {} is missing", column.name).into()),
+            };
+            for row in self.rows.iter() {
+                column.validate(&row[j])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Schema file of a CSV file: data.csv is described by data.schema.json or
+// data.schema.toml if one of them exists
+pub fn schema_path(path: &str) -> Option<PathBuf> {
+    let stem = path.strip_suffix(".csv").unwrap_or(path);
+    ["json", "toml"]
+        .iter()
+        .map(|extension| PathBuf::from(format!("{}.schema.{}", stem, extension)))
+        .find(|p| p.exists())
+}
+
+// Dataset of the content of a CSV file, with the schema given for its header
+// and cells
+pub fn parse_csv(
+    content: &[u8],
+    schema: impl FnOnce(&[String], &[Vec<String>]) -> Result<Schema, Box<dyn Error>>,
+) -> Result<Dataset, Box<dyn Error>> {
+    let mut reader = ReaderBuilder::new().from_reader(content);
+    let header: Vec<String> = reader.headers()?.iter().map(|h| h.to_string()).collect();
+    let mut rows = Vec::new();
+    for result in reader.records() {
+        rows.push(
+            result?
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<String>>(),
+        );
+    }
+
+    let dataset = Dataset {
+        schema: schema(&header, &rows)?,
+        header,
+        rows,
+    };
+    dataset.validate()?;
+    Ok(dataset)
+}
+
+// Read a CSV file with its schema file, or with a schema inferred from its cells,
+// whose last column is the target if `has_target`
+pub fn read_csv_file(path: String, has_target: bool) -> Result<Dataset, Box<dyn Error>> {
+    parse_csv(&std::fs::read(&path)?, |header, rows| {
+        match schema_path(&path) {
+            Some(schema_path) => Schema::load(&schema_path),
+            None => Ok(Schema::infer(header, rows, has_target)),
+        }
+    })
+}
+
+// Content of the CSV file of the cells
+pub fn csv_bytes(dataset: &Dataset) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut writer = WriterBuilder::new().from_writer(Vec::new());
+
+    // Write the records
+    writer.write_record(&dataset.header)?;
+    for row in dataset.rows.iter() {
+        writer.write_record(row)?;
+    }
+
+    Ok(writer.into_inner()?)
+}
+
+// One row per feature, the coefficients of every class separated by whitespace or
+// commas. A binary model has a single column, one value per line.
+pub fn parse_array2(content: &str) -> Result<Array2<f64>, Box<dyn Error>> {
+    let mut data = Vec::new();
+    let mut columns = None;
+    for line in content.lines() {
+        let row = line
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|value| !value.is_empty())
+            .map(|value| value.parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()?;
+        if row.is_empty() {
+            continue;
+        }
+        match columns {
+            None => columns = Some(row.len()),
+            Some(n) if n != row.len() => {
+                return Err(
+                    format!("Expected {} coefficients per line, got {}", n, row.len()).into(),
+                )
+            }
+            Some(_) => {}
+        }
+        data.extend(row);
+    }
+    let columns = columns.unwrap_or(1);
+    Ok(Array2::from_shape_vec(
+        (data.len() / columns, columns),
+        data,
+    )?)
+}
+
+pub fn read_file_to_array2(filename: &str) -> Result<Array2<f64>, Box<dyn std::error::Error>> {
+    parse_array2(&std::fs::read_to_string(filename)?)
+}
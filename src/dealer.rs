@@ -0,0 +1,343 @@
+// tonic::Status is the error type of the handler and its helpers
+#![allow(clippy::result_large_err)]
+
+use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
+use tonic::{Request, Response, Status};
+
+use file::dealer_server::{Dealer, DealerServer};
+use file::{TripleKind, TripleRequest, TripleShares};
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crypi::key_exchange::certificate_fingerprint;
+use crypi::{file, mpc};
+
+// Largest triple dealt, in words of one of its vectors: the secure computations of
+// the server need far less
+const MAX_TRIPLE_WORDS: usize = 1 << 22;
+
+// Computations and shares unused for that long are dropped
+const PENDING_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+// Certificate of the model owner: party 1 of every computation is the server
+const SERVER_CERTIFICATE: &str = "server.crt";
+
+// Share of a triple waiting to be fetched by the second party of a computation
+#[derive(Debug)]
+struct PendingShare {
+    party: u32,
+    kind: i32,
+    rows: u32,
+    cols: u32,
+    shares: TripleShares,
+    created: Instant,
+}
+
+// Fingerprints of the certificates of the parties of a computation, bound by
+// their first request
+#[derive(Debug)]
+struct Computation {
+    parties: [Option<Vec<u8>>; 2],
+    last_seen: Instant,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    computations: HashMap<String, Computation>,
+    pending: HashMap<(String, u64), PendingShare>,
+}
+
+impl State {
+    fn expire(&mut self, now: Instant) {
+        self.pending
+            .retain(|_, share| now.duration_since(share.created) < PENDING_TIMEOUT);
+        self.computations
+            .retain(|_, computation| now.duration_since(computation.last_seen) < PENDING_TIMEOUT);
+    }
+}
+
+// Trusted third party handing out Beaver triples: the first party asking for
+// a given triple of a computation gets one share, the second one the other. The
+// shares of party 1 only go to the server, those of party 0 to a single client,
+// so that no caller gets both shares of a triple.
+#[derive(Debug)]
+pub struct MyDealer {
+    server_certificate: Vec<u8>,
+    state: Mutex<State>,
+}
+
+impl MyDealer {
+    fn new(server_certificate: Vec<u8>) -> Self {
+        MyDealer {
+            server_certificate,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    // Check that the caller may fetch the shares of `party` in the computation,
+    // binding the party to the caller on its first request
+    fn authorize(
+        &self,
+        state: &mut State,
+        computation_id: &str,
+        party: u32,
+        certificate: &[u8],
+        now: Instant,
+    ) -> Result<(), Status> {
+        let is_server = certificate == self.server_certificate.as_slice();
+        match party {
+            0 if is_server => {
+                return Err(Status::permission_denied(
+                    "The server cannot fetch the shares of the data owner",
+                ))
+            }
+            1 if !is_server => {
+                return Err(Status::permission_denied(
+                    "Only the server can fetch the shares of the model owner",
+                ))
+            }
+            0 | 1 => {}
+            _ => return Err(Status::invalid_argument("The party must be 0 or 1")),
+        }
+
+        let computation = state
+            .computations
+            .entry(computation_id.to_string())
+            .or_insert(Computation {
+                parties: [None, None],
+                last_seen: now,
+            });
+        computation.last_seen = now;
+        match &computation.parties[party as usize] {
+            Some(bound) if bound.as_slice() != certificate => Err(Status::permission_denied(
+                "Another client takes part in this computation",
+            )),
+            Some(_) => Ok(()),
+            None => {
+                computation.parties[party as usize] = Some(certificate.to_vec());
+                Ok(())
+            }
+        }
+    }
+}
+
+// Kind of a requested triple, refusing the ones larger than MAX_TRIPLE_WORDS
+fn triple_kind(request: &TripleRequest) -> Result<mpc::TripleKind, Status> {
+    let rows = request.rows as usize;
+    let cols = request.cols as usize;
+    let kind = match request.kind() {
+        TripleKind::Matrix => mpc::TripleKind::Matrix { rows, cols },
+        TripleKind::Arithmetic => mpc::TripleKind::Arithmetic(rows),
+        TripleKind::Boolean => mpc::TripleKind::Boolean(rows),
+    };
+    let too_large = match kind {
+        mpc::TripleKind::Matrix { rows, cols } => {
+            rows.max(cols) > MAX_TRIPLE_WORDS || rows * cols > MAX_TRIPLE_WORDS
+        }
+        mpc::TripleKind::Arithmetic(n) | mpc::TripleKind::Boolean(n) => n > MAX_TRIPLE_WORDS,
+    };
+    if too_large {
+        return Err(Status::invalid_argument(format!(
+            "Triples are limited to {} values",
+            MAX_TRIPLE_WORDS
+        )));
+    }
+    Ok(kind)
+}
+
+#[tonic::async_trait]
+impl Dealer for MyDealer {
+    async fn request_triples(
+        &self,
+        request: Request<TripleRequest>,
+    ) -> Result<Response<TripleShares>, Status> {
+        let certificate = request
+            .peer_certs()
+            .and_then(|certificates| certificates.first().map(|c| c.get_ref().to_vec()))
+            .map(|der| certificate_fingerprint(&der))
+            .ok_or_else(|| Status::unauthenticated("A client certificate is required"))?;
+        let request_contents = request.into_inner();
+        let kind = triple_kind(&request_contents)?;
+        let key = (
+            request_contents.computation_id.clone(),
+            request_contents.sequence,
+        );
+
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state.expire(now);
+        self.authorize(
+            &mut state,
+            &request_contents.computation_id,
+            request_contents.party,
+            &certificate,
+            now,
+        )?;
+
+        if let Some(share) = state.pending.remove(&key) {
+            if share.party == request_contents.party
+                || share.kind != request_contents.kind
+                || share.rows != request_contents.rows
+                || share.cols != request_contents.cols
+            {
+                state.pending.insert(key, share);
+                return Err(Status::invalid_argument(
+                    "The triple does not match the one requested by the other party",
+                ));
+            }
+            return Ok(Response::new(share.shares));
+        }
+
+        let (share0, share1) = mpc::deal_triple(kind);
+        let (mine, theirs) = if request_contents.party == 0 {
+            (share0, share1)
+        } else {
+            (share1, share0)
+        };
+        state.pending.insert(
+            key,
+            PendingShare {
+                party: request_contents.party,
+                kind: request_contents.kind,
+                rows: request_contents.rows,
+                cols: request_contents.cols,
+                shares: TripleShares {
+                    a: theirs.a,
+                    b: theirs.b,
+                    c: theirs.c,
+                },
+                created: now,
+            },
+        );
+
+        let response = TripleShares {
+            a: mine.a,
+            b: mine.b,
+            c: mine.c,
+        };
+        Ok(Response::new(response))
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Get the args passed to the program
+    let args: Vec<String> = std::env::args().collect();
+
+    if args.len() != 2 {
+        println!("Usage: {} <port>", args[0]);
+        return Ok(());
+    }
+
+    let cert = std::fs::read_to_string("dealer.crt")?;
+    let key = std::fs::read_to_string("dealer.key")?;
+    let dealer_identity = Identity::from_pem(cert, key);
+
+    // Both the client and the server authenticate with a certificate of the CA
+    let ca_cert = std::fs::read_to_string("ca.crt")?;
+    let ca_cert = Certificate::from_pem(ca_cert);
+
+    // Fingerprint of the certificate the server authenticates with
+    let server_cert = std::fs::read_to_string(SERVER_CERTIFICATE)?;
+    let server_cert = rustls_pemfile::certs(&mut server_cert.as_bytes())?
+        .into_iter()
+        .next()
+        .ok_or_else(|| format!("{} holds no certificate", SERVER_CERTIFICATE))?;
+    let dealer = MyDealer::new(certificate_fingerprint(&server_cert));
+
+    let addr = "127.0.0.1:".to_owned() + &args[1];
+    let addr = addr.parse()?;
+
+    let tls = ServerTlsConfig::new()
+        .identity(dealer_identity)
+        .client_ca_root(ca_cert);
+
+    println!("Dealing triples on port {}...", args[1]);
+    Server::builder()
+        .tls_config(tls)?
+        .add_service(DealerServer::new(dealer))
+        .serve(addr)
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER: &[u8] = b"server";
+
+    fn request(kind: TripleKind, rows: u32, cols: u32) -> TripleRequest {
+        TripleRequest {
+            computation_id: "computation".to_string(),
+            party: 0,
+            sequence: 0,
+            rows,
+            cols,
+            kind: kind as i32,
+        }
+    }
+
+    #[test]
+    fn parties_are_bound_to_certificates() {
+        let dealer = MyDealer::new(SERVER.to_vec());
+        let mut state = State::default();
+        let now = Instant::now();
+
+        // The server is party 1 only, a client party 0 only
+        assert!(dealer.authorize(&mut state, "c", 0, SERVER, now).is_err());
+        assert!(dealer.authorize(&mut state, "c", 1, b"alice", now).is_err());
+        assert!(dealer.authorize(&mut state, "c", 2, b"alice", now).is_err());
+
+        assert!(dealer.authorize(&mut state, "c", 0, b"alice", now).is_ok());
+        assert!(dealer.authorize(&mut state, "c", 1, SERVER, now).is_ok());
+        assert!(dealer.authorize(&mut state, "c", 0, b"alice", now).is_ok());
+        // Another client cannot fetch the shares of the computation
+        assert!(dealer
+            .authorize(&mut state, "c", 0, b"mallory", now)
+            .is_err());
+        assert!(dealer
+            .authorize(&mut state, "d", 0, b"mallory", now)
+            .is_ok());
+    }
+
+    #[test]
+    fn idle_computations_expire() {
+        let dealer = MyDealer::new(SERVER.to_vec());
+        let mut state = State::default();
+        let start = Instant::now();
+        dealer
+            .authorize(&mut state, "c", 0, b"alice", start)
+            .unwrap();
+        state.pending.insert(
+            ("c".to_string(), 0),
+            PendingShare {
+                party: 0,
+                kind: TripleKind::Arithmetic as i32,
+                rows: 1,
+                cols: 0,
+                shares: TripleShares::default(),
+                created: start,
+            },
+        );
+
+        state.expire(start + PENDING_TIMEOUT / 2);
+        assert_eq!(state.pending.len(), 1);
+        state.expire(start + PENDING_TIMEOUT);
+        assert!(state.pending.is_empty());
+        assert!(state.computations.is_empty());
+    }
+
+    #[test]
+    fn triple_sizes_are_capped() {
+        let max = MAX_TRIPLE_WORDS as u32;
+        assert!(triple_kind(&request(TripleKind::Matrix, 100, 16)).is_ok());
+        assert!(triple_kind(&request(TripleKind::Arithmetic, max, 0)).is_ok());
+        assert!(triple_kind(&request(TripleKind::Boolean, max + 1, 0)).is_err());
+        assert!(triple_kind(&request(TripleKind::Matrix, 1 << 16, 1 << 16)).is_err());
+        assert!(triple_kind(&request(TripleKind::Matrix, u32::MAX, u32::MAX)).is_err());
+    }
+}
@@ -0,0 +1,559 @@
+// Yao garbled circuits with free-XOR and half-gates.
+//
+// The model owner (party 1) garbles and the data owner (party 0) evaluates, so
+// that the labels of the evaluator's inputs are the chosen-message OTs of the
+// OT extension. Every wire w has a zero label W0 and a one label W0 xor delta,
+// the lowest bit of delta being set: the lowest bit of a label is then a
+// permutation bit, and the lowest bits of the evaluator's label and of the
+// zero label are XOR shares of the value of the wire. XOR and NOT gates are
+// free, AND gates cost two ciphertexts. The gates are hashed with fixed-key AES.
+
+use crate::mpc::{random_u64s, MpcResult, Transport};
+use crate::ot::OtExtension;
+use aes::cipher::{BlockEncrypt, KeyInit};
+use aes::Aes128;
+
+pub type Label = u128;
+
+// Wire of a circuit, or a constant known to both parties
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Bit {
+    Constant(bool),
+    Wire(usize),
+}
+
+enum Gate {
+    Xor(usize, usize, usize),
+    And(usize, usize, usize),
+    Not(usize, usize),
+}
+
+// Boolean circuit. Integers are vectors of bits, least significant first.
+#[derive(Default)]
+pub struct Circuit {
+    wires: usize,
+    garbler_inputs: Vec<usize>,
+    evaluator_inputs: Vec<usize>,
+    gates: Vec<Gate>,
+    ands: usize,
+    // XOR-shared between the parties
+    outputs: Vec<Bit>,
+    // Revealed to the evaluator
+    revealed: Vec<Bit>,
+}
+
+pub fn to_bits(value: u64) -> Vec<bool> {
+    (0..64).map(|i| (value >> i) & 1 == 1).collect()
+}
+
+pub fn from_bits(bits: &[bool]) -> u64 {
+    bits.iter()
+        .enumerate()
+        .fold(0, |acc, (i, &b)| acc | ((b as u64) << i))
+}
+
+impl Circuit {
+    pub fn new() -> Self {
+        Circuit::default()
+    }
+
+    fn wire(&mut self) -> usize {
+        self.wires += 1;
+        self.wires - 1
+    }
+
+    pub fn garbler_input(&mut self, bits: usize) -> Vec<Bit> {
+        (0..bits)
+            .map(|_| {
+                let w = self.wire();
+                self.garbler_inputs.push(w);
+                Bit::Wire(w)
+            })
+            .collect()
+    }
+
+    pub fn evaluator_input(&mut self, bits: usize) -> Vec<Bit> {
+        (0..bits)
+            .map(|_| {
+                let w = self.wire();
+                self.evaluator_inputs.push(w);
+                Bit::Wire(w)
+            })
+            .collect()
+    }
+
+    pub fn constant(value: u64) -> Vec<Bit> {
+        to_bits(value).into_iter().map(Bit::Constant).collect()
+    }
+
+    // Gates with a constant input are simplified away
+    pub fn xor(&mut self, a: Bit, b: Bit) -> Bit {
+        match (a, b) {
+            (Bit::Constant(x), Bit::Constant(y)) => Bit::Constant(x ^ y),
+            (Bit::Constant(false), w) | (w, Bit::Constant(false)) => w,
+            (Bit::Constant(true), w) | (w, Bit::Constant(true)) => self.not(w),
+            (Bit::Wire(x), Bit::Wire(y)) if x == y => Bit::Constant(false),
+            (Bit::Wire(x), Bit::Wire(y)) => {
+                let w = self.wire();
+                self.gates.push(Gate::Xor(x, y, w));
+                Bit::Wire(w)
+            }
+        }
+    }
+
+    pub fn and(&mut self, a: Bit, b: Bit) -> Bit {
+        match (a, b) {
+            (Bit::Constant(x), Bit::Constant(y)) => Bit::Constant(x & y),
+            (Bit::Constant(false), _) | (_, Bit::Constant(false)) => Bit::Constant(false),
+            (Bit::Constant(true), w) | (w, Bit::Constant(true)) => w,
+            (Bit::Wire(x), Bit::Wire(y)) if x == y => a,
+            (Bit::Wire(x), Bit::Wire(y)) => {
+                let w = self.wire();
+                self.gates.push(Gate::And(x, y, w));
+                self.ands += 1;
+                Bit::Wire(w)
+            }
+        }
+    }
+
+    pub fn not(&mut self, a: Bit) -> Bit {
+        match a {
+            Bit::Constant(x) => Bit::Constant(!x),
+            Bit::Wire(x) => {
+                let w = self.wire();
+                self.gates.push(Gate::Not(x, w));
+                Bit::Wire(w)
+            }
+        }
+    }
+
+    // Ripple-carry adder modulo 2^n, one AND gate per bit: the carry out of bit
+    // i is c xor ((x xor c) and (y xor c))
+    fn add_with_carry(&mut self, x: &[Bit], y: &[Bit], carry: Bit) -> Vec<Bit> {
+        let mut carry = carry;
+        let mut sum = Vec::with_capacity(x.len());
+        for (i, (&a, &b)) in x.iter().zip(y.iter()).enumerate() {
+            let propagate = self.xor(a, b);
+            sum.push(self.xor(propagate, carry));
+            if i + 1 < x.len() {
+                let u = self.xor(a, carry);
+                let v = self.xor(b, carry);
+                let generate = self.and(u, v);
+                carry = self.xor(carry, generate);
+            }
+        }
+        sum
+    }
+
+    pub fn add(&mut self, x: &[Bit], y: &[Bit]) -> Vec<Bit> {
+        self.add_with_carry(x, y, Bit::Constant(false))
+    }
+
+    // x + not(y) + 1
+    pub fn sub(&mut self, x: &[Bit], y: &[Bit]) -> Vec<Bit> {
+        let negated: Vec<Bit> = y.iter().map(|&b| self.not(b)).collect();
+        self.add_with_carry(x, &negated, Bit::Constant(true))
+    }
+
+    // [x < 0] for a two's complement integer
+    pub fn negative(&self, x: &[Bit]) -> Bit {
+        x[x.len() - 1]
+    }
+
+    // [x < y] for two's complement integers whose difference does not overflow
+    pub fn less_than(&mut self, x: &[Bit], y: &[Bit]) -> Bit {
+        let difference = self.sub(x, y);
+        self.negative(&difference)
+    }
+
+    // x if s else y: y xor (s and (x xor y))
+    pub fn mux(&mut self, s: Bit, x: &[Bit], y: &[Bit]) -> Vec<Bit> {
+        x.iter()
+            .zip(y.iter())
+            .map(|(&a, &b)| {
+                let difference = self.xor(a, b);
+                let selected = self.and(s, difference);
+                self.xor(b, selected)
+            })
+            .collect()
+    }
+
+    // Value of a word arithmetically shared between the parties: the evaluator
+    // inputs its share x0 and the garbler its share x1
+    pub fn shared_word(&mut self) -> Vec<Bit> {
+        let x0 = self.evaluator_input(64);
+        let x1 = self.garbler_input(64);
+        self.add(&x0, &x1)
+    }
+
+    // Arithmetic sharing of a word: the garbler inputs a random mask r, which is
+    // its share, and value - r is revealed to the evaluator as its share
+    pub fn arithmetic_output(&mut self, value: &[Bit]) {
+        let mask = self.garbler_input(64);
+        let masked = self.sub(value, &mask);
+        self.revealed.extend(masked);
+    }
+
+    pub fn output(&mut self, bits: &[Bit]) {
+        self.outputs.extend(bits);
+    }
+}
+
+// Public key of the fixed-key AES permutation
+const HASH_KEY: [u8; 16] = *b"CRYPI half-gates";
+
+// Tweakable hash of the half-gates: H(x, i) = pi(K) xor K with K = 2x xor i,
+// pi being AES with a fixed key and 2x the doubling in GF(2^128)
+struct Hash(Aes128);
+
+impl Hash {
+    fn new() -> Self {
+        Hash(Aes128::new(&HASH_KEY.into()))
+    }
+
+    fn hash(&self, label: Label, tweak: u64) -> Label {
+        let doubled = (label << 1) ^ if label >> 127 == 1 { 0x87 } else { 0 };
+        let k = doubled ^ tweak as u128;
+        let mut block = k.to_le_bytes().into();
+        self.0.encrypt_block(&mut block);
+        u128::from_le_bytes(block.into()) ^ k
+    }
+}
+
+fn random_labels(n: usize) -> Vec<Label> {
+    random_u64s(2 * n)
+        .chunks_exact(2)
+        .map(|w| w[0] as u128 | ((w[1] as u128) << 64))
+        .collect()
+}
+
+fn label_words(labels: impl Iterator<Item = Label>) -> Vec<u64> {
+    labels.flat_map(|l| [l as u64, (l >> 64) as u64]).collect()
+}
+
+fn select(bit: bool, label: Label) -> Label {
+    if bit {
+        label
+    } else {
+        0
+    }
+}
+
+fn lsb(label: Label) -> bool {
+    label & 1 == 1
+}
+
+// Zero labels of every wire and the two ciphertexts of every AND gate
+fn garble(circuit: &Circuit, delta: Label) -> (Vec<Label>, Vec<Label>) {
+    let mut zero = vec![0; circuit.wires];
+    let inputs: Vec<usize> = circuit
+        .garbler_inputs
+        .iter()
+        .chain(circuit.evaluator_inputs.iter())
+        .copied()
+        .collect();
+    for (&w, label) in inputs.iter().zip(random_labels(inputs.len())) {
+        zero[w] = label;
+    }
+
+    let hash = Hash::new();
+    let mut tables = Vec::with_capacity(2 * circuit.ands);
+    let mut index = 0u64;
+    for gate in circuit.gates.iter() {
+        match *gate {
+            Gate::Xor(a, b, out) => zero[out] = zero[a] ^ zero[b],
+            Gate::Not(a, out) => zero[out] = zero[a] ^ delta,
+            Gate::And(a, b, out) => {
+                let (a0, b0) = (zero[a], zero[b]);
+                let (pa, pb) = (lsb(a0), lsb(b0));
+                let (j, k) = (2 * index, 2 * index + 1);
+                index += 1;
+
+                // Garbler half gate
+                let (ha0, ha1) = (hash.hash(a0, j), hash.hash(a0 ^ delta, j));
+                let tg = ha0 ^ ha1 ^ select(pb, delta);
+                let wg = ha0 ^ select(pa, tg);
+                // Evaluator half gate
+                let (hb0, hb1) = (hash.hash(b0, k), hash.hash(b0 ^ delta, k));
+                let te = hb0 ^ hb1 ^ a0;
+                let we = hb0 ^ select(pb, te ^ a0);
+
+                zero[out] = wg ^ we;
+                tables.push(tg);
+                tables.push(te);
+            }
+        }
+    }
+    (zero, tables)
+}
+
+// Labels of every wire from the labels of the inputs
+fn evaluate(circuit: &Circuit, labels: &mut [Label], tables: &[Label]) {
+    let hash = Hash::new();
+    let mut index = 0usize;
+    for gate in circuit.gates.iter() {
+        match *gate {
+            Gate::Xor(a, b, out) => labels[out] = labels[a] ^ labels[b],
+            Gate::Not(a, out) => labels[out] = labels[a],
+            Gate::And(a, b, out) => {
+                let (la, lb) = (labels[a], labels[b]);
+                let (tg, te) = (tables[2 * index], tables[2 * index + 1]);
+                let (j, k) = (2 * index as u64, 2 * index as u64 + 1);
+                index += 1;
+
+                let wg = hash.hash(la, j) ^ select(lsb(la), tg);
+                let we = hash.hash(lb, k) ^ select(lsb(lb), te ^ la);
+                labels[out] = wg ^ we;
+            }
+        }
+    }
+}
+
+// Run the circuit on the input bits of this party, given in the order of its
+// input wires. Returns the XOR shares of the outputs and, for the evaluator,
+// the revealed outputs.
+pub async fn execute(
+    id: usize,
+    transport: &mut dyn Transport,
+    ot: &mut OtExtension,
+    circuit: &Circuit,
+    inputs: &[bool],
+) -> MpcResult<(Vec<bool>, Vec<bool>)> {
+    if id == 1 {
+        if inputs.len() != circuit.garbler_inputs.len() {
+            return Err("Unexpected number of garbler inputs".into());
+        }
+        let delta = random_labels(1)[0] | 1;
+        let (zero, tables) = garble(circuit, delta);
+
+        // The evaluator obliviously gets the labels of its inputs
+        let labels0 = label_words(circuit.evaluator_inputs.iter().map(|&w| zero[w]));
+        let labels1 = label_words(circuit.evaluator_inputs.iter().map(|&w| zero[w] ^ delta));
+        ot.send_chosen(transport, &labels0, &labels1, 2).await?;
+
+        // Labels of our inputs, garbled tables and decoding bits of the revealed outputs
+        let mut message = label_words(
+            circuit
+                .garbler_inputs
+                .iter()
+                .zip(inputs.iter())
+                .map(|(&w, &bit)| zero[w] ^ select(bit, delta)),
+        );
+        message.extend(label_words(tables.into_iter()));
+        message.extend(circuit.revealed.iter().map(|bit| match *bit {
+            Bit::Constant(_) => 0,
+            Bit::Wire(w) => lsb(zero[w]) as u64,
+        }));
+        transport.exchange(message).await?;
+
+        let shares = circuit
+            .outputs
+            .iter()
+            .map(|bit| match *bit {
+                Bit::Constant(value) => value,
+                Bit::Wire(w) => lsb(zero[w]),
+            })
+            .collect();
+        Ok((shares, Vec::new()))
+    } else {
+        if inputs.len() != circuit.evaluator_inputs.len() {
+            return Err("Unexpected number of evaluator inputs".into());
+        }
+        let received = ot.receive_chosen(transport, inputs, 2).await?;
+        let message = transport.exchange(Vec::new()).await?;
+        let garbler_words = 2 * circuit.garbler_inputs.len();
+        let table_words = 4 * circuit.ands;
+        if message.len() != garbler_words + table_words + circuit.revealed.len() {
+            return Err("Unexpected size of the garbled circuit".into());
+        }
+
+        let to_labels = |words: &[u64]| -> Vec<Label> {
+            words
+                .chunks_exact(2)
+                .map(|w| w[0] as u128 | ((w[1] as u128) << 64))
+                .collect()
+        };
+        let mut labels = vec![0; circuit.wires];
+        for (&w, label) in circuit.evaluator_inputs.iter().zip(to_labels(&received)) {
+            labels[w] = label;
+        }
+        for (&w, label) in circuit
+            .garbler_inputs
+            .iter()
+            .zip(to_labels(&message[..garbler_words]))
+        {
+            labels[w] = label;
+        }
+        let tables = to_labels(&message[garbler_words..garbler_words + table_words]);
+        evaluate(circuit, &mut labels, &tables);
+
+        let shares = circuit
+            .outputs
+            .iter()
+            .map(|bit| match *bit {
+                Bit::Constant(_) => false,
+                Bit::Wire(w) => lsb(labels[w]),
+            })
+            .collect();
+        let decoding = &message[garbler_words + table_words..];
+        let revealed = circuit
+            .revealed
+            .iter()
+            .zip(decoding.iter())
+            .map(|(bit, &d)| match *bit {
+                Bit::Constant(value) => value,
+                Bit::Wire(w) => lsb(labels[w]) ^ (d == 1),
+            })
+            .collect();
+        Ok((shares, revealed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mpc::testing::{parties, transports};
+    use crate::mpc::{add, decode, encode, share, Comparisons};
+
+    // Evaluator labels of the input wires encoding the given bits
+    fn input_labels(circuit: &Circuit, zero: &[Label], delta: Label, bits: &[bool]) -> Vec<Label> {
+        let mut labels = vec![0; circuit.wires];
+        let inputs = circuit
+            .garbler_inputs
+            .iter()
+            .chain(circuit.evaluator_inputs.iter());
+        for (&w, &bit) in inputs.zip(bits.iter()) {
+            labels[w] = zero[w] ^ select(bit, delta);
+        }
+        labels
+    }
+
+    #[test]
+    fn half_gates_and() {
+        let mut circuit = Circuit::new();
+        let a = circuit.garbler_input(1)[0];
+        let b = circuit.evaluator_input(1)[0];
+        let Bit::Wire(out) = circuit.and(a, b) else {
+            panic!("The AND of two wires is a wire");
+        };
+
+        for (x, y) in [(false, false), (false, true), (true, false), (true, true)] {
+            let delta = random_labels(1)[0] | 1;
+            let (zero, tables) = garble(&circuit, delta);
+            let mut labels = input_labels(&circuit, &zero, delta, &[x, y]);
+            evaluate(&circuit, &mut labels, &tables);
+            assert_eq!(labels[out], zero[out] ^ select(x && y, delta));
+        }
+    }
+
+    #[test]
+    fn garbled_adder() {
+        let mut circuit = Circuit::new();
+        let x = circuit.garbler_input(64);
+        let y = circuit.evaluator_input(64);
+        let sum = circuit.add(&x, &y);
+        let wires: Vec<usize> = sum
+            .iter()
+            .map(|bit| match *bit {
+                Bit::Wire(w) => w,
+                Bit::Constant(_) => panic!("The sum of two inputs is not constant"),
+            })
+            .collect();
+
+        let (x, y) = (u64::MAX - 5, 1234);
+        let delta = random_labels(1)[0] | 1;
+        let (zero, tables) = garble(&circuit, delta);
+        let mut bits = to_bits(x);
+        bits.extend(to_bits(y));
+        let mut labels = input_labels(&circuit, &zero, delta, &bits);
+        evaluate(&circuit, &mut labels, &tables);
+        let sum: Vec<bool> = wires
+            .iter()
+            .map(|&w| lsb(labels[w]) ^ lsb(zero[w]))
+            .collect();
+        assert_eq!(from_bits(&sum), x.wrapping_add(y));
+    }
+
+    #[tokio::test]
+    async fn compare_with_ots() {
+        let values: [(i64, i64); 5] =
+            [(-3, 2), (2, -3), (7, 7), (-100, -99), (1 << 40, -(1 << 40))];
+        let mut circuit = Circuit::new();
+        let mut garbler_inputs = Vec::new();
+        let mut evaluator_inputs = Vec::new();
+        for &(x, y) in values.iter() {
+            let x_bits = circuit.garbler_input(64);
+            let y_bits = circuit.evaluator_input(64);
+            let less = circuit.less_than(&x_bits, &y_bits);
+            circuit.output(&[less]);
+            garbler_inputs.extend(to_bits(x as u64));
+            evaluator_inputs.extend(to_bits(y as u64));
+        }
+
+        let (mut transport0, mut transport1) = transports();
+        let (ot0, ot1) = tokio::join!(
+            OtExtension::setup(0, &mut transport0),
+            OtExtension::setup(1, &mut transport1)
+        );
+        let (mut ot0, mut ot1) = (ot0.unwrap(), ot1.unwrap());
+        let (evaluator, garbler) = tokio::join!(
+            execute(0, &mut transport0, &mut ot0, &circuit, &evaluator_inputs),
+            execute(1, &mut transport1, &mut ot1, &circuit, &garbler_inputs)
+        );
+        let (shares0, _) = evaluator.unwrap();
+        let (shares1, _) = garbler.unwrap();
+
+        for (i, &(x, y)) in values.iter().enumerate() {
+            assert_eq!(shares0[i] ^ shares1[i], x < y);
+        }
+    }
+
+    #[tokio::test]
+    async fn garbled_clamp_and_step() {
+        let (mut party0, mut party1) = parties();
+        party0.comparisons = Comparisons::GarbledCircuit;
+        party1.comparisons = Comparisons::GarbledCircuit;
+        let values = [-3.0, -1.0, -0.25, 0.0, 0.75, 1.0, 12.5];
+        let x: Vec<u64> = values.iter().map(|v| encode(*v)).collect();
+        let (x0, x1) = share(&x);
+
+        let (c0, c1) = tokio::join!(party0.clamp(&x0, -1.0, 1.0), party1.clamp(&x1, -1.0, 1.0));
+        let clamped = add(&c0.unwrap(), &c1.unwrap());
+        for (c, v) in clamped.iter().zip(values) {
+            assert_eq!(decode(*c), v.clamp(-1.0, 1.0));
+        }
+
+        let (s0, s1) = tokio::join!(party0.step(&x0), party1.step(&x1));
+        let step = add(&s0.unwrap(), &s1.unwrap());
+        for (s, v) in step.iter().zip(values) {
+            assert_eq!(*s, (v >= 0.0) as u64);
+        }
+    }
+
+    #[tokio::test]
+    async fn garbled_relu_and_min_max() {
+        let (mut party0, mut party1) = parties();
+        party0.comparisons = Comparisons::GarbledCircuit;
+        party1.comparisons = Comparisons::GarbledCircuit;
+        let values = [-3.0, -0.5, 0.0, 0.75, 12.5];
+        let others = [2.0, -0.25, 0.0, 0.5, -12.5];
+        let x: Vec<u64> = values.iter().map(|v| encode(*v)).collect();
+        let y: Vec<u64> = others.iter().map(|v| encode(*v)).collect();
+        let (x0, x1) = share(&x);
+        let (y0, y1) = share(&y);
+
+        let (r0, r1) = tokio::join!(party0.relu(&x0), party1.relu(&x1));
+        let relu = add(&r0.unwrap(), &r1.unwrap());
+        for (r, v) in relu.iter().zip(values) {
+            assert_eq!(decode(*r), v.max(0.0));
+        }
+
+        let (m0, m1) = tokio::join!(party0.min_max(&x0, &y0), party1.min_max(&x1, &y1));
+        let ((min0, max0), (min1, max1)) = (m0.unwrap(), m1.unwrap());
+        let (min, max) = (add(&min0, &min1), add(&max0, &max1));
+        for i in 0..values.len() {
+            assert_eq!(decode(min[i]), values[i].min(others[i]));
+            assert_eq!(decode(max[i]), values[i].max(others[i]));
+        }
+    }
+}
@@ -0,0 +1,338 @@
+// Paillier additively homomorphic encryption.
+//
+// With n = p * q and g = n + 1, a message m in Z_n is encrypted as
+// c = (1 + m * n) * r^n mod n^2. Multiplying ciphertexts adds the messages and
+// raising a ciphertext to a power multiplies its message by that power, which
+// is enough to compute X . theta on an encrypted X with a plaintext theta.
+// Real numbers use the fixed-point encoding of the secret sharing engine,
+// negative numbers being represented by n - |m|.
+//
+// An exact logit would let the client read the coefficients off rows such as
+// unit vectors, so dot adds a uniform noise of up to 2^-8 to it, which moves the
+// probabilities by less than 1e-3. What remains is the leakage of the
+// predictions themselves: averaging the noisy logits of repeated queries still
+// estimates the coefficients, and a client encrypting values outside the
+// fixed-point range can scale them above the noise. The server cannot check
+// encrypted rows, so it must trust the clients of this mode not to probe.
+
+use crate::mpc::{encode, random_u64s, FRAC_BITS};
+use num_bigint::BigUint;
+use num_integer::Integer;
+use num_traits::{One, Zero};
+
+// Odd primes used to discard most candidates before running Miller-Rabin
+const SMALL_PRIMES: [u32; 24] = [
+    3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+];
+const MILLER_RABIN_ROUNDS: usize = 40;
+
+// Size of the modulus of the client's keys, and smallest one accepted by the server
+pub const KEY_BITS: usize = 2048;
+pub const MIN_KEY_BITS: u64 = 1024;
+// Bound of the noise of the logits, with 2 * FRAC_BITS fractional bits
+const LOGIT_NOISE_BITS: u32 = 2 * FRAC_BITS - 8;
+
+fn random_biguint(bits: usize) -> BigUint {
+    let words = random_u64s(bits.div_ceil(64));
+    let mut value = BigUint::from_slice(
+        &words
+            .iter()
+            .flat_map(|w| [*w as u32, (*w >> 32) as u32])
+            .collect::<Vec<u32>>(),
+    );
+    let excess = words.len() * 64 - bits;
+    value >>= excess;
+    value
+}
+
+// Uniform random value in [1, bound)
+fn random_below(bound: &BigUint) -> BigUint {
+    loop {
+        let value = random_biguint(bound.bits() as usize);
+        if !value.is_zero() && &value < bound {
+            return value;
+        }
+    }
+}
+
+fn is_probable_prime(candidate: &BigUint) -> bool {
+    for &p in SMALL_PRIMES.iter() {
+        if (candidate % p).is_zero() {
+            return candidate == &BigUint::from(p);
+        }
+    }
+
+    let one = BigUint::one();
+    let minus_one = candidate - &one;
+    let s = minus_one.trailing_zeros().unwrap_or(0);
+    let d = &minus_one >> s;
+
+    'witness: for _ in 0..MILLER_RABIN_ROUNDS {
+        let a = random_below(&minus_one);
+        if a <= one {
+            continue;
+        }
+        let mut x = a.modpow(&d, candidate);
+        if x == one || x == minus_one {
+            continue;
+        }
+        for _ in 1..s {
+            x = x.modpow(&BigUint::from(2u32), candidate);
+            if x == minus_one {
+                continue 'witness;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+// Random prime of exactly `bits` bits, the two top bits being set so that the
+// product of two such primes has exactly 2 * bits bits
+fn random_prime(bits: usize) -> BigUint {
+    loop {
+        let mut candidate = random_biguint(bits);
+        candidate.set_bit(bits as u64 - 1, true);
+        candidate.set_bit(bits as u64 - 2, true);
+        candidate.set_bit(0, true);
+        if is_probable_prime(&candidate) {
+            return candidate;
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PublicKey {
+    pub n: BigUint,
+    n_squared: BigUint,
+}
+
+#[derive(Debug, Clone)]
+pub struct PrivateKey {
+    pub public: PublicKey,
+    lambda: BigUint,
+    mu: BigUint,
+}
+
+// Generate a key pair whose modulus n has `bits` bits
+pub fn keygen(bits: usize) -> PrivateKey {
+    loop {
+        let p = random_prime(bits / 2);
+        let q = random_prime(bits / 2);
+        if p == q {
+            continue;
+        }
+        let n = &p * &q;
+        let lambda = (&p - 1u32).lcm(&(&q - 1u32));
+        // With g = n + 1, L(g^lambda mod n^2) = lambda mod n
+        if let Some(mu) = (&lambda % &n).modinv(&n) {
+            return PrivateKey {
+                public: PublicKey::new(n),
+                lambda,
+                mu,
+            };
+        }
+    }
+}
+
+// Whether a modulus received from a client can be the product of two distinct
+// odd primes: the server cannot check the factorisation itself, but an even
+// modulus or a square one breaks the encryption
+pub fn is_valid_modulus(n: &BigUint) -> bool {
+    if n.bits() < MIN_KEY_BITS || n.is_even() {
+        return false;
+    }
+    let root = n.sqrt();
+    &(&root * &root) != n
+}
+
+impl PublicKey {
+    pub fn new(n: BigUint) -> Self {
+        let n_squared = &n * &n;
+        PublicKey { n, n_squared }
+    }
+
+    // Fixed-point value as an element of Z_n
+    pub fn encode(&self, value: f64) -> BigUint {
+        let encoded = encode(value) as i64;
+        if encoded < 0 {
+            &self.n - BigUint::from(encoded.unsigned_abs())
+        } else {
+            BigUint::from(encoded as u64)
+        }
+    }
+
+    // Fresh encryption of zero, r^n mod n^2
+    fn random_mask(&self) -> BigUint {
+        random_below(&self.n).modpow(&self.n, &self.n_squared)
+    }
+
+    pub fn encrypt(&self, message: &BigUint) -> BigUint {
+        let g_m = (BigUint::one() + message * &self.n) % &self.n_squared;
+        (g_m * self.random_mask()) % &self.n_squared
+    }
+
+    // Enc(m1 + m2)
+    pub fn add(&self, c1: &BigUint, c2: &BigUint) -> BigUint {
+        (c1 * c2) % &self.n_squared
+    }
+
+    // Enc(k * m) for a signed integer k, None if the ciphertext is not invertible
+    // modulo n^2, which no honest encryption is
+    pub fn mul_scalar(&self, ciphertext: &BigUint, scalar: i64) -> Option<BigUint> {
+        let power = ciphertext.modpow(&BigUint::from(scalar.unsigned_abs()), &self.n_squared);
+        if scalar < 0 {
+            power.modinv(&self.n_squared)
+        } else {
+            Some(power)
+        }
+    }
+
+    // Multiply by a fresh encryption of zero: the result can no longer be linked
+    // to the ciphertexts and scalars it was computed from
+    pub fn rerandomize(&self, ciphertext: &BigUint) -> BigUint {
+        (ciphertext * self.random_mask()) % &self.n_squared
+    }
+
+    // Enc(X . theta + noise) for one row of encrypted fixed-point features, with
+    // theta[0] as the intercept when theta has one more coefficient than the row.
+    // The result has 2 * FRAC_BITS fractional bits.
+    pub fn dot(&self, row: &[BigUint], theta: &[f64]) -> Option<BigUint> {
+        let (intercept, weights) = if theta.len() == row.len() + 1 {
+            (theta[0], &theta[1..])
+        } else {
+            (0.0, theta)
+        };
+
+        // Enc(intercept + noise) with the scale of the products
+        let bound = 1u64 << LOGIT_NOISE_BITS;
+        let noise = (random_u64s(1)[0] % (2 * bound + 1)) as i64 - bound as i64;
+        let noise = if noise < 0 {
+            &self.n - BigUint::from(noise.unsigned_abs())
+        } else {
+            BigUint::from(noise as u64)
+        };
+        let scaled = (self.encode(intercept) << FRAC_BITS) + noise;
+        let mut total = self.encrypt(&(scaled % &self.n));
+        for (ciphertext, weight) in row.iter().zip(weights.iter()) {
+            let product = self.mul_scalar(ciphertext, encode(*weight) as i64)?;
+            total = self.add(&total, &product);
+        }
+        Some(self.rerandomize(&total))
+    }
+}
+
+impl PrivateKey {
+    pub fn decrypt(&self, ciphertext: &BigUint) -> BigUint {
+        let n = &self.public.n;
+        let x = ciphertext.modpow(&self.lambda, &self.public.n_squared);
+        let l = (x - 1u32) / n;
+        (l * &self.mu) % n
+    }
+
+    // Decrypt a value with `frac_bits` fractional bits
+    pub fn decrypt_fixed(&self, ciphertext: &BigUint, frac_bits: u32) -> f64 {
+        let n = &self.public.n;
+        let message = self.decrypt(ciphertext);
+        let scale = (1u64 << frac_bits) as f64;
+        if message > n >> 1 {
+            -to_f64(&(n - message)) / scale
+        } else {
+            to_f64(&message) / scale
+        }
+    }
+}
+
+fn to_f64(value: &BigUint) -> f64 {
+    value
+        .to_u64_digits()
+        .iter()
+        .rev()
+        .fold(0.0, |acc, &d| acc * 18446744073709551616.0 + d as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Small keys keep the tests fast, the arithmetic does not depend on the size
+    const TEST_KEY_BITS: usize = 512;
+
+    #[test]
+    fn primes() {
+        assert!(is_probable_prime(&BigUint::from(1_000_000_007u32)));
+        assert!(!is_probable_prime(&BigUint::from(
+            1_000_000_007u64 * 998_244_353
+        )));
+        assert_eq!(random_prime(128).bits(), 128);
+    }
+
+    #[test]
+    fn encryption_round_trip() {
+        let key = keygen(TEST_KEY_BITS);
+        let public = &key.public;
+        assert_eq!(public.n.bits(), TEST_KEY_BITS as u64);
+
+        for value in [0.0, 1.0, -1.0, 3.25, -1234.5] {
+            let ciphertext = public.encrypt(&public.encode(value));
+            assert_eq!(key.decrypt_fixed(&ciphertext, FRAC_BITS), value);
+            // Fresh randomness, same message
+            let again = public.rerandomize(&ciphertext);
+            assert_ne!(again, ciphertext);
+            assert_eq!(key.decrypt_fixed(&again, FRAC_BITS), value);
+        }
+    }
+
+    #[test]
+    fn homomorphic_operations() {
+        let key = keygen(TEST_KEY_BITS);
+        let public = &key.public;
+        let c1 = public.encrypt(&public.encode(2.5));
+        let c2 = public.encrypt(&public.encode(-4.0));
+        assert_eq!(key.decrypt_fixed(&public.add(&c1, &c2), FRAC_BITS), -1.5);
+
+        let product = public.mul_scalar(&c1, -3).unwrap();
+        assert_eq!(key.decrypt_fixed(&product, FRAC_BITS), -7.5);
+    }
+
+    #[test]
+    fn homomorphic_dot_product() {
+        let key = keygen(TEST_KEY_BITS);
+        let public = &key.public;
+        let row: Vec<BigUint> = [1.5, -2.0, 0.25]
+            .iter()
+            .map(|v| public.encrypt(&public.encode(*v)))
+            .collect();
+
+        // Without and with an intercept, up to the noise
+        let noise = 2f64.powi(-8);
+        let dot = public.dot(&row, &[2.0, 0.5, -4.0]).unwrap();
+        assert!((key.decrypt_fixed(&dot, 2 * FRAC_BITS) - 1.0).abs() <= noise);
+        let dot = public.dot(&row, &[-0.5, 2.0, 0.5, -4.0]).unwrap();
+        assert!((key.decrypt_fixed(&dot, 2 * FRAC_BITS) - 0.5).abs() <= noise);
+    }
+
+    #[test]
+    fn logits_are_noisy() {
+        let key = keygen(TEST_KEY_BITS);
+        let public = &key.public;
+        // A unit vector would give the coefficient away without the noise
+        let row = [public.encrypt(&public.encode(1.0))];
+        let logits: Vec<f64> = (0..8)
+            .map(|_| key.decrypt_fixed(&public.dot(&row, &[0.75]).unwrap(), 2 * FRAC_BITS))
+            .collect();
+        assert!(logits.iter().all(|l| (l - 0.75).abs() <= 2f64.powi(-8)));
+        assert!(logits.iter().any(|l| *l != 0.75));
+    }
+
+    #[test]
+    fn client_moduli() {
+        let key = keygen(TEST_KEY_BITS);
+        assert!(!is_valid_modulus(&key.public.n));
+        let key = keygen(MIN_KEY_BITS as usize);
+        assert!(is_valid_modulus(&key.public.n));
+        assert!(!is_valid_modulus(&(&key.public.n + 1u32)));
+        let p = random_prime(MIN_KEY_BITS as usize / 2 + 1);
+        assert!(!is_valid_modulus(&(&p * &p)));
+    }
+}
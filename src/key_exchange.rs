@@ -0,0 +1,258 @@
+// Keys of the integrity checks of a session. The client and the server agree on a
+// session key with ephemeral X25519 keys when the session opens, bound to the
+// certificate of the client, and on a new one, chained to the previous one, at
+// every rotation. Every upload is authenticated with its own key, derived from the
+// session key, by an HMAC of the transcript of its chunks.
+
+use crate::file::UploadChunk;
+use hmac::{Hmac, Mac};
+use ring::agreement::{self, EphemeralPrivateKey, UnparsedPublicKey, X25519};
+use ring::error::Unspecified;
+use ring::rand::SystemRandom;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Age after which the server refuses new uploads under a session key
+pub const KEY_LIFETIME: Duration = Duration::from_secs(10 * 60);
+
+fn hmac(key: &[u8], parts: &[&[u8]]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC can take key of any size");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().to_vec()
+}
+
+pub fn certificate_fingerprint(der: &[u8]) -> Vec<u8> {
+    Sha256::digest(der).to_vec()
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionKey {
+    secret: Vec<u8>,
+    created: Instant,
+}
+
+impl SessionKey {
+    // Key of a session saved by another process. Its age is unknown, it is only
+    // good for a rotation.
+    pub fn resume(secret: Vec<u8>) -> SessionKey {
+        SessionKey {
+            secret,
+            created: Instant::now(),
+        }
+    }
+
+    pub fn secret(&self) -> &[u8] {
+        &self.secret
+    }
+
+    pub fn age(&self) -> Duration {
+        self.created.elapsed()
+    }
+
+    // HMAC key of the upload `upload_id`
+    pub fn transfer_key(&self, upload_id: &str) -> Vec<u8> {
+        hmac(&self.secret, &[b"crypi upload", upload_id.as_bytes()])
+    }
+}
+
+// One side of a key agreement
+pub struct KeyAgreement {
+    private_key: EphemeralPrivateKey,
+    pub public_key: Vec<u8>,
+    client: bool,
+}
+
+impl KeyAgreement {
+    fn new(client: bool) -> Result<Self, Box<dyn Error>> {
+        let private_key = EphemeralPrivateKey::generate(&X25519, &SystemRandom::new())
+            .map_err(|_| "Failed to generate the X25519 key")?;
+        let public_key = private_key
+            .compute_public_key()
+            .map_err(|_| "Failed to compute the X25519 public key")?
+            .as_ref()
+            .to_vec();
+        Ok(KeyAgreement {
+            private_key,
+            public_key,
+            client,
+        })
+    }
+
+    pub fn client() -> Result<Self, Box<dyn Error>> {
+        KeyAgreement::new(true)
+    }
+
+    pub fn server() -> Result<Self, Box<dyn Error>> {
+        KeyAgreement::new(false)
+    }
+
+    // HKDF of the shared secret, salted with the previous session key on a
+    // rotation, over both public keys and the fingerprint of the client certificate
+    pub fn agree(
+        self,
+        peer_public_key: &[u8],
+        client_certificate: &[u8],
+        previous: Option<&SessionKey>,
+    ) -> Result<SessionKey, Box<dyn Error>> {
+        let (client_key, server_key) = if self.client {
+            (self.public_key.as_slice(), peer_public_key)
+        } else {
+            (peer_public_key, self.public_key.as_slice())
+        };
+        let info: Vec<u8> = [b"crypi session".as_slice(), client_key, server_key]
+            .concat()
+            .into_iter()
+            .chain(certificate_fingerprint(client_certificate))
+            .collect();
+        let salt = previous.map(|key| key.secret.clone()).unwrap_or_default();
+
+        let peer = UnparsedPublicKey::new(&X25519, peer_public_key);
+        let key = agreement::agree_ephemeral(self.private_key, &peer, Unspecified, |shared| {
+            let prk = hmac(&salt, &[shared]);
+            Ok(SessionKey {
+                secret: hmac(&prk, &[&info, &[1]]),
+                created: Instant::now(),
+            })
+        });
+        Ok(key.map_err(|_| "Invalid X25519 public key")?)
+    }
+}
+
+// Running hash of the metadata of an upload and of the offset and the hash of
+// every non-empty chunk, in order
+#[derive(Debug, Clone)]
+pub struct Transcript {
+    hasher: Sha256,
+}
+
+impl Transcript {
+    // Metadata from any chunk of the upload
+    pub fn new(chunk: &UploadChunk) -> Self {
+        let mut hasher = Sha256::new();
+        for field in [chunk.upload_id.as_bytes(), chunk.filename.as_bytes()] {
+            hasher.update((field.len() as u64).to_le_bytes());
+            hasher.update(field);
+        }
+        hasher.update(chunk.size.to_le_bytes());
+        hasher.update([chunk.train as u8, chunk.coefs as u8]);
+        Transcript { hasher }
+    }
+
+    pub fn add(&mut self, chunk: &UploadChunk) {
+        if !chunk.content.is_empty() {
+            self.hasher.update(chunk.offset.to_le_bytes());
+            self.hasher.update(&chunk.hash);
+        }
+    }
+
+    pub fn mac(&self, transfer_key: &[u8]) -> Vec<u8> {
+        hmac(transfer_key, &[&self.hasher.clone().finalize()])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CERTIFICATE: &[u8] = b"client certificate";
+
+    fn agree(previous: Option<&SessionKey>) -> (SessionKey, SessionKey) {
+        let client = KeyAgreement::client().unwrap();
+        let server = KeyAgreement::server().unwrap();
+        let server_public_key = server.public_key.clone();
+        let server_key = server
+            .agree(&client.public_key, CERTIFICATE, previous)
+            .unwrap();
+        let client_key = client
+            .agree(&server_public_key, CERTIFICATE, previous)
+            .unwrap();
+        (client_key, server_key)
+    }
+
+    fn chunk(offset: u64, content: &[u8]) -> UploadChunk {
+        UploadChunk {
+            upload_id: "upload".to_string(),
+            filename: "train.csv".to_string(),
+            train: true,
+            size: 8,
+            offset,
+            content: content.to_vec(),
+            hash: Sha256::digest(content).to_vec(),
+            ..Default::default()
+        }
+    }
+
+    fn transcript(chunks: &[UploadChunk]) -> Transcript {
+        let mut transcript = Transcript::new(&chunks[0]);
+        for chunk in chunks {
+            transcript.add(chunk);
+        }
+        transcript
+    }
+
+    #[test]
+    fn both_sides_agree() {
+        let (client_key, server_key) = agree(None);
+        assert_eq!(client_key.secret(), server_key.secret());
+        assert_eq!(client_key.secret().len(), 32);
+    }
+
+    #[test]
+    fn key_is_bound_to_the_certificate() {
+        let client = KeyAgreement::client().unwrap();
+        let server = KeyAgreement::server().unwrap();
+        let server_public_key = server.public_key.clone();
+        let server_key = server
+            .agree(&client.public_key, b"other certificate", None)
+            .unwrap();
+        let client_key = client.agree(&server_public_key, CERTIFICATE, None).unwrap();
+        assert_ne!(client_key.secret(), server_key.secret());
+    }
+
+    #[test]
+    fn rotation_is_chained() {
+        let (previous, _) = agree(None);
+        let (client_key, server_key) = agree(Some(&previous));
+        assert_eq!(client_key.secret(), server_key.secret());
+        assert_ne!(client_key.secret(), previous.secret());
+    }
+
+    #[test]
+    fn invalid_public_key() {
+        let client = KeyAgreement::client().unwrap();
+        assert!(client.agree(&[0u8; 5], CERTIFICATE, None).is_err());
+    }
+
+    #[test]
+    fn transfer_keys_differ_by_upload() {
+        let (key, _) = agree(None);
+        assert_ne!(key.transfer_key("a"), key.transfer_key("b"));
+    }
+
+    #[test]
+    fn tampered_transcript_fails_the_mac() {
+        let (key, _) = agree(None);
+        let transfer_key = key.transfer_key("upload");
+        let chunks = [chunk(0, b"1,2\n"), chunk(4, b"3,4\n")];
+        let mac = transcript(&chunks).mac(&transfer_key);
+        assert_eq!(transcript(&chunks).mac(&transfer_key), mac);
+
+        // Modified, reordered, dropped chunks or metadata
+        let modified = [chunk(0, b"1,2\n"), chunk(4, b"3,5\n")];
+        assert_ne!(transcript(&modified).mac(&transfer_key), mac);
+        let reordered = [chunk(4, b"3,4\n"), chunk(0, b"1,2\n")];
+        assert_ne!(transcript(&reordered).mac(&transfer_key), mac);
+        assert_ne!(transcript(&chunks[..1]).mac(&transfer_key), mac);
+        let mut metadata = chunks.clone();
+        metadata[0].coefs = true;
+        assert_ne!(transcript(&metadata).mac(&transfer_key), mac);
+
+        // Another key
+        assert_ne!(transcript(&chunks).mac(&key.transfer_key("other")), mac);
+    }
+}
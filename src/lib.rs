@@ -0,0 +1,55 @@
+#![allow(non_snake_case)]
+// tonic::Status is the error type of the session helpers shared with the server
+#![allow(clippy::result_large_err)]
+
+// Client library of the server: a CrypiClient opens a session, uploads the
+// datasets and models, and launches the trainings and the predictions, the
+// private ones running their side of the secure computations locally.
+
+mod crypi_client;
+
+// Modules shared with the server and the dealer binaries, which are built on
+// this library. They are not part of its API, the modules only the server uses
+// are part of the server binary.
+#[doc(hidden)]
+pub mod ckks;
+#[doc(hidden)]
+pub mod csv_file;
+#[doc(hidden)]
+pub mod gc;
+#[doc(hidden)]
+pub mod he;
+#[doc(hidden)]
+pub mod key_exchange;
+#[doc(hidden)]
+pub mod metrics;
+#[doc(hidden)]
+pub mod mpc;
+#[doc(hidden)]
+pub mod normalize;
+#[doc(hidden)]
+pub mod ot;
+#[doc(hidden)]
+pub mod resample;
+#[doc(hidden)]
+pub mod schema;
+#[doc(hidden)]
+pub mod split;
+#[doc(hidden)]
+pub mod training;
+#[doc(hidden)]
+pub mod triples;
+
+pub mod file {
+    tonic::include_proto!("file");
+}
+
+pub use crypi_client::{
+    ClientConfig, ClientError, CrypiClient, Prediction, Protocol, TrainedModel, TrainingKey,
+    UploadKind, SESSION_HEADER,
+};
+pub use file::Scaling;
+pub use metrics::{ConfusionMatrix, CrossValidation, Fold, Metrics, ThresholdMetric};
+pub use resample::{Resampling, ResamplingMethod};
+pub use split::SplitOptions;
+pub use training::{ClassWeights, Hyperparameters, Multiclass, Optimizer};
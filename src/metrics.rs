@@ -0,0 +1,552 @@
+// Evaluation of a trained model on the test split of its training dataset. The
+// labels are predicted with the decision threshold of the model, 0.5 unless it was
+// tuned; the ranking metrics use the probabilities. A multiclass model predicts the
+// most probable class: its precision, recall, F1 and areas are the macro averages
+// of every class against the others, and its confusion matrix is empty.
+
+use crate::file;
+use crate::training;
+use ndarray::{Array1, Array2};
+use serde::{Deserialize, Serialize};
+
+// Default decision threshold on the probability of the positive class
+pub const THRESHOLD: f64 = 0.5;
+
+// Metric a tuned decision threshold maximizes
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ThresholdMetric {
+    F1,
+    // Recall + specificity - 1
+    Youden,
+    Accuracy,
+}
+
+fn default_threshold() -> f64 {
+    THRESHOLD
+}
+
+// Label of every row of the probabilities of the classes, one column per class:
+// the class 1 when its probability reaches the threshold if there are two classes,
+// the most probable class otherwise
+pub fn labels(probabilities: &Array2<f64>, threshold: f64) -> Vec<u32> {
+    probabilities
+        .rows()
+        .into_iter()
+        .map(|row| {
+            if row.len() == 2 {
+                return (row[1] >= threshold) as u32;
+            }
+            let mut best = 0;
+            for (k, &p) in row.iter().enumerate() {
+                if p > row[best] {
+                    best = k;
+                }
+            }
+            best as u32
+        })
+        .collect()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConfusionMatrix {
+    pub true_positives: u64,
+    pub false_positives: u64,
+    pub true_negatives: u64,
+    pub false_negatives: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Metrics {
+    pub accuracy: f64,
+    // 0 when the model predicts no positive
+    pub precision: f64,
+    // 0 when the test split has no positive
+    pub recall: f64,
+    pub f1: f64,
+    // Area under the ROC curve, undefined when the test split has a single class
+    pub roc_auc: Option<f64>,
+    // Average precision, undefined when the test split has no positive
+    pub pr_auc: Option<f64>,
+    pub log_loss: f64,
+    // Mean squared error of the probabilities
+    pub brier: f64,
+    pub confusion: ConfusionMatrix,
+    // Decision threshold of the labels
+    #[serde(default = "default_threshold")]
+    pub threshold: f64,
+}
+
+fn ratio(numerator: u64, denominator: u64) -> f64 {
+    if denominator == 0 {
+        0.0
+    } else {
+        numerator as f64 / denominator as f64
+    }
+}
+
+// Probabilities in decreasing order, with their labels
+fn ranked(probabilities: &Array1<f64>, y: &Array1<f64>) -> Vec<(f64, bool)> {
+    let mut ranked: Vec<(f64, bool)> = probabilities
+        .iter()
+        .zip(y.iter())
+        .map(|(&p, &label)| (p, label == 1.0))
+        .collect();
+    ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+    ranked
+}
+
+// Probability that a positive row ranks above a negative one, ties counting for
+// half
+fn roc_auc(ranked: &[(f64, bool)]) -> Option<f64> {
+    let positives = ranked.iter().filter(|(_, positive)| *positive).count();
+    let negatives = ranked.len() - positives;
+    if positives == 0 || negatives == 0 {
+        return None;
+    }
+    // Pairs a positive wins, from the negatives ranked below every tied group
+    let mut wins = 0.0;
+    let mut negatives_below = negatives as f64;
+    for group in ranked.chunk_by(|a, b| a.0 == b.0) {
+        let group_positives = group.iter().filter(|(_, positive)| *positive).count() as f64;
+        let group_negatives = group.len() as f64 - group_positives;
+        negatives_below -= group_negatives;
+        wins += group_positives * (negatives_below + group_negatives / 2.0);
+    }
+    Some(wins / (positives * negatives) as f64)
+}
+
+// Average precision: the precision at every threshold, weighted by the recall it
+// adds
+fn pr_auc(ranked: &[(f64, bool)]) -> Option<f64> {
+    let positives = ranked.iter().filter(|(_, positive)| *positive).count();
+    if positives == 0 {
+        return None;
+    }
+    let (mut true_positives, mut seen, mut area) = (0, 0, 0.0);
+    for group in ranked.chunk_by(|a, b| a.0 == b.0) {
+        let group_positives = group.iter().filter(|(_, positive)| *positive).count();
+        true_positives += group_positives;
+        seen += group.len();
+        area += group_positives as f64 / positives as f64 * true_positives as f64 / seen as f64;
+    }
+    Some(area)
+}
+
+// Threshold maximizing `metric` on the probabilities of the positive class
+// against the labels y, among the probabilities themselves. Ties go to the
+// threshold closest to 0.5.
+pub fn tune_threshold(
+    probabilities: &Array1<f64>,
+    y: &Array1<f64>,
+    metric: ThresholdMetric,
+) -> f64 {
+    let ranked = ranked(probabilities, y);
+    let positives = ranked.iter().filter(|(_, positive)| *positive).count() as f64;
+    let negatives = ranked.len() as f64 - positives;
+    let score = |tp: f64, fp: f64| {
+        let (fn_, tn) = (positives - tp, negatives - fp);
+        match metric {
+            ThresholdMetric::F1 if tp > 0.0 => 2.0 * tp / (2.0 * tp + fp + fn_),
+            ThresholdMetric::F1 => 0.0,
+            ThresholdMetric::Youden => {
+                let rate = |a: f64, b: f64| if a + b > 0.0 { a / (a + b) } else { 0.0 };
+                rate(tp, fn_) + rate(tn, fp) - 1.0
+            }
+            ThresholdMetric::Accuracy => (tp + tn) / (positives + negatives),
+        }
+    };
+
+    let mut best: Option<(f64, f64)> = None;
+    let (mut tp, mut fp) = (0.0, 0.0);
+    // Lowering the threshold to every distinct probability in turn
+    for group in ranked.chunk_by(|a, b| a.0 == b.0) {
+        let group_positives = group.iter().filter(|(_, positive)| *positive).count() as f64;
+        tp += group_positives;
+        fp += group.len() as f64 - group_positives;
+        let (threshold, value) = (group[0].0, score(tp, fp));
+        let better = match best {
+            None => true,
+            Some((best_threshold, best_value)) => {
+                value > best_value
+                    || (value == best_value
+                        && (threshold - THRESHOLD).abs() < (best_threshold - THRESHOLD).abs())
+            }
+        };
+        if better {
+            best = Some((threshold, value));
+        }
+    }
+    best.map(|(threshold, _)| threshold).unwrap_or(THRESHOLD)
+}
+
+// Mean of the values that are defined, if any
+fn defined_mean(values: impl Iterator<Item = Option<f64>>) -> Option<f64> {
+    let values: Vec<f64> = values.flatten().collect();
+    if values.is_empty() {
+        return None;
+    }
+    Some(values.iter().sum::<f64>() / values.len() as f64)
+}
+
+impl Metrics {
+    // Metrics of the probabilities of the positive class against the labels y, the
+    // labels being predicted with `threshold`
+    pub fn evaluate(probabilities: &Array1<f64>, y: &Array1<f64>, threshold: f64) -> Metrics {
+        let mut confusion = ConfusionMatrix::default();
+        for (&p, &label) in probabilities.iter().zip(y.iter()) {
+            match (p >= threshold, label == 1.0) {
+                (true, true) => confusion.true_positives += 1,
+                (true, false) => confusion.false_positives += 1,
+                (false, false) => confusion.true_negatives += 1,
+                (false, true) => confusion.false_negatives += 1,
+            }
+        }
+        let c = confusion;
+        let precision = ratio(c.true_positives, c.true_positives + c.false_positives);
+        let recall = ratio(c.true_positives, c.true_positives + c.false_negatives);
+        let f1 = if precision + recall > 0.0 {
+            2.0 * precision * recall / (precision + recall)
+        } else {
+            0.0
+        };
+        let brier = if y.is_empty() {
+            0.0
+        } else {
+            (probabilities - y).mapv(|d| d * d).sum() / y.len() as f64
+        };
+        let ranked = ranked(probabilities, y);
+
+        Metrics {
+            accuracy: ratio(c.true_positives + c.true_negatives, y.len() as u64),
+            precision,
+            recall,
+            f1,
+            roc_auc: roc_auc(&ranked),
+            pr_auc: pr_auc(&ranked),
+            log_loss: training::log_loss(probabilities, y),
+            brier,
+            confusion,
+            threshold,
+        }
+    }
+
+    // Metrics of the probabilities of every class against the labels y, the
+    // indices of the classes. Two classes are evaluated as a binary model with
+    // `threshold`.
+    pub fn evaluate_classes(
+        probabilities: &Array2<f64>,
+        y: &Array1<f64>,
+        threshold: f64,
+    ) -> Metrics {
+        if probabilities.ncols() == 2 {
+            return Metrics::evaluate(&probabilities.column(1).to_owned(), y, threshold);
+        }
+
+        let predicted = labels(probabilities, threshold);
+        let classes = probabilities.ncols();
+        let mut correct = 0;
+        let (mut precision, mut recall, mut f1) = (0.0, 0.0, 0.0);
+        let (mut roc_areas, mut pr_areas) = (Vec::new(), Vec::new());
+        let mut brier = 0.0;
+        for k in 0..classes {
+            let actual = y.mapv(|label| (label == k as f64) as u32 as f64);
+            let (mut tp, mut fp, mut fn_) = (0, 0, 0);
+            for (&p, &a) in predicted.iter().zip(actual.iter()) {
+                match (p as usize == k, a == 1.0) {
+                    (true, true) => tp += 1,
+                    (true, false) => fp += 1,
+                    (false, true) => fn_ += 1,
+                    (false, false) => {}
+                }
+            }
+            correct += tp;
+            let (p, r) = (ratio(tp, tp + fp), ratio(tp, tp + fn_));
+            precision += p;
+            recall += r;
+            f1 += if p + r > 0.0 {
+                2.0 * p * r / (p + r)
+            } else {
+                0.0
+            };
+
+            let column = probabilities.column(k).to_owned();
+            brier += (&column - &actual).mapv(|d| d * d).sum();
+            let ranked = ranked(&column, &actual);
+            roc_areas.push(roc_auc(&ranked));
+            pr_areas.push(pr_auc(&ranked));
+        }
+        let n = classes.max(1) as f64;
+
+        Metrics {
+            accuracy: ratio(correct, y.len() as u64),
+            precision: precision / n,
+            recall: recall / n,
+            f1: f1 / n,
+            roc_auc: defined_mean(roc_areas.into_iter()),
+            pr_auc: defined_mean(pr_areas.into_iter()),
+            log_loss: training::class_log_loss(probabilities, y),
+            brier: if y.is_empty() {
+                0.0
+            } else {
+                brier / y.len() as f64
+            },
+            confusion: ConfusionMatrix::default(),
+            threshold,
+        }
+    }
+}
+
+// The undefined areas are sent as NaN
+impl From<&Metrics> for file::ResponseMetrics {
+    fn from(metrics: &Metrics) -> Self {
+        let c = metrics.confusion;
+        file::ResponseMetrics {
+            accuracy: metrics.accuracy,
+            precision: metrics.precision,
+            recall: metrics.recall,
+            f1: metrics.f1,
+            roc_auc: metrics.roc_auc.unwrap_or(f64::NAN),
+            pr_auc: metrics.pr_auc.unwrap_or(f64::NAN),
+            log_loss: metrics.log_loss,
+            brier: metrics.brier,
+            confusion: Some(file::ConfusionMatrix {
+                true_positives: c.true_positives,
+                false_positives: c.false_positives,
+                true_negatives: c.true_negatives,
+                false_negatives: c.false_negatives,
+            }),
+            threshold: metrics.threshold,
+        }
+    }
+}
+
+impl From<file::ResponseMetrics> for Metrics {
+    fn from(metrics: file::ResponseMetrics) -> Self {
+        let defined = |area: f64| Some(area).filter(|area| !area.is_nan());
+        let c = metrics.confusion.unwrap_or_default();
+        Metrics {
+            accuracy: metrics.accuracy,
+            precision: metrics.precision,
+            recall: metrics.recall,
+            f1: metrics.f1,
+            roc_auc: defined(metrics.roc_auc),
+            pr_auc: defined(metrics.pr_auc),
+            log_loss: metrics.log_loss,
+            brier: metrics.brier,
+            confusion: ConfusionMatrix {
+                true_positives: c.true_positives,
+                false_positives: c.false_positives,
+                true_negatives: c.true_negatives,
+                false_negatives: c.false_negatives,
+            },
+            threshold: metrics.threshold,
+        }
+    }
+}
+
+// Evaluation of the model trained on one fold of a cross-validation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fold {
+    pub metrics: Metrics,
+    // Epochs the training ran before it converged
+    pub epochs: usize,
+    pub train_rows: usize,
+    pub test_rows: usize,
+}
+
+// Metrics of every fold of a cross-validation, with their mean and their standard
+// deviation over the folds. The areas are averaged over the folds where they are
+// defined. The confusion matrix of the mean is the sum over the folds, the one of
+// the standard deviation is empty.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrossValidation {
+    pub folds: Vec<Fold>,
+    pub mean: Metrics,
+    pub std: Metrics,
+}
+
+// Mean and population standard deviation
+fn mean_std(values: &[f64]) -> (f64, f64) {
+    if values.is_empty() {
+        return (0.0, 0.0);
+    }
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+    (mean, variance.sqrt())
+}
+
+impl CrossValidation {
+    pub fn new(folds: Vec<Fold>) -> Self {
+        let stat = |metric: fn(&Metrics) -> f64| {
+            let values: Vec<f64> = folds.iter().map(|f| metric(&f.metrics)).collect();
+            mean_std(&values)
+        };
+        let area = |metric: fn(&Metrics) -> Option<f64>| {
+            let values: Vec<f64> = folds.iter().filter_map(|f| metric(&f.metrics)).collect();
+            if values.is_empty() {
+                return (None, None);
+            }
+            let (mean, std) = mean_std(&values);
+            (Some(mean), Some(std))
+        };
+
+        let (accuracy, precision, recall, f1, log_loss, brier) = (
+            stat(|m| m.accuracy),
+            stat(|m| m.precision),
+            stat(|m| m.recall),
+            stat(|m| m.f1),
+            stat(|m| m.log_loss),
+            stat(|m| m.brier),
+        );
+        let threshold = stat(|m| m.threshold);
+        let (roc_auc, pr_auc) = (area(|m| m.roc_auc), area(|m| m.pr_auc));
+        let mut confusion = ConfusionMatrix::default();
+        for fold in folds.iter() {
+            let c = fold.metrics.confusion;
+            confusion.true_positives += c.true_positives;
+            confusion.false_positives += c.false_positives;
+            confusion.true_negatives += c.true_negatives;
+            confusion.false_negatives += c.false_negatives;
+        }
+
+        let mean = Metrics {
+            accuracy: accuracy.0,
+            precision: precision.0,
+            recall: recall.0,
+            f1: f1.0,
+            roc_auc: roc_auc.0,
+            pr_auc: pr_auc.0,
+            log_loss: log_loss.0,
+            brier: brier.0,
+            confusion,
+            threshold: threshold.0,
+        };
+        let std = Metrics {
+            accuracy: accuracy.1,
+            precision: precision.1,
+            recall: recall.1,
+            f1: f1.1,
+            roc_auc: roc_auc.1,
+            pr_auc: pr_auc.1,
+            log_loss: log_loss.1,
+            brier: brier.1,
+            confusion: ConfusionMatrix::default(),
+            threshold: threshold.1,
+        };
+        CrossValidation { folds, mean, std }
+    }
+}
+
+impl From<&CrossValidation> for file::ResponseCrossValidation {
+    fn from(cv: &CrossValidation) -> Self {
+        file::ResponseCrossValidation {
+            message: String::new(),
+            folds: cv
+                .folds
+                .iter()
+                .map(|fold| file::FoldMetrics {
+                    metrics: Some((&fold.metrics).into()),
+                    epochs: fold.epochs as u32,
+                    train_rows: fold.train_rows as u64,
+                    test_rows: fold.test_rows as u64,
+                })
+                .collect(),
+            mean: Some((&cv.mean).into()),
+            std: Some(file::ResponseMetrics {
+                confusion: None,
+                ..(&cv.std).into()
+            }),
+        }
+    }
+}
+
+impl From<file::ResponseCrossValidation> for CrossValidation {
+    fn from(cv: file::ResponseCrossValidation) -> Self {
+        CrossValidation {
+            folds: cv
+                .folds
+                .into_iter()
+                .map(|fold| Fold {
+                    metrics: fold.metrics.unwrap_or_default().into(),
+                    epochs: fold.epochs as usize,
+                    train_rows: fold.train_rows as usize,
+                    test_rows: fold.test_rows as usize,
+                })
+                .collect(),
+            mean: cv.mean.unwrap_or_default().into(),
+            std: cv.std.unwrap_or_default().into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    // Pairwise definition of the ROC-AUC
+    fn pairwise_auc(probabilities: &Array1<f64>, y: &Array1<f64>) -> f64 {
+        let (mut wins, mut pairs) = (0.0, 0.0);
+        for (&p, _) in probabilities
+            .iter()
+            .zip(y.iter())
+            .filter(|(_, &l)| l == 1.0)
+        {
+            for (&q, _) in probabilities
+                .iter()
+                .zip(y.iter())
+                .filter(|(_, &l)| l == 0.0)
+            {
+                pairs += 1.0;
+                if p > q {
+                    wins += 1.0;
+                } else if p == q {
+                    wins += 0.5;
+                }
+            }
+        }
+        wins / pairs
+    }
+
+    #[test]
+    fn roc_auc_with_ties() {
+        let probabilities = array![0.9, 0.7, 0.7, 0.7, 0.4, 0.4, 0.1, 0.9];
+        let y = array![1.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0];
+        let auc = roc_auc(&ranked(&probabilities, &y)).unwrap();
+        assert!((auc - pairwise_auc(&probabilities, &y)).abs() < 1e-12);
+        // 7 of the 15 pairs won and 4 tied
+        assert!((auc - 9.0 / 15.0).abs() < 1e-12);
+
+        // All tied: no better than chance
+        let flat = array![0.5, 0.5, 0.5, 0.5];
+        let y = array![1.0, 0.0, 1.0, 0.0];
+        assert_eq!(roc_auc(&ranked(&flat, &y)), Some(0.5));
+    }
+
+    #[test]
+    fn roc_auc_needs_both_classes() {
+        let probabilities = array![0.2, 0.8];
+        assert_eq!(roc_auc(&ranked(&probabilities, &array![1.0, 1.0])), None);
+        assert_eq!(roc_auc(&ranked(&probabilities, &array![0.0, 0.0])), None);
+        assert_eq!(pr_auc(&ranked(&probabilities, &array![0.0, 0.0])), None);
+    }
+
+    #[test]
+    fn binary_metrics() {
+        let probabilities = array![0.9, 0.8, 0.6, 0.3, 0.2];
+        let y = array![1.0, 0.0, 1.0, 1.0, 0.0];
+        let metrics = Metrics::evaluate(&probabilities, &y, 0.5);
+        assert_eq!(metrics.confusion.true_positives, 2);
+        assert_eq!(metrics.confusion.false_positives, 1);
+        assert_eq!(metrics.confusion.true_negatives, 1);
+        assert_eq!(metrics.confusion.false_negatives, 1);
+        assert!((metrics.accuracy - 0.6).abs() < 1e-12);
+        assert!((metrics.precision - 2.0 / 3.0).abs() < 1e-12);
+        assert!((metrics.recall - 2.0 / 3.0).abs() < 1e-12);
+        // Precisions 1, 2/3 and 3/4 at the positives
+        let expected = (1.0 + 2.0 / 3.0 + 3.0 / 4.0) / 3.0;
+        assert!((metrics.pr_auc.unwrap() - expected).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,175 @@
+// Models trained by LaunchTraining, stored on the server as
+// models/<client>/<id>.json, encrypted with the master key, so that predictions
+// can reference them by ID. A client only reaches its own models, which count in
+// its quota.
+
+use crate::at_rest::MasterKey;
+use crate::metrics::{self, Metrics};
+use crate::mpc::random_u64s;
+use crate::normalize::{Imputer, Scaler};
+use crate::schema::Schema;
+use crate::split::SplitOptions;
+use crate::storage;
+use crate::training::{Fit, Hyperparameters};
+use ndarray::{Array1, Array2, Axis};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const MODEL_DIRECTORY: &str = "models";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoredModel {
+    pub id: String,
+    // Coefficients on the scaled features, the intercept first, row by row: one
+    // row per feature holding the coefficient of every column of the model
+    pub theta: Vec<f64>,
+    // Columns of the model, 1 for a binary model and one per class otherwise
+    #[serde(default = "default_columns")]
+    pub columns: usize,
+    // Values filling the missing features, fitted on the training set, if any
+    #[serde(default)]
+    pub imputer: Option<Imputer>,
+    // Scaling of the features fitted on the training set, if any
+    #[serde(default)]
+    pub scaler: Option<Scaler>,
+    // Schema of the training dataset, if any
+    #[serde(default)]
+    pub schema: Option<Schema>,
+    // SHA-256 of the training file, hex encoded
+    pub dataset_hash: String,
+    // Hyperparameters of the training, the defaults for models stored before they
+    // were configurable
+    #[serde(default)]
+    pub hyperparameters: Hyperparameters,
+    // Held-out split of the evaluation, the default for the models stored before
+    // it was configurable
+    #[serde(default)]
+    pub split: SplitOptions,
+    // Epochs the training ran before it converged
+    #[serde(default)]
+    pub epochs: usize,
+    // Decision threshold on the probability of the positive class
+    #[serde(default = "default_threshold")]
+    pub threshold: f64,
+    // Accuracy on the test split of the training file
+    pub accuracy: f64,
+    // Evaluation on the test split, missing for the models stored before it
+    #[serde(default)]
+    pub metrics: Option<Metrics>,
+    // Seconds since the Unix epoch
+    pub created_at: u64,
+}
+
+fn default_threshold() -> f64 {
+    metrics::THRESHOLD
+}
+
+fn default_columns() -> usize {
+    1
+}
+
+impl StoredModel {
+    // New model with a random ID, trained now on `dataset`
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        fit: Fit,
+        imputer: Option<Imputer>,
+        scaler: Option<Scaler>,
+        schema: Option<Schema>,
+        dataset: &[u8],
+        hyperparameters: Hyperparameters,
+        split: SplitOptions,
+        metrics: Metrics,
+    ) -> Self {
+        let id = random_u64s(2)
+            .iter()
+            .map(|w| format!("{:016x}", w))
+            .collect();
+        let dataset_hash = Sha256::digest(dataset)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        StoredModel {
+            id,
+            theta: fit.theta.iter().copied().collect(),
+            columns: fit.theta.ncols(),
+            imputer,
+            scaler,
+            schema,
+            dataset_hash,
+            hyperparameters,
+            split,
+            epochs: fit.epochs,
+            threshold: fit.threshold,
+            accuracy: metrics.accuracy,
+            metrics: Some(metrics),
+            created_at,
+        }
+    }
+
+    // Coefficients on the unscaled features, as the predictors expect them, one
+    // column per column of the model
+    pub fn coefficients(&self) -> Result<Array2<f64>, Box<dyn Error>> {
+        let columns = self.columns.max(1);
+        let theta =
+            Array2::from_shape_vec((self.theta.len() / columns, columns), self.theta.clone())?;
+        let scaler = match &self.scaler {
+            Some(scaler) => scaler,
+            None => return Ok(theta),
+        };
+        let unscaled: Vec<Array1<f64>> = theta
+            .columns()
+            .into_iter()
+            .map(|column| scaler.unscale(&column.to_owned()))
+            .collect();
+        let views: Vec<_> = unscaled.iter().map(|column| column.view()).collect();
+        Ok(ndarray::stack(Axis(1), &views)?)
+    }
+}
+
+#[derive(Debug)]
+pub struct ModelStore {
+    directory: PathBuf,
+    key: MasterKey,
+}
+
+impl ModelStore {
+    pub fn new(directory: impl Into<PathBuf>, key: MasterKey) -> Self {
+        ModelStore {
+            directory: directory.into(),
+            key,
+        }
+    }
+
+    // IDs are hex strings, which keeps them from naming a file outside the store.
+    // Client names are generated by the server.
+    fn path(&self, client: &str, id: &str) -> Result<PathBuf, Box<dyn Error>> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("Invalid model ID {:?}", id).into());
+        }
+        Ok(self.directory.join(client).join(format!("{}.json", id)))
+    }
+
+    pub fn save(&self, client: &str, model: &StoredModel) -> Result<(), Box<dyn Error>> {
+        let path = self.path(client, &model.id)?;
+        std::fs::create_dir_all(self.directory.join(client))?;
+        self.key.write(&path, &serde_json::to_vec_pretty(model)?)
+    }
+
+    pub fn load(&self, client: &str, id: &str) -> Result<StoredModel, Box<dyn Error>> {
+        let content = self.key.read(&self.path(client, id)?)?;
+        Ok(serde_json::from_slice(&content)?)
+    }
+
+    // Size of the models of a client
+    pub fn usage(&self, client: &str) -> u64 {
+        storage::usage(&self.directory.join(client))
+    }
+}
@@ -0,0 +1,1274 @@
+// 2-out-of-2 additive secret sharing over the ring Z_2^64.
+//
+// Every secret value v is split into two shares v0 and v1 such that
+// v = v0 + v1 (mod 2^64), party 0 (the data owner) holding v0 and party 1
+// (the model owner) holding v1. Real numbers are encoded in fixed-point with
+// FRAC_BITS fractional bits, negative numbers using two's complement.
+// Products of shared values are computed with Beaver multiplication triples.
+
+use crate::gc::{self, Bit, Circuit};
+use crate::normalize::Scaler;
+use crate::ot::OtExtension;
+use crate::training::Hyperparameters;
+use ring::digest::{Context, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::error::Error;
+
+pub type MpcResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+// Number of fractional bits of the fixed-point encoding
+pub const FRAC_BITS: u32 = 16;
+
+pub fn encode(value: f64) -> u64 {
+    (value * (1u64 << FRAC_BITS) as f64).round() as i64 as u64
+}
+
+pub fn decode(value: u64) -> f64 {
+    value as i64 as f64 / (1u64 << FRAC_BITS) as f64
+}
+
+pub fn random_u64s(n: usize) -> Vec<u64> {
+    let mut bytes = vec![0u8; n * 8];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("Failed to gather randomness");
+    bytes
+        .chunks_exact(8)
+        .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+        .collect()
+}
+
+// Expand a seed into `words` pseudo-random words, starting at word `offset`
+// (a multiple of 4) of its output stream. SHA-256 in counter mode.
+pub fn prg(seed: &[u8; 32], offset: usize, words: usize) -> Vec<u64> {
+    let mut output = Vec::with_capacity(words + 4);
+    let mut block = (offset / 4) as u64;
+    while output.len() < words {
+        let mut context = Context::new(&SHA256);
+        context.update(seed);
+        context.update(&block.to_le_bytes());
+        let digest = context.finish();
+        output.extend(
+            digest
+                .as_ref()
+                .chunks_exact(8)
+                .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap())),
+        );
+        block += 1;
+    }
+    output.truncate(words);
+    output
+}
+
+// Split secret values into the shares of party 0 and party 1
+pub fn share(values: &[u64]) -> (Vec<u64>, Vec<u64>) {
+    let share0 = random_u64s(values.len());
+    let share1 = values
+        .iter()
+        .zip(share0.iter())
+        .map(|(v, r)| v.wrapping_sub(*r))
+        .collect();
+    (share0, share1)
+}
+
+pub fn add(x: &[u64], y: &[u64]) -> Vec<u64> {
+    x.iter()
+        .zip(y.iter())
+        .map(|(a, b)| a.wrapping_add(*b))
+        .collect()
+}
+
+pub fn sub(x: &[u64], y: &[u64]) -> Vec<u64> {
+    x.iter()
+        .zip(y.iter())
+        .map(|(a, b)| a.wrapping_sub(*b))
+        .collect()
+}
+
+// Row-major (rows x cols) matrix times vector in the ring
+pub fn mat_vec(matrix: &[u64], rows: usize, cols: usize, vector: &[u64]) -> Vec<u64> {
+    (0..rows)
+        .map(|i| {
+            matrix[i * cols..(i + 1) * cols]
+                .iter()
+                .zip(vector.iter())
+                .fold(0u64, |acc, (m, v)| acc.wrapping_add(m.wrapping_mul(*v)))
+        })
+        .collect()
+}
+
+// Kind and size of a Beaver triple
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TripleKind {
+    // C = A . B with A a (rows x cols) matrix and B a vector of cols values
+    Matrix { rows: usize, cols: usize },
+    // c_i = a_i * b_i for n values of the ring
+    Arithmetic(usize),
+    // c_i = a_i & b_i for n words of 64 bits, shared with XOR
+    Boolean(usize),
+}
+
+// One party's shares of a Beaver triple
+#[derive(Debug, Clone)]
+pub struct Triple {
+    pub a: Vec<u64>,
+    pub b: Vec<u64>,
+    pub c: Vec<u64>,
+}
+
+impl TripleKind {
+    // Expected lengths of the a, b and c vectors
+    pub fn lengths(&self) -> (usize, usize, usize) {
+        match *self {
+            TripleKind::Matrix { rows, cols } => (rows * cols, cols, rows),
+            TripleKind::Arithmetic(n) | TripleKind::Boolean(n) => (n, n, n),
+        }
+    }
+}
+
+pub fn xor_share(values: &[u64]) -> (Vec<u64>, Vec<u64>) {
+    let share0 = random_u64s(values.len());
+    let share1 = values
+        .iter()
+        .zip(share0.iter())
+        .map(|(v, r)| v ^ r)
+        .collect();
+    (share0, share1)
+}
+
+// Generate the two shares of a random triple
+pub fn deal_triple(kind: TripleKind) -> (Triple, Triple) {
+    let (a_len, b_len, _) = kind.lengths();
+    let a = random_u64s(a_len);
+    let b = random_u64s(b_len);
+
+    let ((a0, a1), (b0, b1), (c0, c1)) = match kind {
+        TripleKind::Matrix { rows, cols } => {
+            let c = mat_vec(&a, rows, cols, &b);
+            (share(&a), share(&b), share(&c))
+        }
+        TripleKind::Arithmetic(_) => {
+            let c: Vec<u64> = a
+                .iter()
+                .zip(b.iter())
+                .map(|(x, y)| x.wrapping_mul(*y))
+                .collect();
+            (share(&a), share(&b), share(&c))
+        }
+        TripleKind::Boolean(_) => {
+            let c: Vec<u64> = a.iter().zip(b.iter()).map(|(x, y)| x & y).collect();
+            (xor_share(&a), xor_share(&b), xor_share(&c))
+        }
+    };
+    (
+        Triple {
+            a: a0,
+            b: b0,
+            c: c0,
+        },
+        Triple {
+            a: a1,
+            b: b1,
+            c: c1,
+        },
+    )
+}
+
+// Message exchange with the other party: send our values, receive theirs
+#[tonic::async_trait]
+pub trait Transport: Send {
+    async fn exchange(&mut self, outgoing: Vec<u64>) -> MpcResult<Vec<u64>>;
+}
+
+// Source of the correlated randomness consumed by the multiplications. A provider
+// may use the transport to generate it jointly with the other party.
+#[tonic::async_trait]
+pub trait TripleProvider: Send {
+    async fn triple(
+        &mut self,
+        id: usize,
+        transport: &mut dyn Transport,
+        kind: TripleKind,
+    ) -> MpcResult<Triple>;
+}
+
+// Protocol computing the comparisons (step, ReLU, clipping, min and max)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Comparisons {
+    // Kogge-Stone adder on XOR shares, with Beaver triples
+    Adder,
+    // Garbled circuits, in a constant number of rounds
+    GarbledCircuit,
+}
+
+pub struct Party<T: Transport> {
+    pub id: usize,
+    transport: T,
+    triples: Box<dyn TripleProvider>,
+    // Truncate with truncate_exact rather than locally
+    pub exact_truncation: bool,
+    pub comparisons: Comparisons,
+    // OT extension transferring the labels of the garbled circuits, set up on first use
+    garbling: Option<OtExtension>,
+}
+
+impl<T: Transport> Party<T> {
+    pub fn new(id: usize, transport: T, triples: Box<dyn TripleProvider>) -> Self {
+        Party {
+            id,
+            transport,
+            triples,
+            exact_truncation: false,
+            comparisons: Comparisons::Adder,
+            garbling: None,
+        }
+    }
+
+    // Our share of a fresh triple
+    pub async fn triple(&mut self, kind: TripleKind) -> MpcResult<Triple> {
+        self.triples
+            .triple(self.id, &mut self.transport, kind)
+            .await
+    }
+
+    // Send a message to the other party and receive theirs
+    pub async fn exchange(&mut self, outgoing: Vec<u64>) -> MpcResult<Vec<u64>> {
+        self.transport.exchange(outgoing).await
+    }
+
+    // Reconstruct shared values for both parties
+    pub async fn open(&mut self, shares: &[u64]) -> MpcResult<Vec<u64>> {
+        let other = self.exchange(shares.to_vec()).await?;
+        if other.len() != shares.len() {
+            return Err("Unexpected number of shares received".into());
+        }
+        Ok(add(shares, &other))
+    }
+
+    // Reconstruct shared values for the party `owner` only, the other party gets nothing
+    pub async fn reveal_to(&mut self, owner: usize, shares: &[u64]) -> MpcResult<Option<Vec<u64>>> {
+        if self.id == owner {
+            let other = self.exchange(Vec::new()).await?;
+            if other.len() != shares.len() {
+                return Err("Unexpected number of shares received".into());
+            }
+            Ok(Some(add(shares, &other)))
+        } else {
+            self.exchange(shares.to_vec()).await?;
+            Ok(None)
+        }
+    }
+
+    // Truncate fixed-point shares by `bits` bits after a multiplication (SecureML
+    // local truncation). The result is wrong with a probability of about |x| / 2^63.
+    pub fn truncate(&self, shares: &[u64], bits: u32) -> Vec<u64> {
+        shares
+            .iter()
+            .map(|&s| {
+                if self.id == 0 {
+                    ((s as i64) >> bits) as u64
+                } else {
+                    (-((s.wrapping_neg() as i64) >> bits)) as u64
+                }
+            })
+            .collect()
+    }
+
+    // Truncate shares of values below 2^62 in absolute value by `bits` bits, up to
+    // the carry of the low bits but without the failure probability of the local
+    // truncation. With x' = x + 2^62 < 2^63, x0 + x1 = x' + w 2^64 where the wrap
+    // bit w is msb(x0) OR msb(x1), so x' >> bits = (x0 >> bits) + (x1 >> bits)
+    // - w 2^(64 - bits). One multiplication computes w.
+    pub async fn truncate_exact(&mut self, shares: &[u64], bits: u32) -> MpcResult<Vec<u64>> {
+        let n = shares.len();
+        let bias = 1u64 << 62;
+        let shifted = self.add_constant(shares, bias);
+        let msb: Vec<u64> = shifted.iter().map(|s| s >> 63).collect();
+        let zeros = vec![0u64; n];
+        let (x, y) = if self.id == 0 {
+            (msb.clone(), zeros)
+        } else {
+            (zeros, msb.clone())
+        };
+        let both = self.mul(&x, &y).await?;
+
+        Ok((0..n)
+            .map(|i| {
+                // Share of m0 + m1 - m0 * m1
+                let wrap = msb[i].wrapping_sub(both[i]);
+                let truncated = (shifted[i] >> bits).wrapping_sub(wrap << (64 - bits));
+                if self.id == 0 {
+                    truncated.wrapping_sub(bias >> bits)
+                } else {
+                    truncated
+                }
+            })
+            .collect())
+    }
+
+    // Truncate by `bits` bits, exactly or locally depending on exact_truncation
+    pub async fn rescale(&mut self, shares: &[u64], bits: u32) -> MpcResult<Vec<u64>> {
+        if self.exact_truncation {
+            self.truncate_exact(shares, bits).await
+        } else {
+            Ok(self.truncate(shares, bits))
+        }
+    }
+
+    // Shares of X . theta, X being a shared (rows x cols) matrix and theta a shared vector
+    pub async fn mat_vec(
+        &mut self,
+        x: &[u64],
+        rows: usize,
+        cols: usize,
+        theta: &[u64],
+        triple: &Triple,
+    ) -> MpcResult<Vec<u64>> {
+        // Open the masked inputs D = X - A and E = theta - B in a single round
+        let mut masked = sub(x, &triple.a);
+        masked.extend(sub(theta, &triple.b));
+        let opened = self.open(&masked).await?;
+        let (d, e) = opened.split_at(rows * cols);
+
+        // Z = C + D.B + A.E (+ D.E for a single party)
+        let mut z = add(&triple.c, &mat_vec(d, rows, cols, &triple.b));
+        z = add(&z, &mat_vec(&triple.a, rows, cols, e));
+        if self.id == 0 {
+            z = add(&z, &mat_vec(d, rows, cols, e));
+        }
+
+        self.rescale(&z, FRAC_BITS).await
+    }
+
+    // Add a public constant to shared values
+    pub fn add_constant(&self, x: &[u64], constant: u64) -> Vec<u64> {
+        if self.id == 0 {
+            x.iter().map(|v| v.wrapping_add(constant)).collect()
+        } else {
+            x.to_vec()
+        }
+    }
+
+    // Multiply shared fixed-point values by a public constant, encoded with `bits`
+    // fractional bits
+    pub async fn scale(&mut self, x: &[u64], constant: f64, bits: u32) -> MpcResult<Vec<u64>> {
+        let constant = (constant * 2f64.powi(bits as i32)).round() as i64 as u64;
+        let products: Vec<u64> = x.iter().map(|v| v.wrapping_mul(constant)).collect();
+        self.rescale(&products, bits).await
+    }
+
+    // Elementwise product of shared values, without truncation
+    pub async fn mul(&mut self, x: &[u64], y: &[u64]) -> MpcResult<Vec<u64>> {
+        let n = x.len();
+        let triple = self.triple(TripleKind::Arithmetic(n)).await?;
+        let mut masked = sub(x, &triple.a);
+        masked.extend(sub(y, &triple.b));
+        let opened = self.open(&masked).await?;
+        let (d, e) = opened.split_at(n);
+
+        Ok((0..n)
+            .map(|i| {
+                let mut z = triple.c[i]
+                    .wrapping_add(d[i].wrapping_mul(triple.b[i]))
+                    .wrapping_add(e[i].wrapping_mul(triple.a[i]));
+                if self.id == 0 {
+                    z = z.wrapping_add(d[i].wrapping_mul(e[i]));
+                }
+                z
+            })
+            .collect())
+    }
+
+    // Elementwise product of shared fixed-point values
+    pub async fn mul_fixed(&mut self, x: &[u64], y: &[u64]) -> MpcResult<Vec<u64>> {
+        let z = self.mul(x, y).await?;
+        self.rescale(&z, FRAC_BITS).await
+    }
+
+    // Reconstruct XOR-shared words for both parties
+    pub async fn open_xor(&mut self, shares: &[u64]) -> MpcResult<Vec<u64>> {
+        let other = self.exchange(shares.to_vec()).await?;
+        if other.len() != shares.len() {
+            return Err("Unexpected number of shares received".into());
+        }
+        Ok(shares
+            .iter()
+            .zip(other.iter())
+            .map(|(a, b)| a ^ b)
+            .collect())
+    }
+
+    // Bitwise AND of XOR-shared words
+    pub async fn and(&mut self, x: &[u64], y: &[u64]) -> MpcResult<Vec<u64>> {
+        let n = x.len();
+        let triple = self.triple(TripleKind::Boolean(n)).await?;
+        let mut masked: Vec<u64> = x.iter().zip(triple.a.iter()).map(|(v, a)| v ^ a).collect();
+        masked.extend(y.iter().zip(triple.b.iter()).map(|(v, b)| v ^ b));
+        let opened = self.open_xor(&masked).await?;
+        let (d, e) = opened.split_at(n);
+
+        Ok((0..n)
+            .map(|i| {
+                let mut z = triple.c[i] ^ (d[i] & triple.b[i]) ^ (e[i] & triple.a[i]);
+                if self.id == 0 {
+                    z ^= d[i] & e[i];
+                }
+                z
+            })
+            .collect())
+    }
+
+    // Most significant bit of arithmetically shared values, XOR-shared in the
+    // lowest bit of the output words. The shares x0 and x1 are added with a
+    // Kogge-Stone adder on XOR shares, each party knowing one of the operands.
+    pub async fn msb(&mut self, x: &[u64]) -> MpcResult<Vec<u64>> {
+        let zeros = vec![0u64; x.len()];
+        let (u, v) = if self.id == 0 {
+            (x.to_vec(), zeros)
+        } else {
+            (zeros, x.to_vec())
+        };
+
+        // Generate and propagate signals of every bit position
+        let propagate: Vec<u64> = u.iter().zip(v.iter()).map(|(a, b)| a ^ b).collect();
+        let mut g = self.and(&u, &v).await?;
+        let mut p = propagate.clone();
+
+        let n = x.len();
+        for shift in [1, 2, 4, 8, 16, 32] {
+            let mut left = p.clone();
+            left.extend(p.iter());
+            let mut right: Vec<u64> = g.iter().map(|w| w << shift).collect();
+            right.extend(p.iter().map(|w| w << shift));
+            let products = self.and(&left, &right).await?;
+            g = g
+                .iter()
+                .zip(products[..n].iter())
+                .map(|(a, b)| a ^ b)
+                .collect();
+            p = products[n..].to_vec();
+        }
+
+        // The carry into bit 63 is the generate signal of bits 0 to 62
+        Ok(propagate
+            .iter()
+            .zip(g.iter())
+            .map(|(p, g)| ((p >> 63) ^ (g >> 62)) & 1)
+            .collect())
+    }
+
+    // Convert XOR-shared bits to arithmetic shares of the integers 0 and 1:
+    // b = b0 + b1 - 2 * b0 * b1
+    pub async fn bit_to_arith(&mut self, bits: &[u64]) -> MpcResult<Vec<u64>> {
+        let zeros = vec![0u64; bits.len()];
+        let (x, y) = if self.id == 0 {
+            (bits.to_vec(), zeros)
+        } else {
+            (zeros, bits.to_vec())
+        };
+        let xy = self.mul(&x, &y).await?;
+        Ok((0..bits.len())
+            .map(|i| x[i].wrapping_add(y[i]).wrapping_sub(xy[i].wrapping_mul(2)))
+            .collect())
+    }
+
+    // Run a garbled circuit, party 1 garbling and party 0 evaluating, on our input
+    // bits. Returns our XOR shares of its outputs and, for party 0, its revealed outputs.
+    pub async fn garbled(
+        &mut self,
+        circuit: &Circuit,
+        inputs: &[bool],
+    ) -> MpcResult<(Vec<bool>, Vec<bool>)> {
+        if self.garbling.is_none() {
+            self.garbling = Some(OtExtension::setup(self.id, &mut self.transport).await?);
+        }
+        let ot = self.garbling.as_mut().unwrap();
+        gc::execute(self.id, &mut self.transport, ot, circuit, inputs).await
+    }
+
+    // Apply `function` to every tuple of shared words operands[k][i] in a single
+    // garbled circuit, and return arithmetic shares of its `outputs` words
+    async fn garbled_words<F>(
+        &mut self,
+        operands: &[&[u64]],
+        outputs: usize,
+        function: F,
+    ) -> MpcResult<Vec<Vec<u64>>>
+    where
+        F: Fn(&mut Circuit, &[Vec<Bit>]) -> Vec<Vec<Bit>>,
+    {
+        let n = operands.first().map_or(0, |o| o.len());
+        let mut circuit = Circuit::new();
+        let mut inputs = Vec::new();
+        let mut masks = Vec::new();
+        for i in 0..n {
+            let words: Vec<Vec<Bit>> = operands
+                .iter()
+                .map(|operand| {
+                    inputs.extend(gc::to_bits(operand[i]));
+                    circuit.shared_word()
+                })
+                .collect();
+            for value in function(&mut circuit, &words) {
+                circuit.arithmetic_output(&value);
+                if self.id == 1 {
+                    let mask = random_u64s(1)[0];
+                    inputs.extend(gc::to_bits(mask));
+                    masks.push(mask);
+                }
+            }
+        }
+
+        let (_, revealed) = self.garbled(&circuit, &inputs).await?;
+        let shares: Vec<u64> = if self.id == 1 {
+            masks
+        } else {
+            revealed.chunks_exact(64).map(gc::from_bits).collect()
+        };
+        Ok((0..outputs)
+            .map(|k| shares.iter().skip(k).step_by(outputs).copied().collect())
+            .collect())
+    }
+
+    // Arithmetic shares of the integer [x >= 0] for shared fixed-point values
+    pub async fn step(&mut self, x: &[u64]) -> MpcResult<Vec<u64>> {
+        if self.comparisons == Comparisons::GarbledCircuit {
+            let mut outputs = self
+                .garbled_words(&[x], 1, |circuit, words| {
+                    let mut step = Circuit::constant(0);
+                    step[0] = circuit.not(circuit.negative(&words[0]));
+                    vec![step]
+                })
+                .await?;
+            return Ok(outputs.remove(0));
+        }
+
+        let msb = self.msb(x).await?;
+        let negative = self.bit_to_arith(&msb).await?;
+        let zeros = vec![0u64; x.len()];
+        Ok(sub(&self.add_constant(&zeros, 1), &negative))
+    }
+
+    // max(x, 0)
+    pub async fn relu(&mut self, x: &[u64]) -> MpcResult<Vec<u64>> {
+        if self.comparisons == Comparisons::GarbledCircuit {
+            let mut outputs = self
+                .garbled_words(&[x], 1, |circuit, words| {
+                    let negative = circuit.negative(&words[0]);
+                    vec![circuit.mux(negative, &Circuit::constant(0), &words[0])]
+                })
+                .await?;
+            return Ok(outputs.remove(0));
+        }
+
+        let positive = self.step(x).await?;
+        self.mul(x, &positive).await
+    }
+
+    // Element-wise minimum and maximum of x and y
+    pub async fn min_max(&mut self, x: &[u64], y: &[u64]) -> MpcResult<(Vec<u64>, Vec<u64>)> {
+        if self.comparisons == Comparisons::GarbledCircuit {
+            let mut outputs = self
+                .garbled_words(&[x, y], 2, |circuit, words| {
+                    let less = circuit.less_than(&words[0], &words[1]);
+                    vec![
+                        circuit.mux(less, &words[0], &words[1]),
+                        circuit.mux(less, &words[1], &words[0]),
+                    ]
+                })
+                .await?;
+            let max = outputs.pop().unwrap();
+            return Ok((outputs.pop().unwrap(), max));
+        }
+
+        // max = y + [x >= y] (x - y), min = x + y - max
+        let difference = sub(x, y);
+        let greater = self.step(&difference).await?;
+        let max = add(y, &self.mul(&greater, &difference).await?);
+        Ok((sub(&add(x, y), &max), max))
+    }
+
+    // Clip shared fixed-point values to [low, high]
+    pub async fn clamp(&mut self, x: &[u64], low: f64, high: f64) -> MpcResult<Vec<u64>> {
+        let (low, high) = (encode(low), encode(high));
+        if self.comparisons == Comparisons::GarbledCircuit {
+            let mut outputs = self
+                .garbled_words(&[x], 1, |circuit, words| {
+                    let (low, high) = (Circuit::constant(low), Circuit::constant(high));
+                    let below = circuit.less_than(&words[0], &low);
+                    let above = circuit.less_than(&high, &words[0]);
+                    let clipped = circuit.mux(below, &low, &words[0]);
+                    vec![circuit.mux(above, &high, &clipped)]
+                })
+                .await?;
+            return Ok(outputs.remove(0));
+        }
+
+        // x + [x - high >= 0] (high - x) + [low - x >= 0] (low - x)
+        let n = x.len();
+        let above = self.add_constant(x, high.wrapping_neg());
+        let negated: Vec<u64> = x.iter().map(|v| v.wrapping_neg()).collect();
+        let below = self.add_constant(&negated, low);
+        let mut differences = above.clone();
+        differences.extend(below.iter());
+        let outside = self.step(&differences).await?;
+
+        let mut gaps: Vec<u64> = above.iter().map(|v| v.wrapping_neg()).collect();
+        gaps.extend(below.iter());
+        let corrections = self.mul(&outside, &gaps).await?;
+        Ok(add(&add(x, &corrections[..n]), &corrections[n..]))
+    }
+
+    // Approximation of the sigmoid on shared fixed-point values: the input is
+    // clipped to [-SIGMOID_RANGE, SIGMOID_RANGE] and the sigmoid evaluated there
+    // with an odd Chebyshev polynomial of degree SIGMOID_DEGREE
+    pub async fn sigmoid(&mut self, z: &[u64]) -> MpcResult<Vec<u64>> {
+        let n = z.len();
+        let u = self.scale(z, 1.0 / SIGMOID_RANGE, FRAC_BITS).await?;
+
+        let u = self.clamp(&u, -1.0, 1.0).await?;
+
+        // Odd powers of u and their weighted sum
+        let coefficients = sigmoid_coefficients();
+        let u2 = self.mul_fixed(&u, &u).await?;
+        let mut power = u.clone();
+        let mut total = vec![0u64; n];
+        for degree in (1..=SIGMOID_DEGREE).step_by(2) {
+            if degree > 1 {
+                power = self.mul_fixed(&power, &u2).await?;
+            }
+            let weight = encode(coefficients[degree]);
+            for (t, p) in total.iter_mut().zip(power.iter()) {
+                *t = t.wrapping_add(p.wrapping_mul(weight));
+            }
+        }
+        let total = self.rescale(&total, FRAC_BITS).await?;
+        Ok(self.add_constant(&total, encode(coefficients[0])))
+    }
+}
+
+// Half-width of the interval on which the sigmoid is approximated
+pub const SIGMOID_RANGE: f64 = 8.0;
+// Degree of the odd polynomial approximating the sigmoid on that interval
+pub const SIGMOID_DEGREE: usize = 11;
+
+// Monomial coefficients in u = z / SIGMOID_RANGE of the Chebyshev interpolant
+// of the sigmoid on [-SIGMOID_RANGE, SIGMOID_RANGE]
+pub fn sigmoid_coefficients() -> Vec<f64> {
+    let nodes = 256;
+    let degree = SIGMOID_DEGREE;
+
+    // Chebyshev coefficients from the values at the Chebyshev nodes
+    let mut chebyshev = vec![0.0; degree + 1];
+    for j in 0..nodes {
+        let angle = std::f64::consts::PI * (j as f64 + 0.5) / nodes as f64;
+        let value = 1.0 / (1.0 + (-SIGMOID_RANGE * angle.cos()).exp());
+        for (k, c) in chebyshev.iter_mut().enumerate() {
+            *c += 2.0 / nodes as f64 * value * (k as f64 * angle).cos();
+        }
+    }
+    chebyshev[0] /= 2.0;
+
+    // T_k(u) in the monomial basis: T_k = 2u T_(k-1) - T_(k-2)
+    let mut previous = vec![0.0; degree + 1];
+    let mut current = vec![0.0; degree + 1];
+    previous[0] = 1.0;
+    current[1] = 1.0;
+    let mut monomial = vec![0.0; degree + 1];
+    monomial[0] += chebyshev[0];
+    for (k, &c_k) in chebyshev.iter().enumerate().skip(1) {
+        if k > 1 {
+            let mut next: Vec<f64> = std::iter::once(0.0)
+                .chain(current[..degree].iter().map(|t| 2.0 * t))
+                .collect();
+            for (n, p) in next.iter_mut().zip(previous.iter()) {
+                *n -= p;
+            }
+            previous = current;
+            current = next;
+        }
+        for (m, t) in monomial.iter_mut().zip(current.iter()) {
+            *m += c_k * t;
+        }
+    }
+    monomial
+}
+
+// Data owner side of the secure linear inference: returns its shares of X . theta.
+// X is the (rows x cols) plaintext feature matrix of the data owner.
+pub async fn linear_data_owner<T: Transport>(
+    party: &mut Party<T>,
+    x: &[f64],
+    rows: usize,
+    cols: usize,
+) -> MpcResult<Vec<u64>> {
+    // Offline phase: the correlated randomness does not depend on the inputs
+    let triple = party.triple(TripleKind::Matrix { rows, cols }).await?;
+
+    // Share X: keep the first share and send the second one to the model owner,
+    // receiving our share of the model coefficients in exchange
+    let encoded: Vec<u64> = x.iter().map(|&v| encode(v)).collect();
+    let (x0, x1) = share(&encoded);
+    let theta0 = party.exchange(x1).await?;
+    if theta0.len() != cols {
+        return Err("Unexpected number of coefficient shares received".into());
+    }
+
+    party.mat_vec(&x0, rows, cols, &theta0, &triple).await
+}
+
+// Model owner side of the secure linear inference: returns its shares of X . theta
+pub async fn linear_model_owner<T: Transport>(
+    party: &mut Party<T>,
+    theta: &[f64],
+    rows: usize,
+) -> MpcResult<Vec<u64>> {
+    let cols = theta.len();
+    let triple = party.triple(TripleKind::Matrix { rows, cols }).await?;
+
+    let encoded: Vec<u64> = theta.iter().map(|&v| encode(v)).collect();
+    let (theta0, theta1) = share(&encoded);
+    let x1 = party.exchange(theta0).await?;
+    if x1.len() != rows * cols {
+        return Err("Unexpected number of feature shares received".into());
+    }
+
+    party.mat_vec(&x1, rows, cols, &theta1, &triple).await
+}
+
+// Shares of the sigmoid probability and of the label [probability >= threshold]
+// of every row from the shares of X . theta. The label is an integer 0 or 1. It is
+// computed on the logit, [z >= logit(threshold)], which the approximation of the
+// sigmoid does not shift.
+pub async fn classify<T: Transport>(
+    party: &mut Party<T>,
+    z: &[u64],
+    threshold: f64,
+) -> MpcResult<(Vec<u64>, Vec<u64>)> {
+    let probabilities = party.sigmoid(z).await?;
+    let threshold = threshold.clamp(1e-9, 1.0 - 1e-9);
+    let cutoff = (threshold / (1.0 - threshold)).ln();
+    let shifted = party.add_constant(z, encode(cutoff).wrapping_neg());
+    let labels = party.step(&shifted).await?;
+    Ok((probabilities, labels))
+}
+
+// Fractional bits of the learning rate divided by the number of rows, which is
+// too small for the fixed-point encoding
+const STEP_BITS: u32 = 32;
+
+// Full-batch gradient descent of training::fit on shared data: both parties
+// hold shares of the (rows x cols) matrix X and of the labels y, and get shares
+// of theta. A training performs millions of truncations, which are all exact.
+pub async fn logistic_regression<T: Transport>(
+    party: &mut Party<T>,
+    x: &[u64],
+    y: &[u64],
+    rows: usize,
+    cols: usize,
+    alpha: f64,
+    iterations: usize,
+) -> MpcResult<Vec<u64>> {
+    party.exact_truncation = true;
+    let x_t: Vec<u64> = (0..cols * rows)
+        .map(|i| x[(i % rows) * cols + i / rows])
+        .collect();
+    let mut theta = vec![0u64; cols];
+
+    for _ in 0..iterations {
+        let triple = party.triple(TripleKind::Matrix { rows, cols }).await?;
+        let z = party.mat_vec(x, rows, cols, &theta, &triple).await?;
+        let h = party.sigmoid(&z).await?;
+
+        // theta -= alpha / m * X^T . (h - y)
+        let residuals = sub(&h, y);
+        let triple = party
+            .triple(TripleKind::Matrix {
+                rows: cols,
+                cols: rows,
+            })
+            .await?;
+        let gradient = party.mat_vec(&x_t, cols, rows, &residuals, &triple).await?;
+        let step = party
+            .scale(&gradient, alpha / rows as f64, STEP_BITS)
+            .await?;
+        theta = sub(&theta, &step);
+    }
+
+    Ok(theta)
+}
+
+// Largest absolute value of the features of a secure training, whose centered
+// values must stay below 2^15
+pub const MAX_TRAINING_FEATURE: f64 = 16384.0;
+
+// Standard deviations below are taken for constant columns, which are only
+// centered, the fixed-point rounding leaving them slightly above 0
+const MIN_STD: f64 = 1e-3;
+
+// Fractional bits of the inverse standard deviations
+const SCALE_BITS: u32 = 32;
+
+// Z-score scaling of a shared (rows x cols) matrix, fitted on all its rows as in
+// the plaintext training. The mean and the standard deviation of every column are
+// opened to both parties, the rows stay shared. The features must stay below
+// MAX_TRAINING_FEATURE in absolute value.
+pub async fn standardize<T: Transport>(
+    party: &mut Party<T>,
+    x: &[u64],
+    rows: usize,
+    cols: usize,
+) -> MpcResult<(Vec<u64>, Scaler)> {
+    party.exact_truncation = true;
+    let column_sums = |values: &[u64]| -> Vec<u64> {
+        let mut sums = vec![0u64; cols];
+        for row in values.chunks(cols) {
+            sums = add(&sums, row);
+        }
+        sums
+    };
+
+    let mean: Vec<f64> = party
+        .open(&column_sums(x))
+        .await?
+        .iter()
+        .map(|&sum| decode(sum) / rows as f64)
+        .collect();
+    let offsets: Vec<u64> = (0..rows * cols).map(|i| encode(mean[i % cols])).collect();
+    let centered = if party.id == 0 {
+        sub(x, &offsets)
+    } else {
+        x.to_vec()
+    };
+
+    let squares = party.mul_fixed(&centered, &centered).await?;
+    let std: Vec<f64> = party
+        .open(&column_sums(&squares))
+        .await?
+        .iter()
+        .map(|&sum| (decode(sum) / rows as f64).max(0.0).sqrt())
+        .map(|std| if std < MIN_STD { 0.0 } else { std })
+        .collect();
+
+    // Centered features times 1 / std, the scaled features staying below 2^14
+    let factors: Vec<u64> = std
+        .iter()
+        .map(|&std| if std > 0.0 { 1.0 / std } else { 1.0 })
+        .map(|factor| (factor * 2f64.powi(SCALE_BITS as i32)).round() as i64 as u64)
+        .collect();
+    let products: Vec<u64> = centered
+        .iter()
+        .enumerate()
+        .map(|(i, v)| v.wrapping_mul(factors[i % cols]))
+        .collect();
+    let scaled = party.rescale(&products, SCALE_BITS).await?;
+    Ok((scaled, Scaler::ZScore { mean, std }))
+}
+
+// Secure counterpart of the plaintext training: the features are scaled with
+// standardize, prefixed with a column of ones if `params.intercept`, and fitted by
+// gradient descent with the learning rate and the epochs of `params`. Returns the
+// shared coefficients on the scaled features, the intercept first if any, and the
+// scaler mapping them back to the raw features.
+pub async fn scaled_logistic_regression<T: Transport>(
+    party: &mut Party<T>,
+    x: &[u64],
+    y: &[u64],
+    rows: usize,
+    cols: usize,
+    params: &Hyperparameters,
+) -> MpcResult<(Vec<u64>, Scaler)> {
+    let (mut x, scaler) = standardize(party, x, rows, cols).await?;
+    let mut cols = cols;
+    if params.intercept {
+        // Shares of 1: encode(1) for party 0, 0 for party 1
+        let one = if party.id == 0 { encode(1.0) } else { 0 };
+        x = x
+            .chunks(cols)
+            .flat_map(|row| std::iter::once(one).chain(row.iter().copied()))
+            .collect();
+        cols += 1;
+    }
+    let theta = logistic_regression(
+        party,
+        &x,
+        y,
+        rows,
+        cols,
+        params.learning_rate,
+        params.epochs,
+    )
+    .await?;
+    Ok((theta, scaler))
+}
+
+// In-memory transport connecting the two parties of a test, which run
+// concurrently in the same task, and a dealer for their triples
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::{deal_triple, MpcResult, Party, Transport, Triple, TripleKind, TripleProvider};
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+    pub struct ChannelTransport {
+        sender: UnboundedSender<Vec<u64>>,
+        receiver: UnboundedReceiver<Vec<u64>>,
+    }
+
+    // Transports of party 0 and party 1, connected to each other
+    pub fn transports() -> (ChannelTransport, ChannelTransport) {
+        let (sender0, receiver1) = unbounded_channel();
+        let (sender1, receiver0) = unbounded_channel();
+        (
+            ChannelTransport {
+                sender: sender0,
+                receiver: receiver0,
+            },
+            ChannelTransport {
+                sender: sender1,
+                receiver: receiver1,
+            },
+        )
+    }
+
+    #[tonic::async_trait]
+    impl Transport for ChannelTransport {
+        async fn exchange(&mut self, outgoing: Vec<u64>) -> MpcResult<Vec<u64>> {
+            self.sender
+                .send(outgoing)
+                .map_err(|_| "The other party has left")?;
+            self.receiver
+                .recv()
+                .await
+                .ok_or_else(|| "The other party has left".into())
+        }
+    }
+
+    // Triples dealt in-process: the provider of party 0 deals every triple and
+    // hands the other share to the provider of party 1
+    pub struct TestDealer {
+        sender: Option<UnboundedSender<Triple>>,
+        receiver: Option<UnboundedReceiver<Triple>>,
+    }
+
+    #[tonic::async_trait]
+    impl TripleProvider for TestDealer {
+        async fn triple(
+            &mut self,
+            _id: usize,
+            _transport: &mut dyn Transport,
+            kind: TripleKind,
+        ) -> MpcResult<Triple> {
+            if let Some(sender) = &self.sender {
+                let (triple0, triple1) = deal_triple(kind);
+                sender
+                    .send(triple1)
+                    .map_err(|_| "The other party has left")?;
+                Ok(triple0)
+            } else {
+                let receiver = self.receiver.as_mut().unwrap();
+                receiver
+                    .recv()
+                    .await
+                    .ok_or_else(|| "The other party has left".into())
+            }
+        }
+    }
+
+    // Parties 0 and 1 of a computation with dealt triples
+    pub fn parties() -> (Party<ChannelTransport>, Party<ChannelTransport>) {
+        let (transport0, transport1) = transports();
+        let (sender, receiver) = unbounded_channel();
+        let dealer0 = TestDealer {
+            sender: Some(sender),
+            receiver: None,
+        };
+        let dealer1 = TestDealer {
+            sender: None,
+            receiver: Some(receiver),
+        };
+        (
+            Party::new(0, transport0, Box::new(dealer0)),
+            Party::new(1, transport1, Box::new(dealer1)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::parties;
+    use super::*;
+
+    #[test]
+    fn encoding_round_trip() {
+        for value in [0.0, 1.5, -2.25, 1234.5, -0.0001] {
+            assert!((decode(encode(value)) - value).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn shares_add_up() {
+        let values: Vec<u64> = [3.0, -7.5, 0.0].iter().map(|v| encode(*v)).collect();
+        let (share0, share1) = share(&values);
+        assert_eq!(add(&share0, &share1), values);
+        assert_eq!(sub(&add(&share0, &share1), &share1), share0);
+    }
+
+    #[test]
+    fn plaintext_mat_vec() {
+        // [[1, 2], [3, 4], [5, 6]] . [1, -1]
+        let matrix = [1, 2, 3, 4, 5, 6];
+        let vector = [1, 1u64.wrapping_neg()];
+        let product: Vec<i64> = mat_vec(&matrix, 3, 2, &vector)
+            .iter()
+            .map(|v| *v as i64)
+            .collect();
+        assert_eq!(product, vec![-1, -1, -1]);
+    }
+
+    #[tokio::test]
+    async fn secure_linear_prediction() {
+        let (rows, cols) = (3, 2);
+        let x = [1.0, 2.0, -3.0, 0.5, 0.25, -1.0];
+        let theta = [2.0, -4.0];
+
+        let (mut party0, mut party1) = parties();
+        let (z0, z1) = tokio::join!(
+            async {
+                let z0 = linear_data_owner(&mut party0, &x, rows, cols).await?;
+                party0.reveal_to(0, &z0).await
+            },
+            async {
+                let z1 = linear_model_owner(&mut party1, &theta, rows).await?;
+                party1.reveal_to(0, &z1).await
+            }
+        );
+        // Only the data owner learns X . theta
+        assert!(z1.unwrap().is_none());
+        let z = z0.unwrap().unwrap();
+        for (value, expected) in z.iter().zip([-6.0, -8.0, 4.5]) {
+            assert!((decode(*value) - expected).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn dealt_triples_are_consistent() {
+        let (triple0, triple1) = deal_triple(TripleKind::Arithmetic(10));
+        let a = add(&triple0.a, &triple1.a);
+        let b = add(&triple0.b, &triple1.b);
+        let c = add(&triple0.c, &triple1.c);
+        for i in 0..10 {
+            assert_eq!(c[i], a[i].wrapping_mul(b[i]));
+        }
+
+        let (triple0, triple1) = deal_triple(TripleKind::Boolean(10));
+        for i in 0..10 {
+            let a = triple0.a[i] ^ triple1.a[i];
+            let b = triple0.b[i] ^ triple1.b[i];
+            assert_eq!(triple0.c[i] ^ triple1.c[i], a & b);
+        }
+
+        let (rows, cols) = (2, 5);
+        let (triple0, triple1) = deal_triple(TripleKind::Matrix { rows, cols });
+        let a = add(&triple0.a, &triple1.a);
+        let b = add(&triple0.b, &triple1.b);
+        let c = add(&triple0.c, &triple1.c);
+        assert_eq!(c, mat_vec(&a, rows, cols, &b));
+    }
+
+    #[tokio::test]
+    async fn secure_mul_and_and() {
+        let (mut party0, mut party1) = parties();
+        let x: Vec<u64> = [3, 5u64.wrapping_neg(), 0, u64::MAX].to_vec();
+        let y: Vec<u64> = [7, 6, 9, 2].to_vec();
+        let (x0, x1) = share(&x);
+        let (y0, y1) = share(&y);
+        let (z0, z1) = tokio::join!(party0.mul(&x0, &y0), party1.mul(&x1, &y1));
+        let z = add(&z0.unwrap(), &z1.unwrap());
+        for i in 0..x.len() {
+            assert_eq!(z[i], x[i].wrapping_mul(y[i]));
+        }
+
+        let (x0, x1) = xor_share(&x);
+        let (y0, y1) = xor_share(&y);
+        let (z0, z1) = tokio::join!(party0.and(&x0, &y0), party1.and(&x1, &y1));
+        let (z0, z1) = (z0.unwrap(), z1.unwrap());
+        for i in 0..x.len() {
+            assert_eq!(z0[i] ^ z1[i], x[i] & y[i]);
+        }
+    }
+
+    #[tokio::test]
+    async fn msb_matches_plaintext() {
+        let (mut party0, mut party1) = parties();
+        let mut x: Vec<u64> = [0, 1, u64::MAX, 1 << 63, (1 << 63) - 1].to_vec();
+        x.extend(random_u64s(50));
+        let (x0, x1) = share(&x);
+        let (msb0, msb1) = tokio::join!(party0.msb(&x0), party1.msb(&x1));
+        let (msb0, msb1) = (msb0.unwrap(), msb1.unwrap());
+        for i in 0..x.len() {
+            assert_eq!(msb0[i] ^ msb1[i], x[i] >> 63);
+        }
+    }
+
+    #[tokio::test]
+    async fn step_matches_plaintext() {
+        let (mut party0, mut party1) = parties();
+        let values = [-3.5, -0.001, 0.0, 0.001, 42.0];
+        let x: Vec<u64> = values.iter().map(|v| encode(*v)).collect();
+        let (x0, x1) = share(&x);
+        let (step0, step1) = tokio::join!(party0.step(&x0), party1.step(&x1));
+        let step = add(&step0.unwrap(), &step1.unwrap());
+        for (s, v) in step.iter().zip(values) {
+            assert_eq!(*s, (v >= 0.0) as u64);
+        }
+    }
+
+    #[tokio::test]
+    async fn exact_truncation_matches_plaintext() {
+        let (mut party0, mut party1) = parties();
+        // Products of two fixed-point values, far beyond the range where the
+        // local truncation may fail
+        let values: [i64; 6] = [0, 1, -1, 123_456_789, -987_654_321_000, (1 << 61) - 7];
+        let x: Vec<u64> = values.iter().map(|v| *v as u64).collect();
+        for _ in 0..20 {
+            let (x0, x1) = share(&x);
+            let (t0, t1) = tokio::join!(
+                party0.truncate_exact(&x0, FRAC_BITS),
+                party1.truncate_exact(&x1, FRAC_BITS)
+            );
+            let truncated = add(&t0.unwrap(), &t1.unwrap());
+            for (t, v) in truncated.iter().zip(values) {
+                // Exact up to the carry of the low bits
+                let error = (*t as i64).wrapping_sub(v >> FRAC_BITS);
+                assert!(error.abs() <= 1, "{} >> 16 gave {}", v, *t as i64);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn sigmoid_is_close_to_plaintext() {
+        let (mut party0, mut party1) = parties();
+        party0.exact_truncation = true;
+        party1.exact_truncation = true;
+        let values = [-20.0, -4.0, -1.0, 0.0, 0.5, 3.0, 20.0];
+        let z: Vec<u64> = values.iter().map(|v| encode(*v)).collect();
+        let (z0, z1) = share(&z);
+        let (h0, h1) = tokio::join!(party0.sigmoid(&z0), party1.sigmoid(&z1));
+        let h = add(&h0.unwrap(), &h1.unwrap());
+        for (h, v) in h.iter().zip(values) {
+            let expected = 1.0 / (1.0 + f64::exp(-v));
+            assert!((decode(*h) - expected).abs() < 0.01);
+        }
+    }
+
+    #[tokio::test]
+    async fn relu_and_min_max_match_plaintext() {
+        let (mut party0, mut party1) = parties();
+        let values = [-3.0, -0.5, 0.0, 0.75, 12.5];
+        let others = [2.0, -0.25, 0.0, 0.5, -12.5];
+        let x: Vec<u64> = values.iter().map(|v| encode(*v)).collect();
+        let y: Vec<u64> = others.iter().map(|v| encode(*v)).collect();
+        let (x0, x1) = share(&x);
+        let (y0, y1) = share(&y);
+
+        let (r0, r1) = tokio::join!(party0.relu(&x0), party1.relu(&x1));
+        let relu = add(&r0.unwrap(), &r1.unwrap());
+        for (r, v) in relu.iter().zip(values) {
+            assert_eq!(decode(*r), v.max(0.0));
+        }
+
+        let (m0, m1) = tokio::join!(party0.min_max(&x0, &y0), party1.min_max(&x1, &y1));
+        let ((min0, max0), (min1, max1)) = (m0.unwrap(), m1.unwrap());
+        let (min, max) = (add(&min0, &min1), add(&max0, &max1));
+        for i in 0..values.len() {
+            assert_eq!(decode(min[i]), values[i].min(others[i]));
+            assert_eq!(decode(max[i]), values[i].max(others[i]));
+        }
+    }
+
+    // Rows of two features, the second one constant, and a target
+    fn training_set() -> (Vec<f64>, Vec<f64>) {
+        let first = [52.0, 61.0, 47.0, 70.0, 39.0, 58.0, 66.0, 44.0];
+        let x = first.iter().flat_map(|&v| [v, 3.0]).collect();
+        let y = first.iter().map(|&v| (v > 55.0) as u32 as f64).collect();
+        (x, y)
+    }
+
+    #[tokio::test]
+    async fn standardize_matches_plaintext() {
+        let (x, _) = training_set();
+        let (rows, cols) = (x.len() / 2, 2);
+        let encoded: Vec<u64> = x.iter().map(|v| encode(*v)).collect();
+        let (x0, x1) = share(&encoded);
+        let (mut party0, mut party1) = parties();
+        let (s0, s1) = tokio::join!(
+            standardize(&mut party0, &x0, rows, cols),
+            standardize(&mut party1, &x1, rows, cols)
+        );
+        let ((scaled0, scaler0), (scaled1, scaler1)) = (s0.unwrap(), s1.unwrap());
+        assert_eq!(scaler0, scaler1);
+
+        let X = ndarray::Array2::from_shape_vec((rows, cols), x).unwrap();
+        let expected = Scaler::fit_z_score(&X);
+        let (Scaler::ZScore { mean, std }, Scaler::ZScore { mean: m, std: s }) =
+            (&scaler0, &expected)
+        else {
+            panic!("Not a z-score scaler");
+        };
+        for j in 0..cols {
+            assert!((mean[j] - m[j]).abs() < 1e-3);
+            assert!((std[j] - s[j]).abs() < 1e-3);
+        }
+        // The constant column is only centered
+        assert_eq!(std[1], 0.0);
+
+        let scaled = add(&scaled0, &scaled1);
+        for (value, expected) in scaled.iter().zip(expected.transform(&X).iter()) {
+            assert!((decode(*value) - expected).abs() < 1e-2);
+        }
+    }
+
+    #[tokio::test]
+    async fn scaled_training_matches_plaintext() {
+        let (x, y) = training_set();
+        let (rows, cols) = (y.len(), 2);
+        let params = Hyperparameters {
+            learning_rate: 0.5,
+            epochs: 10,
+            ..Default::default()
+        };
+
+        // Plaintext gradient descent on the scaled features with an intercept
+        let X = ndarray::Array2::from_shape_vec((rows, cols), x.clone()).unwrap();
+        let scaler = Scaler::fit_z_score(&X);
+        let X = crate::normalize::add_intercept(&scaler.transform(&X));
+        let target = ndarray::Array1::from(y.clone());
+        let mut expected = ndarray::Array1::<f64>::zeros(cols + 1);
+        for _ in 0..params.epochs {
+            let h = X.dot(&expected).mapv(|z| 1.0 / (1.0 + f64::exp(-z)));
+            let gradient = X.t().dot(&(h - &target));
+            expected = expected - gradient * (params.learning_rate / rows as f64);
+        }
+
+        let encoded: Vec<u64> = x.iter().map(|v| encode(*v)).collect();
+        let (x0, x1) = share(&encoded);
+        let (y0, y1) = share(&y.iter().map(|v| encode(*v)).collect::<Vec<u64>>());
+        let (mut party0, mut party1) = parties();
+        let (t0, t1) = tokio::join!(
+            scaled_logistic_regression(&mut party0, &x0, &y0, rows, cols, &params),
+            scaled_logistic_regression(&mut party1, &x1, &y1, rows, cols, &params)
+        );
+        let ((theta0, scaler), (theta1, _)) = (t0.unwrap(), t1.unwrap());
+        let theta: Vec<f64> = add(&theta0, &theta1).iter().map(|v| decode(*v)).collect();
+        assert_eq!(theta.len(), cols + 1);
+        for (value, expected) in theta.iter().zip(expected.iter()) {
+            assert!((value - expected).abs() < 0.05, "{} != {}", value, expected);
+        }
+
+        // Back on the unscaled features, the intercept first
+        let unscaled = scaler.unscale(&ndarray::Array1::from(theta));
+        assert_eq!(unscaled.len(), cols + 1);
+        assert!(unscaled[1] > 0.0);
+        assert!(unscaled[2].abs() < 1e-3);
+    }
+}
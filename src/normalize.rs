@@ -1,102 +1,523 @@
-use crate::csv_file::Record;
-use ndarray::s;
-use ndarray::{Array1, Array2, Axis};
-use std::error::Error;
-
-// Normalize data using min-max normalization
-pub fn normalize_data(records: &[Record]) -> Result<Array2<f32>, Box<dyn Error>> {
-    let data: Vec<Vec<f32>> = records
-        .iter()
-        .map(|record| {
-            vec![
-                record.male as f32,
-                record.age as f32,
-                record.currentSmoker as f32,
-                record.cigsPerDay as f32,
-                record.BPMeds as f32,
-                record.prevalentStroke as f32,
-                record.prevalentHyp as f32,
-                record.diabetes as f32,
-                record.totChol as f32,
-                record.sysBP as f32,
-                record.diaBP as f32,
-                record.BMI as f32,
-                record.heartRate as f32,
-                record.glucose as f32,
-                record.TenYearCHD as f32,
-            ]
-        })
-        .collect();
-
-    let mut array_data =
-        Array2::from_shape_vec((records.len(), 15), data.into_iter().flatten().collect())?;
-
-    for feature_idx in 0..15 {
-        let column = array_data.column(feature_idx);
-        let min = column.fold(column[0], |min, &val| min.min(val));
-        let max = column.fold(column[0], |max, &val| max.max(val));
-
-        if (max - min) > 0.0 {
-            let _ = array_data
-                .column_mut(feature_idx)
-                .mapv_inplace(|value| (value - min) / (max - min));
-        }
-    }
-
-    Ok(array_data)
-}
-
-pub fn impute_nan_with_mean(column: &mut Array1<f64>) {
-    let mean = column
-        .iter()
-        .filter_map(|&x| if x.is_nan() { None } else { Some(x) })
-        .sum::<f64>()
-        / (column.len() - column.iter().filter(|&&x| x.is_nan()).count()) as f64;
-
-    column.mapv_inplace(|x| if x.is_nan() { mean } else { x });
-}
-
-pub fn clean_dataset(records: Vec<Record>) -> (Array2<f64>, Array1<f64>, Array2<f64>, Array1<f64>) {
-    // Convert the records to an ndarray Array2<f64>
-    let data = Array2::from_shape_fn((records.len(), 15), |(i, j)| match j {
-        0 => records[i].male as f64,
-        1 => records[i].age as f64,
-        2 => records[i].currentSmoker as f64,
-        3 => records[i].cigsPerDay,
-        4 => records[i].BPMeds,
-        5 => records[i].prevalentStroke as f64,
-        6 => records[i].prevalentHyp as f64,
-        7 => records[i].diabetes as f64,
-        8 => records[i].totChol,
-        9 => records[i].sysBP,
-        10 => records[i].diaBP,
-        11 => records[i].BMI,
-        12 => records[i].heartRate,
-        13 => records[i].glucose,
-        14 => records[i].TenYearCHD as f64,
-        _ => unreachable!(),
-    });
-
-    // Split the dataset into X and y
-    let mut X = data.slice(s![.., ..-1]).to_owned();
-    let y = data.column(data.ncols() - 1).to_owned();
-
-    // Impute missing values with mean
-    for i in 0..X.ncols() {
-        let mut column = X.column_mut(i).to_owned();
-        impute_nan_with_mean(&mut column);
-        X.column_mut(i).assign(&column);
-    }
-
-    // Split the dataset into train and test sets
-    let n = X.nrows();
-    let train_indices: Vec<usize> = (0..n).filter(|i| i % 5 != 0).collect();
-    let test_indices: Vec<usize> = (0..n).filter(|i| i % 5 == 0).collect();
-
-    let X_train = X.select(Axis(0), &train_indices);
-    let y_train = y.select(Axis(0), &train_indices);
-    let X_test = X.select(Axis(0), &test_indices);
-    let y_test = y.select(Axis(0), &test_indices);
-
-    return (X_train, y_train, X_test, y_test);
-}
+use crate::csv_file::Dataset;
+use crate::schema::{ColumnType, Imputation, Schema};
+use crate::split::{Split, SplitOptions};
+use ndarray::s;
+use ndarray::{Array1, Array2, Axis};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+// Feature scaling fitted on a training set, applied to every dataset the model
+// then sees. Constant columns are only shifted.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Scaler {
+    // (x - min) / (max - min)
+    MinMax { min: Vec<f64>, max: Vec<f64> },
+    // (x - mean) / std
+    ZScore { mean: Vec<f64>, std: Vec<f64> },
+}
+
+impl Scaler {
+    pub fn fit_min_max(X: &Array2<f64>) -> Self {
+        let min = X
+            .columns()
+            .into_iter()
+            .map(|c| c.fold(f64::INFINITY, |m, &v| m.min(v)))
+            .collect();
+        let max = X
+            .columns()
+            .into_iter()
+            .map(|c| c.fold(f64::NEG_INFINITY, |m, &v| m.max(v)))
+            .collect();
+        Scaler::MinMax { min, max }
+    }
+
+    pub fn fit_z_score(X: &Array2<f64>) -> Self {
+        let mean = X.mean_axis(Axis(0)).unwrap().to_vec();
+        let std = X.std_axis(Axis(0), 0.0).to_vec();
+        Scaler::ZScore { mean, std }
+    }
+
+    // Every feature is mapped to (x - offset) / divisor
+    fn affine(&self) -> (Vec<f64>, Vec<f64>) {
+        let (offset, divisor): (Vec<f64>, Vec<f64>) = match self {
+            Scaler::MinMax { min, max } => (
+                min.clone(),
+                max.iter().zip(min).map(|(a, b)| a - b).collect(),
+            ),
+            Scaler::ZScore { mean, std } => (mean.clone(), std.clone()),
+        };
+        let divisor = divisor
+            .into_iter()
+            .map(|d| if d > 0.0 { d } else { 1.0 })
+            .collect();
+        (offset, divisor)
+    }
+
+    pub fn transform(&self, X: &Array2<f64>) -> Array2<f64> {
+        let (offset, divisor) = self.affine();
+        let mut scaled = X.to_owned();
+        for (j, mut column) in scaled.columns_mut().into_iter().enumerate() {
+            column.mapv_inplace(|v| (v - offset[j]) / divisor[j]);
+        }
+        scaled
+    }
+
+    // Coefficients of the same model on unscaled features, the intercept first:
+    // theta . scale(x) = sum(theta_j / d_j x_j) + theta_0 - sum(theta_j o_j / d_j).
+    // The predictions on data the server never sees in the clear use the scaler
+    // this way.
+    pub fn unscale(&self, theta: &Array1<f64>) -> Array1<f64> {
+        let (offset, divisor) = self.affine();
+        let (intercept, weights) = if theta.len() == offset.len() + 1 {
+            (theta[0], theta.slice(s![1..]))
+        } else {
+            (0.0, theta.view())
+        };
+
+        let mut unscaled = Vec::with_capacity(weights.len() + 1);
+        unscaled.push(intercept);
+        for (j, &w) in weights.iter().enumerate() {
+            unscaled[0] -= w * offset[j] / divisor[j];
+            unscaled.push(w / divisor[j]);
+        }
+        Array1::from(unscaled)
+    }
+}
+
+// Feature matrix of a dataset with the feature layout of `schema`: the columns are
+// looked up by name, categorical columns are one-hot encoded and missing values
+// are NaN. The cells are checked against the schema.
+fn feature_matrix(dataset: &Dataset, schema: &Schema) -> Result<Array2<f64>, Box<dyn Error>> {
+    dataset.check_rows()?;
+    let mut columns = Vec::new();
+    for column in schema.feature_columns() {
+        let j = dataset
+            .position(&column.name)
+            .ok_or_else(|| format!("The column {} is missing", column.name))?;
+        columns.push((column, j));
+    }
+
+    let width = schema.feature_names().len();
+    let mut values = Vec::with_capacity(dataset.rows.len() * width);
+    for row in dataset.rows.iter() {
+        for (column, j) in columns.iter() {
+            column.validate(&row[*j])?;
+            values.extend(column.encode(&row[*j]));
+        }
+    }
+    Ok(Array2::from_shape_vec((dataset.rows.len(), width), values)?)
+}
+
+// Values filling the missing features, one per feature, fitted on a training set
+// with the imputation strategy of every column
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Imputer {
+    pub fill: Vec<f64>,
+}
+
+fn mean(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+fn median(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let middle = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[middle - 1] + sorted[middle]) / 2.0
+    } else {
+        sorted[middle]
+    }
+}
+
+// Most frequent value, the smallest one on ties
+fn mode(values: &[f64]) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let mut best = (0.0, 0);
+    for run in sorted.chunk_by(|a, b| a == b) {
+        if run.len() > best.1 {
+            best = (run[0], run.len());
+        }
+    }
+    best.0
+}
+
+impl Imputer {
+    // X has the feature layout of `schema`, with NaN for the missing values
+    pub fn fit(X: &Array2<f64>, schema: &Schema) -> Self {
+        let present = |j: usize| -> Vec<f64> {
+            X.column(j)
+                .iter()
+                .copied()
+                .filter(|v| !v.is_nan())
+                .collect()
+        };
+
+        let mut fill = Vec::with_capacity(X.ncols());
+        let mut j = 0;
+        for column in schema.feature_columns() {
+            let width = column.width();
+            match (column.kind, column.impute) {
+                (_, Imputation::Constant(value)) => fill.extend(vec![value; width]),
+                // Frequencies of the categories
+                (_, Imputation::Mean | Imputation::DropRow) => {
+                    fill.extend((j..j + width).map(|k| mean(&present(k))))
+                }
+                (ColumnType::Categorical, Imputation::Median | Imputation::Mode) => {
+                    let counts: Vec<f64> =
+                        (j..j + width).map(|k| present(k).iter().sum()).collect();
+                    let most = (0..width)
+                        .max_by(|&a, &b| counts[a].total_cmp(&counts[b]).then(b.cmp(&a)))
+                        .unwrap_or(0);
+                    fill.extend((0..width).map(|k| (k == most) as u32 as f64));
+                }
+                (_, Imputation::Median) => fill.push(median(&present(j))),
+                (_, Imputation::Mode) => fill.push(mode(&present(j))),
+            }
+            j += width;
+        }
+        Imputer { fill }
+    }
+
+    pub fn transform(&self, X: &Array2<f64>) -> Array2<f64> {
+        let mut imputed = X.to_owned();
+        for (j, mut column) in imputed.columns_mut().into_iter().enumerate() {
+            column.mapv_inplace(|v| if v.is_nan() { self.fill[j] } else { v });
+        }
+        imputed
+    }
+}
+
+// Build the feature matrix of a dataset to predict on with the feature layout of
+// `schema`, the schema of the dataset or of the training dataset of the model. The
+// target column is dropped. The missing values are filled by the imputer of the
+// model, or by an imputer fitted on the dataset itself.
+pub fn prediction_features(
+    dataset: &Dataset,
+    schema: &Schema,
+    imputer: Option<&Imputer>,
+) -> Result<Array2<f64>, Box<dyn Error>> {
+    let X = feature_matrix(dataset, schema)?;
+    Ok(match imputer {
+        Some(imputer) => imputer.transform(&X),
+        None => Imputer::fit(&X, schema).transform(&X),
+    })
+}
+
+// Prepend a column of ones so that the first coefficient of the model acts as the intercept
+pub fn add_intercept(X: &Array2<f64>) -> Array2<f64> {
+    let mut with_intercept = Array2::ones((X.nrows(), X.ncols() + 1));
+    with_intercept.slice_mut(s![.., 1..]).assign(X);
+    with_intercept
+}
+
+// Feature matrix, with NaN for the missing values, and target of a training
+// dataset, without the rows dropped by an imputation strategy. A multiclass target
+// holds the index of the class of every row, a binary one 0 or 1.
+fn labeled_features(
+    dataset: &Dataset,
+    multiclass: bool,
+) -> Result<(Array2<f64>, Array1<f64>), Box<dyn Error>> {
+    dataset.check_rows()?;
+    let schema = &dataset.schema;
+    let target = schema.column(&schema.target);
+    let j = dataset.position(&schema.target);
+    let (target, j) = match (target, j) {
+        (Some(target), Some(j)) => (target, j),
+        _ => return Err(format!("The target column {} is missing", schema.target).into()),
+    };
+    let y = dataset
+        .rows
+        .iter()
+        .map(|row| {
+            if multiclass {
+                target.encode_class(&row[j])
+            } else {
+                target.encode_target(&row[j])
+            }
+        })
+        .collect::<Result<Array1<f64>, _>>()?;
+    let X = feature_matrix(dataset, schema)?;
+
+    let mut dropped = Vec::new();
+    let mut k = 0;
+    for column in schema.feature_columns() {
+        if column.impute == Imputation::DropRow {
+            dropped.push(k);
+        }
+        k += column.width();
+    }
+    let kept: Vec<usize> = (0..X.nrows())
+        .filter(|&i| dropped.iter().all(|&k| !X[[i, k]].is_nan()))
+        .collect();
+
+    Ok((X.select(Axis(0), &kept), y.select(Axis(0), &kept)))
+}
+
+// Build the feature matrix and the target of a training dataset, imputed with the
+// statistics of the whole dataset
+pub fn training_data(dataset: &Dataset) -> Result<(Array2<f64>, Array1<f64>), Box<dyn Error>> {
+    let (X, y) = labeled_features(dataset, false)?;
+    let X = Imputer::fit(&X, &dataset.schema).transform(&X);
+    Ok((X, y))
+}
+
+// Most classes of a multiclass target, the model having one column of coefficients
+// per class up to the largest class index
+const MAX_CLASSES: usize = 100;
+
+// Number of classes of a target: the categories of a categorical column, up to the
+// largest class index otherwise, and at least 2
+fn class_count(dataset: &Dataset, y: &Array1<f64>) -> Result<usize, Box<dyn Error>> {
+    let largest = y.iter().fold(0.0, |m: f64, &v| m.max(v));
+    let categories = dataset
+        .schema
+        .column(&dataset.schema.target)
+        .map_or(0, |c| c.categories.len());
+    if largest >= MAX_CLASSES as f64 || categories > MAX_CLASSES {
+        return Err(format!(
+            "The target {} has more than {} classes",
+            dataset.schema.target, MAX_CLASSES
+        )
+        .into());
+    }
+    Ok(categories.max(largest as usize + 1).max(2))
+}
+
+// Train and test sets of a training dataset, with the imputer and the scaler fitted
+// on the train set
+pub struct TrainingSplit {
+    // Classes of the target of the whole dataset
+    pub classes: usize,
+    pub imputer: Imputer,
+    pub scaler: Scaler,
+    pub X_train: Array2<f64>,
+    pub y_train: Array1<f64>,
+    pub X_test: Array2<f64>,
+    pub y_test: Array1<f64>,
+}
+
+// Train and test sets of the rows of `split`, imputed and scaled with the
+// statistics of the train set only, with an intercept column if `intercept`
+fn prepare_split(
+    X: &Array2<f64>,
+    y: &Array1<f64>,
+    schema: &Schema,
+    split: &Split,
+    fit: fn(&Array2<f64>) -> Scaler,
+    intercept: bool,
+    classes: usize,
+) -> TrainingSplit {
+    let X_train = X.select(Axis(0), &split.train);
+    let y_train = y.select(Axis(0), &split.train);
+    let X_test = X.select(Axis(0), &split.test);
+    let y_test = y.select(Axis(0), &split.test);
+
+    let imputer = Imputer::fit(&X_train, schema);
+    let X_train = imputer.transform(&X_train);
+    let X_test = imputer.transform(&X_test);
+
+    let scaler = fit(&X_train);
+    let (mut X_train, mut X_test) = (scaler.transform(&X_train), scaler.transform(&X_test));
+    if intercept {
+        X_train = add_intercept(&X_train);
+        X_test = add_intercept(&X_test);
+    }
+
+    TrainingSplit {
+        classes,
+        imputer,
+        scaler,
+        X_train,
+        y_train,
+        X_test,
+        y_test,
+    }
+}
+
+// Split a training dataset into train and test sets with `options`
+pub fn clean_dataset(
+    dataset: &Dataset,
+    fit: fn(&Array2<f64>) -> Scaler,
+    intercept: bool,
+    multiclass: bool,
+    options: &SplitOptions,
+) -> Result<TrainingSplit, Box<dyn Error>> {
+    let (X, y) = labeled_features(dataset, multiclass)?;
+    let split = options.holdout(&y)?;
+    let classes = class_count(dataset, &y)?;
+    Ok(prepare_split(
+        &X,
+        &y,
+        &dataset.schema,
+        &split,
+        fit,
+        intercept,
+        classes,
+    ))
+}
+
+// Train and test sets of every fold of a cross-validation, each imputed and scaled
+// with the statistics of its own train set
+pub fn cross_validation_splits(
+    dataset: &Dataset,
+    fit: fn(&Array2<f64>) -> Scaler,
+    intercept: bool,
+    multiclass: bool,
+    options: &SplitOptions,
+    folds: usize,
+) -> Result<Vec<TrainingSplit>, Box<dyn Error>> {
+    let (X, y) = labeled_features(dataset, multiclass)?;
+    let classes = class_count(dataset, &y)?;
+    Ok(options
+        .k_fold(&y, folds)?
+        .iter()
+        .map(|split| prepare_split(&X, &y, &dataset.schema, split, fit, intercept, classes))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::Column;
+    use ndarray::array;
+
+    fn column(name: &str, kind: ColumnType, impute: Imputation) -> Column {
+        Column {
+            name: name.to_string(),
+            kind,
+            nullable: true,
+            categories: Vec::new(),
+            impute,
+        }
+    }
+
+    fn close(a: &Array2<f64>, b: &Array2<f64>) -> bool {
+        a.iter().zip(b.iter()).all(|(x, y)| (x - y).abs() < 1e-12)
+    }
+
+    #[test]
+    fn scalers() {
+        let X = array![[1.0, 10.0], [2.0, 20.0], [3.0, 60.0]];
+
+        let scaled = Scaler::fit_min_max(&X).transform(&X);
+        assert!(close(&scaled, &array![[0.0, 0.0], [0.5, 0.2], [1.0, 1.0]]));
+
+        let scaled = Scaler::fit_z_score(&X).transform(&X);
+        for column in scaled.columns() {
+            assert!(column.mean().unwrap().abs() < 1e-12);
+            assert!((column.std(0.0) - 1.0).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn persisted_scaler_round_trip() {
+        let X = array![[1.0, -4.0], [5.0, 2.0], [0.5, 8.0]];
+        for scaler in [Scaler::fit_min_max(&X), Scaler::fit_z_score(&X)] {
+            let json = serde_json::to_string(&scaler).unwrap();
+            let restored: Scaler = serde_json::from_str(&json).unwrap();
+            assert_eq!(restored, scaler);
+            assert!(close(&restored.transform(&X), &scaler.transform(&X)));
+
+            // The unscaled coefficients give the same logits on the raw features
+            let theta = array![0.5, 2.0, -1.0];
+            let scaled = add_intercept(&scaler.transform(&X)).dot(&theta);
+            let unscaled = add_intercept(&X).dot(&scaler.unscale(&theta));
+            for (a, b) in scaled.iter().zip(unscaled.iter()) {
+                assert!((a - b).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn constant_columns_are_only_shifted() {
+        let X = array![[3.0, 1.0], [3.0, 2.0], [3.0, 4.0]];
+        for scaler in [Scaler::fit_min_max(&X), Scaler::fit_z_score(&X)] {
+            let scaled = scaler.transform(&X);
+            assert!(scaled.iter().all(|v| v.is_finite()));
+            assert!(scaled.column(0).iter().all(|&v| v == 0.0));
+            assert!(scaler
+                .unscale(&array![1.0, 1.0, 1.0])
+                .iter()
+                .all(|v| v.is_finite()));
+        }
+        if let Scaler::ZScore { std, .. } = Scaler::fit_z_score(&X) {
+            assert_eq!(std[0], 0.0);
+        }
+    }
+
+    #[test]
+    fn imputation_strategies() {
+        let nan = f64::NAN;
+        let schema = Schema {
+            columns: vec![
+                column("mean", ColumnType::Float, Imputation::Mean),
+                column("median", ColumnType::Float, Imputation::Median),
+                column("mode", ColumnType::Integer, Imputation::Mode),
+                column("constant", ColumnType::Float, Imputation::Constant(-1.0)),
+                column("target", ColumnType::Integer, Imputation::Mean),
+            ],
+            target: "target".to_string(),
+        };
+        let X = array![
+            [1.0, 1.0, 2.0, nan],
+            [2.0, 2.0, 2.0, 5.0],
+            [9.0, 100.0, 7.0, 6.0],
+            [nan, nan, nan, 7.0],
+        ];
+
+        let imputer = Imputer::fit(&X, &schema);
+        assert_eq!(imputer.fill, vec![4.0, 2.0, 2.0, -1.0]);
+        let imputed = imputer.transform(&X);
+        assert_eq!(imputed.row(3).to_vec(), vec![4.0, 2.0, 2.0, 7.0]);
+        assert_eq!(imputed[[0, 3]], -1.0);
+        assert_eq!(imputed.row(2), X.row(2));
+    }
+
+    #[test]
+    fn all_missing_columns() {
+        let nan = f64::NAN;
+        let schema = Schema {
+            columns: vec![
+                column("mean", ColumnType::Float, Imputation::Mean),
+                column("median", ColumnType::Float, Imputation::Median),
+                column("mode", ColumnType::Float, Imputation::Mode),
+            ],
+            target: String::new(),
+        };
+        let X = array![[nan, nan, nan], [nan, nan, nan]];
+        let imputed = Imputer::fit(&X, &schema).transform(&X);
+        assert!(imputed.iter().all(|&v| v == 0.0));
+
+        // Scaled afterwards as a constant column
+        let scaled = Scaler::fit_z_score(&imputed).transform(&imputed);
+        assert!(scaled.iter().all(|v| v.is_finite()));
+    }
+
+    #[test]
+    fn categorical_mode() {
+        let mut colour = column("colour", ColumnType::Categorical, Imputation::Mode);
+        colour.categories = vec!["red".to_string(), "green".to_string(), "blue".to_string()];
+        let schema = Schema {
+            columns: vec![colour],
+            target: String::new(),
+        };
+        let nan = f64::NAN;
+        let X = array![
+            [0.0, 1.0, 0.0],
+            [0.0, 0.0, 1.0],
+            [0.0, 1.0, 0.0],
+            [nan, nan, nan]
+        ];
+        let imputer = Imputer::fit(&X, &schema);
+        assert_eq!(imputer.fill, vec![0.0, 1.0, 0.0]);
+    }
+}
@@ -0,0 +1,517 @@
+// Oblivious transfer between the two parties of a secure computation.
+//
+// A small number of base OTs (the "simplest OT" of Chou and Orlandi, in the
+// 2048-bit MODP group of RFC 3526) are extended to as many OTs as needed with
+// the IKNP extension. The data owner (party 0) is always the receiver of the
+// extended OTs and the model owner (party 1) always the sender, which means
+// the roles are swapped for the base OTs. SHA-256 is used both as the random
+// oracle and, in counter mode, as the PRG stretching the base OT seeds.
+
+use crate::mpc::{prg, random_u64s, MpcResult, Transport};
+use num_bigint::BigUint;
+use ring::digest::{Context, SHA256};
+
+// Security parameter: number of base OTs
+const KAPPA: usize = 128;
+
+// Maximum number of OTs extended at once, bounding the size of the messages
+const MAX_BATCH: usize = 1 << 20;
+
+const MODP_2048: &str = "FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD1\
+29024E088A67CC74020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B\
+302B0A6DF25F14374FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B\
+0BFF5CB6F406B7EDEE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3D\
+C2007CB8A163BF0598DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD96\
+1C62F356208552BB9ED529077096966D670C354E4ABC9804F1746C08CA18217C\
+32905E462E36CE3BE39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9\
+DE2BCBF6955817183995497CEA956AE515D2261898FA051015728E5A8AACAA68\
+FFFFFFFFFFFFFFFF";
+
+// Number of u64 words of a serialized group element
+const ELEMENT_WORDS: usize = 2048 / 64;
+
+fn modulus() -> BigUint {
+    BigUint::parse_bytes(MODP_2048.as_bytes(), 16).unwrap()
+}
+
+fn random_exponent() -> BigUint {
+    let words = random_u64s(4);
+    BigUint::from_bytes_le(
+        &words
+            .iter()
+            .flat_map(|w| w.to_le_bytes())
+            .collect::<Vec<u8>>(),
+    )
+}
+
+fn element_to_words(element: &BigUint) -> Vec<u64> {
+    let mut words = element.to_u64_digits();
+    words.resize(ELEMENT_WORDS, 0);
+    words
+}
+
+fn words_to_element(words: &[u64]) -> BigUint {
+    BigUint::from_bytes_le(
+        &words
+            .iter()
+            .flat_map(|w| w.to_le_bytes())
+            .collect::<Vec<u8>>(),
+    )
+}
+
+fn hash_element(element: &BigUint) -> [u8; 32] {
+    let mut context = Context::new(&SHA256);
+    context.update(&element.to_bytes_le());
+    context.finish().as_ref().try_into().unwrap()
+}
+
+// Random oracle: hash the row of an extended OT into `words` words
+fn hash_row(index: u64, row: u128, words: usize) -> Vec<u64> {
+    let mut output = Vec::with_capacity(words + 4);
+    let mut block = 0u64;
+    while output.len() < words {
+        let mut context = Context::new(&SHA256);
+        context.update(&index.to_le_bytes());
+        context.update(&row.to_le_bytes());
+        context.update(&block.to_le_bytes());
+        let digest = context.finish();
+        output.extend(
+            digest
+                .as_ref()
+                .chunks_exact(8)
+                .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap())),
+        );
+        block += 1;
+    }
+    output.truncate(words);
+    output
+}
+
+// Transpose KAPPA columns of `words` words into 64 * words rows of KAPPA bits
+fn transpose(columns: &[Vec<u64>], words: usize) -> Vec<u128> {
+    let mut rows = vec![0u128; words * 64];
+    for (i, column) in columns.iter().enumerate() {
+        for (w, &word) in column.iter().enumerate() {
+            let mut word = word;
+            while word != 0 {
+                let bit = word.trailing_zeros() as usize;
+                rows[w * 64 + bit] |= 1u128 << i;
+                word &= word - 1;
+            }
+        }
+    }
+    rows
+}
+
+fn bits_to_words(bits: &[bool], words: usize) -> Vec<u64> {
+    let mut packed = vec![0u64; words];
+    for (j, &bit) in bits.iter().enumerate() {
+        if bit {
+            packed[j / 64] |= 1u64 << (j % 64);
+        }
+    }
+    packed
+}
+
+pub enum OtExtension {
+    // Data owner: both seeds of every base OT
+    Receiver {
+        seeds: Vec<([u8; 32], [u8; 32])>,
+        offset: usize,
+        counter: u64,
+    },
+    // Model owner: the base OT choice bits and the chosen seeds
+    Sender {
+        delta: u128,
+        seeds: Vec<[u8; 32]>,
+        offset: usize,
+        counter: u64,
+    },
+}
+
+impl OtExtension {
+    // Run the base OTs with the other party
+    pub async fn setup(id: usize, transport: &mut dyn Transport) -> MpcResult<Self> {
+        let p = modulus();
+        let g = BigUint::from(2u32);
+
+        if id == 0 {
+            // Base OT sender
+            let a = random_exponent();
+            let big_a = g.modpow(&a, &p);
+            transport.exchange(element_to_words(&big_a)).await?;
+            let message = transport.exchange(Vec::new()).await?;
+            if message.len() != KAPPA * ELEMENT_WORDS {
+                return Err("Unexpected size of the base OT message".into());
+            }
+
+            // B / A = A^(p - 2) . B
+            let a_inverse = big_a.modpow(&(&p - 2u32), &p);
+            let seeds = message
+                .chunks_exact(ELEMENT_WORDS)
+                .map(|words| {
+                    let big_b = words_to_element(words);
+                    let k0 = hash_element(&big_b.modpow(&a, &p));
+                    let k1 = hash_element(&((&big_b * &a_inverse) % &p).modpow(&a, &p));
+                    (k0, k1)
+                })
+                .collect();
+
+            Ok(OtExtension::Receiver {
+                seeds,
+                offset: 0,
+                counter: 0,
+            })
+        } else {
+            // Base OT receiver, the choice bits make up the IKNP correlation
+            let message = transport.exchange(Vec::new()).await?;
+            if message.len() != ELEMENT_WORDS {
+                return Err("Unexpected size of the base OT message".into());
+            }
+            let big_a = words_to_element(&message);
+            let delta_words = random_u64s(2);
+            let delta = delta_words[0] as u128 | (delta_words[1] as u128) << 64;
+
+            let mut reply = Vec::with_capacity(KAPPA * ELEMENT_WORDS);
+            let mut seeds = Vec::with_capacity(KAPPA);
+            for i in 0..KAPPA {
+                let b = random_exponent();
+                let mut big_b = g.modpow(&b, &p);
+                if (delta >> i) & 1 == 1 {
+                    big_b = (big_b * &big_a) % &p;
+                }
+                reply.extend(element_to_words(&big_b));
+                seeds.push(hash_element(&big_a.modpow(&b, &p)));
+            }
+            transport.exchange(reply).await?;
+
+            Ok(OtExtension::Sender {
+                delta,
+                seeds,
+                offset: 0,
+                counter: 0,
+            })
+        }
+    }
+
+    // Receiver side of `choices.len()` correlated OTs: OT j outputs
+    // `lengths[j]` words equal to x_j + choices[j] * correlation_j, the sender holding -x_j
+    pub async fn receive_correlated(
+        &mut self,
+        transport: &mut dyn Transport,
+        choices: &[bool],
+        lengths: &[usize],
+    ) -> MpcResult<Vec<u64>> {
+        let mut output = Vec::new();
+        for (choices, lengths) in choices.chunks(MAX_BATCH).zip(lengths.chunks(MAX_BATCH)) {
+            output.extend(self.receive_batch(transport, choices, lengths).await?);
+        }
+        Ok(output)
+    }
+
+    // Sender side of the correlated OTs, OT j carrying the correlation made of
+    // the next `lengths[j]` words of `correlations`. Returns the sender's output -x.
+    pub async fn send_correlated(
+        &mut self,
+        transport: &mut dyn Transport,
+        correlations: &[u64],
+        lengths: &[usize],
+    ) -> MpcResult<Vec<u64>> {
+        let mut output = Vec::new();
+        let mut position = 0;
+        for lengths in lengths.chunks(MAX_BATCH) {
+            let size: usize = lengths.iter().sum();
+            if position + size > correlations.len() {
+                return Err("Not enough correlations for the OTs".into());
+            }
+            let batch = &correlations[position..position + size];
+            output.extend(self.send_batch(transport, batch, lengths).await?);
+            position += size;
+        }
+        Ok(output)
+    }
+
+    // Receiver side of `choices.len()` OTs of messages of `length` words: OT j
+    // outputs the message chosen by choices[j]
+    pub async fn receive_chosen(
+        &mut self,
+        transport: &mut dyn Transport,
+        choices: &[bool],
+        length: usize,
+    ) -> MpcResult<Vec<u64>> {
+        let mut output = Vec::with_capacity(choices.len() * length);
+        for choices in choices.chunks(MAX_BATCH) {
+            let (rows, counter) = self.extend_receiver(transport, choices).await?;
+            let masked = transport.exchange(Vec::new()).await?;
+            if masked.len() != 2 * choices.len() * length {
+                return Err("Unexpected size of the OT message".into());
+            }
+            for (j, &choice) in choices.iter().enumerate() {
+                let x = hash_row(counter + j as u64, rows[j], length);
+                let start = (2 * j + choice as usize) * length;
+                output.extend(
+                    x.iter()
+                        .zip(&masked[start..start + length])
+                        .map(|(x, m)| x ^ m),
+                );
+            }
+        }
+        Ok(output)
+    }
+
+    // Sender side of the OTs of messages of `length` words, OT j transferring the
+    // j-th message of `messages0` or of `messages1`
+    pub async fn send_chosen(
+        &mut self,
+        transport: &mut dyn Transport,
+        messages0: &[u64],
+        messages1: &[u64],
+        length: usize,
+    ) -> MpcResult<()> {
+        let count = messages0.len() / length.max(1);
+        let mut start = 0;
+        while start < count {
+            let batch = MAX_BATCH.min(count - start);
+            let (rows, delta, counter) = self.extend_sender(transport, batch).await?;
+            let mut masked = Vec::with_capacity(2 * batch * length);
+            for (j, row) in rows.iter().take(batch).enumerate() {
+                let words = (start + j) * length..(start + j + 1) * length;
+                let x0 = hash_row(counter + j as u64, *row, length);
+                let x1 = hash_row(counter + j as u64, row ^ delta, length);
+                masked.extend(x0.iter().zip(&messages0[words.clone()]).map(|(x, m)| x ^ m));
+                masked.extend(x1.iter().zip(&messages1[words]).map(|(x, m)| x ^ m));
+            }
+            transport.exchange(masked).await?;
+            start += batch;
+        }
+        Ok(())
+    }
+
+    // IKNP extension on the receiver side: returns the rows t_j = q_j xor
+    // choices[j] * delta and the index of the first OT
+    async fn extend_receiver(
+        &mut self,
+        transport: &mut dyn Transport,
+        choices: &[bool],
+    ) -> MpcResult<(Vec<u128>, u64)> {
+        let (seeds, offset, counter) = match self {
+            OtExtension::Receiver {
+                seeds,
+                offset,
+                counter,
+            } => (seeds, offset, counter),
+            OtExtension::Sender { .. } => return Err("The model owner cannot receive OTs".into()),
+        };
+
+        // Round the number of OTs up to the PRG block size (256 bits)
+        let words = choices.len().div_ceil(256) * 4;
+        let r = bits_to_words(choices, words);
+
+        let mut columns = Vec::with_capacity(KAPPA);
+        let mut u = Vec::with_capacity(KAPPA * words);
+        for (k0, k1) in seeds.iter() {
+            let t = prg(k0, *offset, words);
+            let masked = prg(k1, *offset, words);
+            u.extend(
+                t.iter()
+                    .zip(masked.iter())
+                    .zip(r.iter())
+                    .map(|((t, m), r)| t ^ m ^ r),
+            );
+            columns.push(t);
+        }
+        *offset += words;
+        transport.exchange(u).await?;
+
+        let first = *counter;
+        *counter += choices.len() as u64;
+        Ok((transpose(&columns, words), first))
+    }
+
+    // IKNP extension on the sender side: returns the rows q_j, delta and the index
+    // of the first OT
+    async fn extend_sender(
+        &mut self,
+        transport: &mut dyn Transport,
+        count: usize,
+    ) -> MpcResult<(Vec<u128>, u128, u64)> {
+        let (delta, seeds, offset, counter) = match self {
+            OtExtension::Sender {
+                delta,
+                seeds,
+                offset,
+                counter,
+            } => (*delta, seeds, offset, counter),
+            OtExtension::Receiver { .. } => return Err("The data owner cannot send OTs".into()),
+        };
+
+        let words = count.div_ceil(256) * 4;
+        let u = transport.exchange(Vec::new()).await?;
+        if u.len() != KAPPA * words {
+            return Err("Unexpected size of the OT extension message".into());
+        }
+
+        // q_i = t_i xor delta_i . r
+        let columns: Vec<Vec<u64>> = seeds
+            .iter()
+            .enumerate()
+            .map(|(i, seed)| {
+                let q = prg(seed, *offset, words);
+                if (delta >> i) & 1 == 1 {
+                    q.iter()
+                        .zip(&u[i * words..(i + 1) * words])
+                        .map(|(q, u)| q ^ u)
+                        .collect()
+                } else {
+                    q
+                }
+            })
+            .collect();
+        *offset += words;
+
+        let first = *counter;
+        *counter += count as u64;
+        Ok((transpose(&columns, words), delta, first))
+    }
+
+    async fn receive_batch(
+        &mut self,
+        transport: &mut dyn Transport,
+        choices: &[bool],
+        lengths: &[usize],
+    ) -> MpcResult<Vec<u64>> {
+        let (rows, counter) = self.extend_receiver(transport, choices).await?;
+        let tau = transport.exchange(Vec::new()).await?;
+        if tau.len() != lengths.iter().sum::<usize>() {
+            return Err("Unexpected size of the correlated OT message".into());
+        }
+
+        let mut output = Vec::with_capacity(tau.len());
+        let mut position = 0;
+        for (j, (&choice, &length)) in choices.iter().zip(lengths.iter()).enumerate() {
+            let x = hash_row(counter + j as u64, rows[j], length);
+            if choice {
+                output.extend(
+                    x.iter()
+                        .zip(&tau[position..position + length])
+                        .map(|(x, t)| x.wrapping_sub(*t)),
+                );
+            } else {
+                output.extend(x);
+            }
+            position += length;
+        }
+
+        Ok(output)
+    }
+
+    async fn send_batch(
+        &mut self,
+        transport: &mut dyn Transport,
+        correlations: &[u64],
+        lengths: &[usize],
+    ) -> MpcResult<Vec<u64>> {
+        let (rows, delta, counter) = self.extend_sender(transport, lengths.len()).await?;
+        let mut tau = Vec::with_capacity(correlations.len());
+        let mut output = Vec::with_capacity(correlations.len());
+        let mut position = 0;
+        for (j, &length) in lengths.iter().enumerate() {
+            let x0 = hash_row(counter + j as u64, rows[j], length);
+            let x1 = hash_row(counter + j as u64, rows[j] ^ delta, length);
+            for w in 0..length {
+                tau.push(
+                    x1[w]
+                        .wrapping_sub(x0[w])
+                        .wrapping_sub(correlations[position + w]),
+                );
+                output.push(x0[w].wrapping_neg());
+            }
+            position += length;
+        }
+
+        transport.exchange(tau).await?;
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mpc::testing::transports;
+
+    // Base OTs of the data owner (receiver) and of the model owner (sender)
+    async fn extensions() -> (OtExtension, impl Transport, OtExtension, impl Transport) {
+        let (mut transport0, mut transport1) = transports();
+        let (receiver, sender) = tokio::join!(
+            OtExtension::setup(0, &mut transport0),
+            OtExtension::setup(1, &mut transport1)
+        );
+        (receiver.unwrap(), transport0, sender.unwrap(), transport1)
+    }
+
+    fn random_choices(n: usize) -> Vec<bool> {
+        random_u64s(n).iter().map(|v| v & 1 == 1).collect()
+    }
+
+    #[tokio::test]
+    async fn correlated_ots() {
+        let (mut receiver, mut transport0, mut sender, mut transport1) = extensions().await;
+
+        // Two rounds, to also check the extensions after the first one
+        for n in [300, 5] {
+            let choices = random_choices(n);
+            let lengths: Vec<usize> = (0..n).map(|j| 1 + j % 3).collect();
+            let correlations = random_u64s(lengths.iter().sum());
+            let (received, sent) = tokio::join!(
+                receiver.receive_correlated(&mut transport0, &choices, &lengths),
+                sender.send_correlated(&mut transport1, &correlations, &lengths)
+            );
+            let (received, sent) = (received.unwrap(), sent.unwrap());
+            assert_eq!(received.len(), correlations.len());
+            assert_eq!(sent.len(), correlations.len());
+
+            // x + choice * correlation on one side, -x on the other
+            let mut position = 0;
+            for (&choice, &length) in choices.iter().zip(lengths.iter()) {
+                for k in position..position + length {
+                    let expected = if choice { correlations[k] } else { 0 };
+                    assert_eq!(received[k].wrapping_add(sent[k]), expected);
+                }
+                position += length;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn chosen_ots() {
+        let (mut receiver, mut transport0, mut sender, mut transport1) = extensions().await;
+
+        let (n, length) = (100, 2);
+        let choices = random_choices(n);
+        let messages0 = random_u64s(n * length);
+        let messages1 = random_u64s(n * length);
+        let (received, sent) = tokio::join!(
+            receiver.receive_chosen(&mut transport0, &choices, length),
+            sender.send_chosen(&mut transport1, &messages0, &messages1, length)
+        );
+        sent.unwrap();
+        let received = received.unwrap();
+
+        for (j, &choice) in choices.iter().enumerate() {
+            let messages = if choice { &messages1 } else { &messages0 };
+            assert_eq!(
+                received[j * length..(j + 1) * length],
+                messages[j * length..(j + 1) * length]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn roles_are_enforced() {
+        let (mut receiver, mut transport0, _, _) = extensions().await;
+        assert!(receiver
+            .send_correlated(&mut transport0, &[1], &[1])
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,323 @@
+// Backends of LaunchPrediction, one for every PredictionMode
+
+use crate::at_rest::{self, MasterKey};
+use crate::file::{CkksCiphertext, ComparisonProtocol, RequestPrediction, ResponsePrediction};
+use crate::normalize::Imputer;
+use crate::schema::Schema;
+use crate::training::Multiclass;
+use crate::{ckks, he, metrics, mpc, normalize, training};
+use crate::{computation_id, spawn_party, triple_provider, MpcChannel};
+use ndarray::{Array1, Array2, Axis};
+use num_bigint::BigUint;
+use tonic::transport::Channel;
+use tonic::Status;
+
+// Model a prediction is computed with
+pub struct Model {
+    // Coefficients on the unscaled features, the intercept first if any, one column
+    // for a binary model and one per class otherwise
+    pub coefficients: Array2<f64>,
+    pub multiclass: Multiclass,
+    // Schema of the training dataset of a stored model, which gives the feature
+    // layout of the predictions
+    pub schema: Option<Schema>,
+    // Values filling the missing features in the predictions, fitted on the
+    // training dataset of a stored model
+    pub imputer: Option<Imputer>,
+    // Decision threshold on the probability of the positive class
+    pub threshold: f64,
+}
+
+#[tonic::async_trait]
+pub trait SecurePredictor: Send + Sync {
+    async fn predict(
+        &self,
+        request: RequestPrediction,
+        model: Model,
+    ) -> Result<ResponsePrediction, Status>;
+}
+
+impl Model {
+    // Coefficients of a binary model, the only ones the predictions on encrypted or
+    // secret-shared data support
+    fn binary(&self) -> Result<Array1<f64>, Status> {
+        if self.multiclass != Multiclass::Binary || self.coefficients.ncols() != 1 {
+            return Err(Status::invalid_argument(
+                "Only binary models can predict on encrypted or secret-shared data",
+            ));
+        }
+        Ok(self.coefficients.column(0).to_owned())
+    }
+
+    // Feature layout of the model, without its coefficients
+    pub fn layout(&self) -> Result<ResponsePrediction, Status> {
+        let encode = |value: Option<Result<String, serde_json::Error>>| {
+            value
+                .unwrap_or_else(|| Ok(String::new()))
+                .map_err(|e| Status::internal(format!("Failed to encode the layout: {}", e)))
+        };
+        Ok(ResponsePrediction {
+            schema: encode(self.schema.as_ref().map(serde_json::to_string))?,
+            imputer: encode(self.imputer.as_ref().map(serde_json::to_string))?,
+            threshold: self.threshold,
+            coefficients: self.coefficients.nrows() as u32,
+            ..Default::default()
+        })
+    }
+}
+
+// A model with one more coefficient than there are features carries an intercept
+fn check_features(coefficients: usize, features: usize) -> Result<(), Status> {
+    if coefficients != features && coefficients != features + 1 {
+        return Err(Status::invalid_argument(format!(
+            "The model has {} coefficients but the dataset has {} features",
+            coefficients, features
+        )));
+    }
+    Ok(())
+}
+
+// Prediction on the uploaded prediction dataset, computed in the clear
+pub struct PlaintextPredictor {
+    pub prediction_file: String,
+    // Key the prediction dataset is encrypted with
+    pub master_key: MasterKey,
+}
+
+#[tonic::async_trait]
+impl SecurePredictor for PlaintextPredictor {
+    async fn predict(
+        &self,
+        _request: RequestPrediction,
+        model: Model,
+    ) -> Result<ResponsePrediction, Status> {
+        if self.prediction_file.is_empty() {
+            return Ok(ResponsePrediction {
+                message: "The testing dataset is missing".to_string(),
+                ..Default::default()
+            });
+        }
+
+        let content = at_rest::read_encrypted_csv(&self.prediction_file, &self.master_key)
+            .map_err(|e| Status::internal(format!("Failed to read CSV file: {}", e)))?;
+
+        let schema = model.schema.as_ref().unwrap_or(&content.schema);
+        let mut X = normalize::prediction_features(&content, schema, model.imputer.as_ref())
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let threshold = model.threshold;
+        let coefficients = &model.coefficients;
+        check_features(coefficients.nrows(), X.ncols())?;
+        if coefficients.nrows() == X.ncols() + 1 {
+            X = normalize::add_intercept(&X);
+        }
+
+        let proba = training::class_proba(coefficients, &X, model.multiclass);
+        let labels = metrics::labels(&proba, threshold);
+        // The positive class of a binary model, the predicted class otherwise
+        let probabilities = match model.multiclass {
+            Multiclass::Binary => proba.column(1).to_vec(),
+            _ => proba
+                .axis_iter(Axis(0))
+                .zip(labels.iter())
+                .map(|(row, &label)| row[label as usize])
+                .collect(),
+        };
+        Ok(ResponsePrediction {
+            labels,
+            probabilities,
+            threshold,
+            class_probabilities: proba.iter().copied().collect(),
+            classes: proba.ncols() as u32,
+            ..Default::default()
+        })
+    }
+}
+
+// Model owner side of a prediction on the client's secret-shared dataset
+pub struct SecretSharedPredictor<'a> {
+    pub dealer: Option<Channel>,
+    pub mpc_channel: &'a tokio::sync::Mutex<Option<MpcChannel>>,
+}
+
+#[tonic::async_trait]
+impl SecurePredictor for SecretSharedPredictor<'_> {
+    async fn predict(
+        &self,
+        request: RequestPrediction,
+        model: Model,
+    ) -> Result<ResponsePrediction, Status> {
+        let threshold = model.threshold;
+        let model = model.binary()?;
+        let rows = request.rows as usize;
+        check_features(model.len(), request.features as usize)?;
+        let coefficients = model.len() as u32;
+        let theta = model.to_vec();
+
+        let comparisons = match request.comparisons() {
+            ComparisonProtocol::Adder => mpc::Comparisons::Adder,
+            ComparisonProtocol::GarbledCircuit => mpc::Comparisons::GarbledCircuit,
+        };
+
+        let computation_id = computation_id();
+        let triples = triple_provider(&self.dealer, request.triples(), &computation_id)?;
+
+        // The model owner only sees shares: its shares of the probabilities and
+        // labels are sent to the data owner, the result is never reconstructed,
+        // logged or stored on the server
+        let channel = spawn_party(triples, move |mut party| async move {
+            party.comparisons = comparisons;
+            let z1 = mpc::linear_model_owner(&mut party, &theta, rows).await?;
+            let (probabilities, labels) = mpc::classify(&mut party, &z1, threshold).await?;
+            let mut outputs = probabilities;
+            outputs.extend(labels);
+            party.reveal_to(0, &outputs).await?;
+            Ok(())
+        });
+        *self.mpc_channel.lock().await = Some(channel);
+
+        Ok(ResponsePrediction {
+            coefficients,
+            computation_id,
+            threshold,
+            ..Default::default()
+        })
+    }
+}
+
+// Encrypted logits of the client's Paillier-encrypted dataset. The server never
+// holds the private key: it only learns the dimensions of the dataset
+pub struct PaillierPredictor;
+
+#[tonic::async_trait]
+impl SecurePredictor for PaillierPredictor {
+    async fn predict(
+        &self,
+        request: RequestPrediction,
+        model: Model,
+    ) -> Result<ResponsePrediction, Status> {
+        let threshold = model.threshold;
+        let model = model.binary()?;
+        let rows = request.rows as usize;
+        let features = request.features as usize;
+        check_features(model.len(), features)?;
+        let query = request
+            .paillier
+            .ok_or_else(|| Status::invalid_argument("The encrypted dataset is missing"))?;
+        if query.ciphertexts.len() != rows * features {
+            return Err(Status::invalid_argument(format!(
+                "Expected {} ciphertexts, got {}",
+                rows * features,
+                query.ciphertexts.len()
+            )));
+        }
+
+        let key = he::PublicKey::new(BigUint::from_bytes_be(&query.modulus));
+        if !he::is_valid_modulus(&key.n) {
+            return Err(Status::invalid_argument(format!(
+                "The Paillier modulus must be odd, not a square and have at least {} bits",
+                he::MIN_KEY_BITS
+            )));
+        }
+        let ciphertexts: Vec<BigUint> = query
+            .ciphertexts
+            .iter()
+            .map(|c| BigUint::from_bytes_be(c))
+            .collect();
+        let coefficients = model.len() as u32;
+        let theta = model.to_vec();
+
+        // Modular exponentiations on 4096-bit numbers, kept off the async runtime
+        let logits = tokio::task::spawn_blocking(move || {
+            ciphertexts
+                .chunks(features.max(1))
+                .take(rows)
+                .map(|row| key.dot(row, &theta).map(|c| c.to_bytes_be()))
+                .collect::<Option<Vec<Vec<u8>>>>()
+        })
+        .await
+        .map_err(|e| Status::internal(format!("Homomorphic evaluation failed: {}", e)))?
+        .ok_or_else(|| Status::invalid_argument("Invalid Paillier ciphertext"))?;
+
+        Ok(ResponsePrediction {
+            coefficients,
+            ciphertexts: logits,
+            threshold,
+            ..Default::default()
+        })
+    }
+}
+
+// Encrypted logits of the client's CKKS-encrypted dataset, a block of rows at a
+// time. The logits are rerandomised with the client's public key and flooded, so
+// that they only reveal the predictions.
+pub struct CkksPredictor;
+
+#[tonic::async_trait]
+impl SecurePredictor for CkksPredictor {
+    async fn predict(
+        &self,
+        request: RequestPrediction,
+        model: Model,
+    ) -> Result<ResponsePrediction, Status> {
+        let threshold = model.threshold;
+        let model = model.binary()?;
+        let rows = request.rows as usize;
+        let features = request.features as usize;
+        check_features(model.len(), features)?;
+        let query = request
+            .ckks
+            .ok_or_else(|| Status::invalid_argument("The encrypted dataset is missing"))?;
+        let blocks = rows.div_ceil(ckks::SLOTS);
+        if query.columns.len() != blocks * features {
+            return Err(Status::invalid_argument(format!(
+                "Expected {} ciphertexts, got {}",
+                blocks * features,
+                query.columns.len()
+            )));
+        }
+
+        let key = query
+            .public_key
+            .map(|k| ckks::PublicKey { b: k.c0, a: k.c1 })
+            .filter(|k| k.is_valid())
+            .ok_or_else(|| Status::invalid_argument("Invalid CKKS public key"))?;
+        let columns: Vec<ckks::Ciphertext> = query
+            .columns
+            .into_iter()
+            .map(|c| ckks::Ciphertext { c0: c.c0, c1: c.c1 })
+            .collect();
+        if !columns.iter().all(|c| c.is_valid()) {
+            return Err(Status::invalid_argument("Invalid CKKS ciphertext"));
+        }
+        let coefficients = model.len() as u32;
+        let theta = model.to_vec();
+        if !ckks::fits(&theta) {
+            return Err(Status::failed_precondition(
+                "The coefficients of the model are too large for CKKS",
+            ));
+        }
+
+        let logits = tokio::task::spawn_blocking(move || {
+            columns
+                .chunks(features.max(1))
+                .take(blocks)
+                .map(|block| {
+                    let logits = ckks::dot(block, &theta, &key);
+                    CkksCiphertext {
+                        c0: logits.c0,
+                        c1: logits.c1,
+                    }
+                })
+                .collect()
+        })
+        .await
+        .map_err(|e| Status::internal(format!("Homomorphic evaluation failed: {}", e)))?;
+
+        Ok(ResponsePrediction {
+            coefficients,
+            logits,
+            threshold,
+            ..Default::default()
+        })
+    }
+}
@@ -0,0 +1,248 @@
+// Resampling of a train set with imbalanced classes, so that every class has as
+// many rows as the largest one (oversampling, SMOTE) or as the smallest one
+// (undersampling). Only the train set is resampled, the test set keeps the actual
+// class proportions.
+
+use crate::split;
+use ndarray::{Array1, Array2, Axis};
+use serde::{Deserialize, Serialize};
+
+pub const NEIGHBORS: usize = 5;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResamplingMethod {
+    #[default]
+    None,
+    // Random copies of the rows of the smaller classes
+    Oversample,
+    // Random subset of the rows of the larger classes
+    Undersample,
+    // Synthetic rows interpolated between a row of a smaller class and one of its
+    // nearest neighbors in the class
+    Smote,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Resampling {
+    pub method: ResamplingMethod,
+    // Neighbors of a row SMOTE interpolates towards
+    pub neighbors: usize,
+    pub seed: u64,
+}
+
+impl Default for Resampling {
+    fn default() -> Self {
+        Resampling {
+            method: ResamplingMethod::None,
+            neighbors: NEIGHBORS,
+            seed: 0,
+        }
+    }
+}
+
+// Uniform in [0, 1)
+fn uniform(state: &mut u64) -> f64 {
+    (split::next_random(state) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn below(n: usize, state: &mut u64) -> usize {
+    (split::next_random(state) % n as u64) as usize
+}
+
+// Rows of every class, in the order the classes appear
+fn classes(y: &Array1<f64>) -> Vec<(f64, Vec<usize>)> {
+    let mut classes: Vec<(f64, Vec<usize>)> = Vec::new();
+    for (i, &label) in y.iter().enumerate() {
+        match classes.iter_mut().find(|(l, _)| *l == label) {
+            Some((_, rows)) => rows.push(i),
+            None => classes.push((label, vec![i])),
+        }
+    }
+    classes
+}
+
+fn distance(a: ndarray::ArrayView1<f64>, b: ndarray::ArrayView1<f64>) -> f64 {
+    a.iter().zip(b.iter()).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+// `count` synthetic rows of the class of `rows`
+fn smote(X: &Array2<f64>, rows: &[usize], count: usize, k: usize, state: &mut u64) -> Array2<f64> {
+    let mut synthetic = Array2::zeros((count, X.ncols()));
+    // Nearest neighbors of every row within the class
+    let neighbors: Vec<Vec<usize>> = rows
+        .iter()
+        .map(|&i| {
+            let mut others: Vec<(f64, usize)> = rows
+                .iter()
+                .filter(|&&j| j != i)
+                .map(|&j| (distance(X.row(i), X.row(j)), j))
+                .collect();
+            others.sort_by(|a, b| a.0.total_cmp(&b.0));
+            others.into_iter().take(k).map(|(_, j)| j).collect()
+        })
+        .collect();
+
+    for mut row in synthetic.rows_mut() {
+        let base = below(rows.len(), state);
+        let origin = X.row(rows[base]);
+        match neighbors[base].len() {
+            // A class of a single row can only be copied
+            0 => row.assign(&origin),
+            n => {
+                let neighbor = X.row(neighbors[base][below(n, state)]);
+                let gap = uniform(state);
+                row.assign(&(&origin + &((&neighbor - &origin) * gap)));
+            }
+        }
+    }
+    synthetic
+}
+
+impl Resampling {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.method == ResamplingMethod::Smote && self.neighbors == 0 {
+            return Err("SMOTE needs at least one neighbor".to_string());
+        }
+        Ok(())
+    }
+
+    // Resampled train set (X, y)
+    pub fn apply(&self, X: &Array2<f64>, y: &Array1<f64>) -> (Array2<f64>, Array1<f64>) {
+        let classes = classes(y);
+        let largest = classes.iter().map(|(_, r)| r.len()).max().unwrap_or(0);
+        let smallest = classes.iter().map(|(_, r)| r.len()).min().unwrap_or(0);
+        let mut state = self.seed;
+
+        match self.method {
+            ResamplingMethod::None => (X.to_owned(), y.to_owned()),
+            ResamplingMethod::Oversample => {
+                let mut kept: Vec<usize> = (0..y.len()).collect();
+                for (_, rows) in classes.iter() {
+                    kept.extend((rows.len()..largest).map(|_| rows[below(rows.len(), &mut state)]));
+                }
+                (X.select(Axis(0), &kept), y.select(Axis(0), &kept))
+            }
+            ResamplingMethod::Undersample => {
+                let mut kept = Vec::with_capacity(smallest * classes.len());
+                for (_, mut rows) in classes {
+                    split::shuffle(&mut rows, &mut state);
+                    kept.extend_from_slice(&rows[..smallest]);
+                }
+                kept.sort_unstable();
+                (X.select(Axis(0), &kept), y.select(Axis(0), &kept))
+            }
+            ResamplingMethod::Smote => {
+                let (mut X_resampled, mut y_resampled) = (X.to_owned(), y.to_vec());
+                for (label, rows) in classes.iter() {
+                    let count = largest - rows.len();
+                    if count == 0 {
+                        continue;
+                    }
+                    let synthetic = smote(X, rows, count, self.neighbors, &mut state);
+                    X_resampled
+                        .append(Axis(0), synthetic.view())
+                        .expect("The synthetic rows have the width of X");
+                    y_resampled.extend(std::iter::repeat_n(*label, count));
+                }
+                (X_resampled, Array1::from(y_resampled))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::{array, s};
+
+    // 6 rows of class 0 around the origin, 2 of class 1 around (10, 10) and 1 of
+    // class 2
+    fn imbalanced() -> (Array2<f64>, Array1<f64>) {
+        let X = array![
+            [0.0, 0.0],
+            [1.0, 0.0],
+            [0.0, 1.0],
+            [1.0, 1.0],
+            [0.5, 0.5],
+            [0.2, 0.8],
+            [10.0, 10.0],
+            [11.0, 12.0],
+            [-5.0, 3.0]
+        ];
+        let y = array![0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 2.0];
+        (X, y)
+    }
+
+    fn count(y: &Array1<f64>, label: f64) -> usize {
+        y.iter().filter(|&&l| l == label).count()
+    }
+
+    #[test]
+    fn smote_output_shape() {
+        let (X, y) = imbalanced();
+        let resampling = Resampling {
+            method: ResamplingMethod::Smote,
+            neighbors: 3,
+            seed: 11,
+        };
+        let (X_resampled, y_resampled) = resampling.apply(&X, &y);
+        assert_eq!(X_resampled.dim(), (18, 2));
+        assert_eq!(y_resampled.len(), 18);
+        for label in [0.0, 1.0, 2.0] {
+            assert_eq!(count(&y_resampled, label), 6);
+        }
+        // The original rows come first, unchanged
+        assert_eq!(X_resampled.slice(s![..9, ..]), X);
+        assert_eq!(y_resampled.slice(s![..9]), y);
+
+        for (row, &label) in X_resampled
+            .rows()
+            .into_iter()
+            .zip(y_resampled.iter())
+            .skip(9)
+        {
+            if label == 1.0 {
+                // On the segment between the two rows of the class
+                let gap = row[0] - 10.0;
+                assert!((0.0..=1.0).contains(&gap));
+                assert!((row[1] - (10.0 + 2.0 * gap)).abs() < 1e-9);
+            } else {
+                // A single row is copied
+                assert_eq!(label, 2.0);
+                assert_eq!(row, X.row(8));
+            }
+        }
+    }
+
+    #[test]
+    fn random_resampling_sizes() {
+        let (X, y) = imbalanced();
+        let oversample = Resampling {
+            method: ResamplingMethod::Oversample,
+            ..Resampling::default()
+        };
+        let (X_resampled, y_resampled) = oversample.apply(&X, &y);
+        assert_eq!(X_resampled.dim(), (18, 2));
+        assert_eq!(count(&y_resampled, 1.0), 6);
+
+        let undersample = Resampling {
+            method: ResamplingMethod::Undersample,
+            ..Resampling::default()
+        };
+        let (X_resampled, y_resampled) = undersample.apply(&X, &y);
+        assert_eq!(X_resampled.dim(), (3, 2));
+        assert_eq!(y_resampled, array![0.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn smote_needs_neighbors() {
+        let resampling = Resampling {
+            method: ResamplingMethod::Smote,
+            neighbors: 0,
+            seed: 0,
+        };
+        assert!(resampling.validate().is_err());
+    }
+}
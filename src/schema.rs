@@ -0,0 +1,318 @@
+// Description of the columns of a tabular dataset, loaded from a JSON or TOML file
+// or inferred from the CSV file itself. For instance:
+//
+// target = "TenYearCHD"
+// [[columns]]
+// name = "age"
+// type = "integer"
+// [[columns]]
+// name = "smoking"
+// type = "categorical"
+// categories = ["never", "former", "current"]
+// nullable = true
+// impute = "mode"
+
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::Path;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnType {
+    Integer,
+    Float,
+    // One of a fixed list of strings, one-hot encoded in the features
+    Categorical,
+}
+
+// How the missing values of a nullable column are filled. The statistics are
+// computed on the training set and stored with the model.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Imputation {
+    #[default]
+    Mean,
+    Median,
+    // Most frequent value, or category
+    Mode,
+    // impute = { constant = 0.0 }, every one-hot feature of a categorical column
+    // taking the value
+    Constant(f64),
+    // The training rows are dropped, the rows to predict on are filled with the mean
+    DropRow,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Column {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: ColumnType,
+    // Whether cells may be missing (empty, NA or NaN)
+    #[serde(default)]
+    pub nullable: bool,
+    #[serde(default)]
+    pub categories: Vec<String>,
+    #[serde(default)]
+    pub impute: Imputation,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Schema {
+    pub columns: Vec<Column>,
+    // Target column, binary unless the model is multiclass, absent from the
+    // datasets to predict on
+    pub target: String,
+}
+
+pub fn is_missing(cell: &str) -> bool {
+    matches!(cell.trim(), "" | "NA" | "NaN" | "nan")
+}
+
+impl Schema {
+    // JSON, or TOML for a .toml file
+    pub fn load(path: &Path) -> Result<Schema, Box<dyn Error>> {
+        let content = std::fs::read_to_string(path)?;
+        let schema: Schema = if path.extension().is_some_and(|e| e == "toml") {
+            toml::from_str(&content)?
+        } else {
+            serde_json::from_str(&content)?
+        };
+        schema.check()?;
+        Ok(schema)
+    }
+
+    // Types from the cells: integer if they all parse as integers, float if they
+    // all parse as numbers, categorical otherwise. The last column is the target if
+    // the file has one, every column is a feature otherwise.
+    pub fn infer(header: &[String], rows: &[Vec<String>], has_target: bool) -> Schema {
+        let columns = header
+            .iter()
+            .enumerate()
+            .map(|(j, name)| {
+                let cells: Vec<&str> = rows
+                    .iter()
+                    .map(|row| row[j].trim())
+                    .filter(|cell| !is_missing(cell))
+                    .collect();
+                let nullable = cells.len() < rows.len();
+                let kind = if cells.iter().all(|c| c.parse::<i64>().is_ok()) {
+                    ColumnType::Integer
+                } else if cells.iter().all(|c| c.parse::<f64>().is_ok()) {
+                    ColumnType::Float
+                } else {
+                    ColumnType::Categorical
+                };
+                let mut categories = Vec::new();
+                if kind == ColumnType::Categorical {
+                    categories = cells.iter().map(|c| c.to_string()).collect();
+                    categories.sort();
+                    categories.dedup();
+                }
+                Column {
+                    name: name.clone(),
+                    kind,
+                    nullable,
+                    categories,
+                    impute: Imputation::default(),
+                }
+            })
+            .collect();
+
+        let target = match header.last() {
+            Some(last) if has_target => last.clone(),
+            _ => String::new(),
+        };
+        Schema { columns, target }
+    }
+
+    pub fn check(&self) -> Result<(), Box<dyn Error>> {
+        for column in self.columns.iter() {
+            if column.kind == ColumnType::Categorical && column.categories.is_empty() {
+                return Err(
+                    format!("The categorical column {} has no categories", column.name).into(),
+                );
+            }
+        }
+        // A dataset to predict on may have no target
+        if !self.target.is_empty() && self.column(&self.target).is_none() {
+            return Err(format!("The target column {} is not in the schema", self.target).into());
+        }
+        Ok(())
+    }
+
+    pub fn column(&self, name: &str) -> Option<&Column> {
+        self.columns.iter().find(|c| c.name == name)
+    }
+
+    // Columns the features are built from, in order
+    pub fn feature_columns(&self) -> impl Iterator<Item = &Column> {
+        self.columns.iter().filter(move |c| c.name != self.target)
+    }
+
+    // Name of every feature, categorical columns giving one feature per category
+    pub fn feature_names(&self) -> Vec<String> {
+        self.feature_columns()
+            .flat_map(|c| match c.kind {
+                ColumnType::Categorical => c
+                    .categories
+                    .iter()
+                    .map(|category| format!("{}={}", c.name, category))
+                    .collect(),
+                _ => vec![c.name.clone()],
+            })
+            .collect()
+    }
+}
+
+impl Column {
+    // Number of features of the column
+    pub fn width(&self) -> usize {
+        match self.kind {
+            ColumnType::Categorical => self.categories.len(),
+            _ => 1,
+        }
+    }
+
+    // Check a cell against the column type
+    pub fn validate(&self, cell: &str) -> Result<(), Box<dyn Error>> {
+        let cell = cell.trim();
+        if is_missing(cell) {
+            if !self.nullable {
+                return Err(format!("Missing value in the column {}", self.name).into());
+            }
+            return Ok(());
+        }
+        let valid = match self.kind {
+            ColumnType::Integer => cell.parse::<i64>().is_ok(),
+            ColumnType::Float => cell.parse::<f64>().is_ok(),
+            ColumnType::Categorical => self.categories.iter().any(|c| c == cell),
+        };
+        if !valid {
+            return Err(format!("Invalid value {:?} in the column {}", cell, self.name).into());
+        }
+        Ok(())
+    }
+
+    // Values of the features of a cell, NaN for a missing value
+    pub fn encode(&self, cell: &str) -> Vec<f64> {
+        let cell = cell.trim();
+        match self.kind {
+            ColumnType::Categorical if is_missing(cell) => vec![f64::NAN; self.categories.len()],
+            ColumnType::Categorical => self
+                .categories
+                .iter()
+                .map(|c| (c == cell) as u32 as f64)
+                .collect(),
+            _ => vec![cell.parse().unwrap_or(f64::NAN)],
+        }
+    }
+
+    // Value of a binary target: 0 or 1, or the index of one of two categories
+    pub fn encode_target(&self, cell: &str) -> Result<f64, Box<dyn Error>> {
+        let value = match self.kind {
+            ColumnType::Categorical if self.categories.len() == 2 => self
+                .categories
+                .iter()
+                .position(|c| c == cell.trim())
+                .map(|i| i as f64),
+            ColumnType::Categorical => None,
+            _ => cell.trim().parse::<f64>().ok(),
+        };
+        match value {
+            Some(v) if v == 0.0 || v == 1.0 => Ok(v),
+            _ => Err(format!("The target {} must be binary, got {:?}", self.name, cell).into()),
+        }
+    }
+
+    // Class of a multiclass target: the index of one of the categories, or a
+    // non-negative integer
+    pub fn encode_class(&self, cell: &str) -> Result<f64, Box<dyn Error>> {
+        let value = match self.kind {
+            ColumnType::Categorical => self
+                .categories
+                .iter()
+                .position(|c| c == cell.trim())
+                .map(|i| i as f64),
+            _ => cell
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|v| *v >= 0.0 && v.fract() == 0.0),
+        };
+        value.ok_or_else(|| {
+            format!(
+                "The target {} must be a class index or category, got {:?}",
+                self.name, cell
+            )
+            .into()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(cells: &[&str]) -> Vec<String> {
+        cells.iter().map(|c| c.to_string()).collect()
+    }
+
+    fn sample() -> (Vec<String>, Vec<Vec<String>>) {
+        let header = strings(&["age", "bmi", "smoking", "chd"]);
+        let rows = vec![
+            strings(&["52", "24.5", "never", "0"]),
+            strings(&["61", "NA", "current", "1"]),
+            strings(&["47", "31", "", "0"]),
+        ];
+        (header, rows)
+    }
+
+    #[test]
+    fn infer_with_target() {
+        let (header, rows) = sample();
+        let schema = Schema::infer(&header, &rows, true);
+        assert_eq!(schema.target, "chd");
+        assert!(schema.check().is_ok());
+        assert_eq!(
+            schema.feature_names(),
+            ["age", "bmi", "smoking=current", "smoking=never"]
+        );
+    }
+
+    #[test]
+    fn infer_without_target() {
+        let (header, rows) = sample();
+        let schema = Schema::infer(&header, &rows, false);
+        assert_eq!(schema.target, "");
+        assert!(schema.check().is_ok());
+        assert_eq!(schema.feature_columns().count(), 4);
+        assert_eq!(schema.feature_names().last().unwrap(), "chd");
+    }
+
+    #[test]
+    fn infer_types() {
+        let (header, rows) = sample();
+        let schema = Schema::infer(&header, &rows, true);
+        let kinds: Vec<ColumnType> = schema.columns.iter().map(|c| c.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                ColumnType::Integer,
+                ColumnType::Float,
+                ColumnType::Categorical,
+                ColumnType::Integer
+            ]
+        );
+        let nullable: Vec<bool> = schema.columns.iter().map(|c| c.nullable).collect();
+        assert_eq!(nullable, [false, true, true, false]);
+        assert_eq!(schema.columns[2].categories, ["current", "never"]);
+    }
+
+    #[test]
+    fn check_rejects_unknown_target() {
+        let (header, rows) = sample();
+        let mut schema = Schema::infer(&header, &rows, true);
+        schema.target = "outcome".to_string();
+        assert!(schema.check().is_err());
+    }
+}
@@ -1,289 +1,1171 @@
-use ndarray::{Array1, ArrayBase};
-use ndarray::s;
-use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
-use tonic::{Request, Response, Status};
-
-use ring::digest::{Context, SHA256};
-
-use bincode::deserialize;
-
-use file::file_server::{File, FileServer};
-use file::{FileFinished, FileResponse, FileTransfer};
-
-use std::sync::Mutex;
-
-use std::io::Write;
-
-use hmac::{Hmac, Mac};
-use sha2::Sha256;
-
-// Create alias for HMAC-SHA256
-type HmacSha256 = Hmac<Sha256>;
-
-mod csv_file;
-mod normalize;
-mod training;
-
-// Import the generated proto-rust file into a module
-pub mod file {
-    tonic::include_proto!("file");
-}
-
-// Implement the service skeleton for the "File" service
-// defined in the proto
-#[derive(Debug, Default)]
-pub struct MyServer {
-    received_data: Mutex<Vec<u8>>,
-    coefs_path: Mutex<String>,
-    training_file: Mutex<String>,
-    prediction_file: Mutex<String>,
-    hmac_hash: Mutex<Vec<u8>>,
-}
-
-// Implement the service function(s) defined in the proto
-// for the File service (SendFile...)
-#[tonic::async_trait]
-impl File for MyServer {
-    async fn send_file(
-        &self,
-        request: Request<FileTransfer>,
-    ) -> Result<Response<FileResponse>, Status> {
-        let request_contents = request.into_inner();
-        let file_contents = request_contents.content;
-        let client_hash = request_contents.hash; // Assuming the client sends the hash along with the content
-
-        // Before appending the received chunk to received_data,
-        // there is a checkup of its integrity using cryptographic
-        // hash functions (in this case SHA-256)
-
-        // Compute the hash of the received chunk
-        let mut context = Context::new(&SHA256);
-        context.update(&file_contents);
-        let computed_hash = context.finish();
-
-        // Verify the integrity of the chunk by comparing the computed hash with the received hash
-        if computed_hash.as_ref() != client_hash.as_slice() {
-            return Err(Status::invalid_argument(
-                "Hash mismatch, data integrity compromised",
-            ));
-        }
-
-        // Append received chunk to received_data
-        self.received_data.lock().unwrap().extend(file_contents);
-
-        let response = file::FileResponse {
-            message: format!("OK").into(),
-        };
-
-        Ok(Response::new(response))
-    }
-
-    async fn finish_transfer(
-        &self,
-        request: Request<FileFinished>,
-    ) -> Result<Response<FileResponse>, Status> {
-        let request_contents = request.into_inner();
-        // Get the filename
-        let filename = request_contents.filename;
-        let received_hmac_hash = request_contents.hmac_hash;
-
-        println!("{} received", filename);
-        println!("Checking integrity...");
-
-        let mut received_data = self.received_data.lock().unwrap();
-
-        // Create a new HMAC instance for this file transfer
-        let mut hmac =
-            HmacSha256::new_from_slice(b"secret").expect("HMAC can take key of any size");
-
-        // Compute the HMAC hash of the received data
-        hmac.update(&received_data);
-        let mut computed_hmac_hash = hmac.clone().finalize().into_bytes().to_vec();
-
-        // Verify the integrity of the entire file by comparing the computed HMAC hash with the received HMAC hash
-        if computed_hmac_hash != received_hmac_hash {
-            return Err(Status::invalid_argument(
-                "HMAC hash mismatch, data integrity compromised",
-            ));
-        }
-
-        println!("Integrity OK");
-
-        // if it is a csv file then deserialize it and write it to a csv file
-        if filename.ends_with(".csv") {
-            // Deserialize the received data into a vector of CSV records
-            let content_deserialized = deserialize::<Vec<csv_file::Record>>(&*received_data)
-                .expect("Failed to deserialize");
-
-            // Write the deserialized data to a CSV file
-            csv_file::write_csv_file(content_deserialized, &filename);
-        }
-
-        if filename.ends_with(".txt") {
-            // Deserialize the received data into a vector of CSV records
-            let content_deserialized =
-                deserialize::<Array1<f64>>(&*received_data).expect("Failed to deserialize");
-
-            // Write the deserialized data to a txt file
-            csv_file::write_array1_to_file(&content_deserialized, &filename);
-        }
-
-        // Clear the received_data for future transfers
-        received_data.clear();
-        computed_hmac_hash.clear();
-
-        let response = file::FileResponse {
-            message: format!("OK").into(),
-        };
-        Ok(Response::new(response))
-    }
-
-    async fn priming_send(
-        &self,
-        request: Request<file::FileRequest>,
-    ) -> Result<Response<file::FileResponse>, Status> {
-        // Get the filename from the request
-        let request_contents = request.into_inner();
-        let filename = request_contents.filename;
-        let training = request_contents.train;
-        let coefs = request_contents.coefs;
-
-        // Saving of whatever type the client sends
-        if training {
-            self.training_file
-                .lock()
-                .unwrap()
-                .push_str(&filename.to_string());
-        } else {
-            if coefs {
-                self.coefs_path
-                    .lock()
-                    .unwrap()
-                    .push_str(&filename.to_string());
-            } else {
-                self.prediction_file
-                    .lock()
-                    .unwrap()
-                    .push_str(&filename.to_string());
-            }
-        }
-
-        // Create a file with the filename
-        let mut file = std::fs::File::create(&filename)?;
-
-        match file.write_all(b"") {
-            Ok(_) => {
-                let response = file::FileResponse {
-                    message: format!("OK").into(),
-                };
-
-                Ok(Response::new(response))
-            }
-            Err(_) => {
-                let response = file::FileResponse {
-                    message: format!("ERROR").into(),
-                };
-
-                Ok(Response::new(response))
-            }
-        }
-    }
-
-    async fn launch_training(
-        &self,
-        _request: Request<file::RequestTraining>,
-    ) -> Result<Response<file::ResponseAccuracy>, Status> {
-        let mut message = String::from("");
-        let mut accuracy = 0.0;
-        if self.training_file.lock().unwrap().is_empty() {
-            message = "The training dataset is missing".to_string();
-        } else {
-            let content =
-                csv_file::read_csv_file(self.training_file.lock().unwrap().to_string())
-                    .map_err(|e| Status::internal(format!("Failed to read CSV file: {}", e)))?;
-
-            let (X_train, y_train, X_test, y_test) = normalize::clean_dataset(content.to_owned());
-            let model = training::train_log_reg(&X_train, &y_train);
-            accuracy = training::model_accuracy(&model.to_owned(), &X_test, &y_test);
-            println!("Accuracy: {}", accuracy);
-        }
-
-        let response = file::ResponseAccuracy {
-            message: message.into(),
-            accuracy: accuracy as f32,
-        };
-
-        Ok(Response::new(response))
-    }
-
-    async fn launch_prediction(
-        &self,
-        _request: Request<file::RequestPrediction>,
-    ) -> Result<Response<file::ResponsePrediction>, Status> {
-        let mut message = String::from("");
-        let prediction: Array1<f64> = ArrayBase::zeros(0);
-        if self.prediction_file.lock().unwrap().is_empty() {
-            message = "The testing dataset is missing".to_string();
-        } else if self.coefs_path.lock().unwrap().is_empty() {
-            message = "The model coefficients are missing".to_string();
-        } else {
-            let content = csv_file::read_csv_file(self.prediction_file.lock().unwrap().to_string())
-                .map_err(|e| Status::internal(format!("Failed to read CSV file: {}", e)))?;
-            let model = csv_file::read_file_to_array1(&self.coefs_path.lock().unwrap())
-                .map_err(|e| Status::internal(format!("Failed to read txt file: {}", e)))?;
-
-            let dataset = 
-                normalize::normalize_data(&[content.into_iter().nth(0).unwrap()]);
-            // Selection of only the first 10 rows of the dataset
-            let X_test_0 = dataset.unwrap().slice(s![0..1, ..]).to_owned();
-            let _prediction = training::predict(&model.to_owned(), &X_test_0.mapv(|x| x as f64));
-        }
-
-        let response = file::ResponsePrediction {
-            message: message.into(),
-            prediction: serde_json::to_string(&prediction).unwrap(),
-        };
-
-        Ok(Response::new(response))
-    }
-}
-
-// Runtime to run our server
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Get the args passed to the program
-    let args: Vec<String> = std::env::args().collect();
-
-    if args.len() != 2 {
-        println!("Usage: {} <port>", args[0]);
-        return Ok(());
-    }
-
-    let cert = std::fs::read_to_string("server.crt")?;
-    let key = std::fs::read_to_string("server.key")?;
-    let server_identity = Identity::from_pem(cert, key);
-
-    let client_ca_cert = std::fs::read_to_string("ca.crt")?;
-    let client_ca_cert = Certificate::from_pem(client_ca_cert);
-
-    // Consider implementing certificate pinning with `rustls` by providing
-    // a custom certificate verifier that checks the server's certificate
-    // against a known, trusted copy.
-
-    let addr = "127.0.0.1:".to_owned() + &args[1];
-    let addr = addr.parse()?;
-    let server = MyServer::default();
-
-    let tls = ServerTlsConfig::new()
-        .identity(server_identity)
-        .client_ca_root(client_ca_cert);
-
-    println!("Hosting on port {}, waiting for commands...", args[1]);
-    Server::builder()
-        .tls_config(tls)?
-        .add_service(FileServer::new(server))
-        .serve(addr)
-        .await?;
-
-    Ok(())
-}
+#![allow(non_snake_case)]
+// tonic::Status is the error type of every handler and its helpers
+#![allow(clippy::result_large_err)]
+
+use http::uri::Uri;
+use ndarray::Array2;
+use num_bigint::BigUint;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity, Server, ServerTlsConfig};
+use tonic::{Request, Response, Status};
+
+use ring::digest::{Context, SHA256};
+
+use bincode::deserialize;
+
+use file::file_server::{File, FileServer};
+use file::{FileResponse, UploadChunk, UploadStatus, UploadStatusRequest};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+
+use at_rest::MasterKey;
+use key_exchange::{KeyAgreement, SessionKey, Transcript, KEY_LIFETIME};
+use session::SessionStore;
+use storage::Storage;
+
+use crypi::{
+    ckks, csv_file, file, he, key_exchange, metrics, mpc, normalize, resample, schema, split,
+    training, triples,
+};
+
+mod at_rest;
+mod model_store;
+mod predictor;
+mod session;
+mod storage;
+
+use predictor::SecurePredictor;
+
+// Implement the service skeleton for the "File" service
+// defined in the proto
+#[derive(Debug)]
+pub struct MyServer {
+    sessions: Arc<SessionStore<Session>>,
+    storage: Storage,
+    // Key of the files stored by the server
+    master_key: MasterKey,
+    dealer: Option<Channel>,
+    // Shared by the model owner and the data owners, every one in their own session,
+    // one at a time
+    secure_training: Mutex<Option<SecureTraining>>,
+    models: model_store::ModelStore,
+    // Taken to check the quota of a new upload and register it, so that concurrent
+    // uploads cannot both take the last free bytes of a client
+    quota: tokio::sync::Mutex<()>,
+}
+
+// State of a client session, isolated from the other clients
+#[derive(Debug)]
+struct Session {
+    // Directory of the files uploaded in the session, deleted when it expires
+    directory: PathBuf,
+    // Uploads in progress or complete, by upload ID
+    uploads: Mutex<HashMap<String, Upload>>,
+    coefs_path: Mutex<String>,
+    training_file: Mutex<String>,
+    prediction_file: Mutex<String>,
+    mpc_channel: tokio::sync::Mutex<Option<MpcChannel>>,
+    // Agreed on when the session opens, rotated by the client
+    key: Mutex<Option<SessionKey>>,
+}
+
+impl From<PathBuf> for Session {
+    fn from(directory: PathBuf) -> Self {
+        Session {
+            directory,
+            uploads: Mutex::new(HashMap::new()),
+            coefs_path: Mutex::new(String::new()),
+            training_file: Mutex::new(String::new()),
+            prediction_file: Mutex::new(String::new()),
+            mpc_channel: tokio::sync::Mutex::new(None),
+            key: Mutex::new(None),
+        }
+    }
+}
+
+// File streamed by UploadFile, resumable from the end of the received data
+#[derive(Debug)]
+struct Upload {
+    filename: String,
+    // Sanitized file name, for the logs
+    name: String,
+    // Name of the stored file, without its extension
+    object_id: String,
+    train: bool,
+    coefs: bool,
+    size: u64,
+    data: Vec<u8>,
+    // Received in full and being written, the data having been taken
+    storing: bool,
+    complete: bool,
+    // HMAC key of the upload, derived from the session key when it started
+    key: Vec<u8>,
+    transcript: Transcript,
+}
+
+// Secure training waiting for the shares of the data owners
+#[derive(Debug)]
+struct SecureTraining {
+    // Session of the model owner, which opened the training and alone launches it.
+    // The training is abandoned when the session expires.
+    owner: Weak<Session>,
+    // Paillier modulus of the model owner
+    modulus: Vec<u8>,
+    contributions: Vec<file::TrainingShares>,
+}
+
+impl SecureTraining {
+    // Still waiting for its launch, the session of the model owner being alive
+    fn is_pending(&self) -> bool {
+        self.owner.strong_count() > 0
+    }
+
+    fn is_owner(&self, session: &Arc<Session>) -> bool {
+        std::ptr::eq(self.owner.as_ptr(), Arc::as_ptr(session))
+    }
+}
+
+// Handler side of the channels linking the MpcExchange RPC to a running secure computation
+#[derive(Debug)]
+struct MpcChannel {
+    to_party: mpsc::Sender<Vec<u64>>,
+    from_party: mpsc::Receiver<Vec<u64>>,
+}
+
+// Transport of the model owner party, fed by the MpcExchange RPC
+struct ChannelTransport {
+    incoming: mpsc::Receiver<Vec<u64>>,
+    outgoing: mpsc::Sender<Vec<u64>>,
+}
+
+#[tonic::async_trait]
+impl mpc::Transport for ChannelTransport {
+    async fn exchange(&mut self, outgoing: Vec<u64>) -> mpc::MpcResult<Vec<u64>> {
+        self.outgoing.send(outgoing).await?;
+        self.incoming
+            .recv()
+            .await
+            .ok_or_else(|| "The data owner left the computation".into())
+    }
+}
+
+// Start the model owner side of a secure computation, returning the channels to reach it
+fn spawn_party<F, Fut>(triples: Box<dyn mpc::TripleProvider>, program: F) -> MpcChannel
+where
+    F: FnOnce(mpc::Party<ChannelTransport>) -> Fut,
+    Fut: std::future::Future<Output = mpc::MpcResult<()>> + Send + 'static,
+{
+    let (to_party, incoming) = mpsc::channel(1);
+    let (outgoing, from_party) = mpsc::channel(1);
+    let party = mpc::Party::new(1, ChannelTransport { incoming, outgoing }, triples);
+    let computation = program(party);
+
+    tokio::spawn(async move {
+        if let Err(e) = computation.await {
+            println!("Secure computation aborted: {}", e);
+        }
+    });
+
+    MpcChannel {
+        to_party,
+        from_party,
+    }
+}
+
+// Server side of the agreement on a session key with the client of the request,
+// returning the key and the server's public key
+fn agree_session_key(
+    request: &Request<file::KeyExchange>,
+    previous: Option<&SessionKey>,
+) -> Result<(SessionKey, Vec<u8>), Status> {
+    let agreement = KeyAgreement::server().map_err(|e| Status::internal(e.to_string()))?;
+    let public_key = agreement.public_key.clone();
+    let certificate = session::client_certificate(request).unwrap_or_default();
+    let key = agreement
+        .agree(&request.get_ref().public_key, &certificate, previous)
+        .map_err(|e| Status::invalid_argument(e.to_string()))?;
+    Ok((key, public_key))
+}
+
+// Random identifier of a secure computation, shared with the dealer
+fn computation_id() -> String {
+    mpc::random_u64s(2)
+        .iter()
+        .map(|w| format!("{:016x}", w))
+        .collect()
+}
+
+// Source of the triples of the server's side of a secure computation
+fn triple_provider(
+    dealer: &Option<Channel>,
+    source: file::TripleSource,
+    computation_id: &str,
+) -> Result<Box<dyn mpc::TripleProvider>, Status> {
+    match source {
+        file::TripleSource::Dealer => match dealer {
+            Some(channel) => Ok(Box::new(triples::DealerProvider::new(
+                channel.clone(),
+                computation_id.to_string(),
+            ))),
+            None => Err(Status::failed_precondition(
+                "The server is not connected to a dealer",
+            )),
+        },
+        file::TripleSource::ObliviousTransfer => Ok(Box::<triples::OtProvider>::default()),
+    }
+}
+
+// Hyperparameters of a training request, the ones left at 0 taking their default
+fn hyperparameters(request: &file::RequestTraining) -> training::Hyperparameters {
+    let default = training::Hyperparameters::default();
+    let or_default = |value: f64, default: f64| if value == 0.0 { default } else { value };
+    training::Hyperparameters {
+        learning_rate: or_default(request.learning_rate, default.learning_rate),
+        epochs: match request.epochs {
+            0 => default.epochs,
+            epochs => epochs as usize,
+        },
+        batch_size: request.batch_size as usize,
+        optimizer: match request.optimizer() {
+            file::Optimizer::Sgd => training::Optimizer::Sgd,
+            file::Optimizer::Momentum => training::Optimizer::Momentum,
+            file::Optimizer::Adam => training::Optimizer::Adam,
+            file::Optimizer::Lbfgs => training::Optimizer::Lbfgs,
+        },
+        l1: request.l1,
+        l2: request.l2,
+        intercept: !request.no_intercept,
+        tolerance: request.tolerance,
+        seed: request.seed,
+        class_weights: match request.class_weights.clone().unwrap_or_default() {
+            weights if weights.balanced => training::ClassWeights::Balanced,
+            weights if weights.negative == 0.0 && weights.positive == 0.0 => {
+                training::ClassWeights::Uniform
+            }
+            weights => training::ClassWeights::Custom {
+                negative: weights.negative,
+                positive: weights.positive,
+            },
+        },
+        resampling: {
+            let resampling = request.resampling.clone().unwrap_or_default();
+            resample::Resampling {
+                method: match resampling.method() {
+                    file::ResamplingMethod::NoResampling => resample::ResamplingMethod::None,
+                    file::ResamplingMethod::Oversample => resample::ResamplingMethod::Oversample,
+                    file::ResamplingMethod::Undersample => resample::ResamplingMethod::Undersample,
+                    file::ResamplingMethod::Smote => resample::ResamplingMethod::Smote,
+                },
+                neighbors: match resampling.neighbors {
+                    0 => resample::NEIGHBORS,
+                    neighbors => neighbors as usize,
+                },
+                seed: resampling.seed,
+            }
+        },
+        threshold_metric: match request.threshold_metric() {
+            file::ThresholdMetric::FixedThreshold => None,
+            file::ThresholdMetric::F1 => Some(metrics::ThresholdMetric::F1),
+            file::ThresholdMetric::Youden => Some(metrics::ThresholdMetric::Youden),
+            file::ThresholdMetric::Accuracy => Some(metrics::ThresholdMetric::Accuracy),
+        },
+        multiclass: match request.multiclass() {
+            file::Multiclass::Binary => training::Multiclass::Binary,
+            file::Multiclass::Softmax => training::Multiclass::Softmax,
+            file::Multiclass::OneVsRest => training::Multiclass::OneVsRest,
+        },
+    }
+}
+
+// Held-out split of a training request, the test fraction left at 0 taking its
+// default
+fn split_options(split: &Option<file::Split>) -> split::SplitOptions {
+    let split = split.clone().unwrap_or_default();
+    split::SplitOptions {
+        test_fraction: match split.test_fraction {
+            0.0 => split::TEST_FRACTION,
+            fraction => fraction,
+        },
+        shuffle: split.shuffle,
+        stratify: split.stratify,
+        seed: split.seed,
+    }
+}
+
+// Scaler fit, hyperparameters and split of a training request, checked
+// Hyperparameters of a secure training request, the ones left at 0 taking their
+// default. The other hyperparameters do not apply to the secure training.
+fn secure_hyperparameters(
+    request: &file::RequestSecureTraining,
+) -> Result<training::Hyperparameters, Status> {
+    let default = training::Hyperparameters::default();
+    let params = training::Hyperparameters {
+        learning_rate: match request.learning_rate {
+            0.0 => default.learning_rate,
+            learning_rate => learning_rate,
+        },
+        epochs: match request.epochs {
+            0 => default.epochs,
+            epochs => epochs as usize,
+        },
+        intercept: !request.no_intercept,
+        ..default
+    };
+    params.validate().map_err(Status::invalid_argument)?;
+    Ok(params)
+}
+
+type TrainingOptions = (
+    fn(&ndarray::Array2<f64>) -> normalize::Scaler,
+    training::Hyperparameters,
+    split::SplitOptions,
+);
+
+fn training_options(request: &file::RequestTraining) -> Result<TrainingOptions, Status> {
+    let fit = match request.scaling() {
+        file::Scaling::ZScore => normalize::Scaler::fit_z_score,
+        file::Scaling::MinMax => normalize::Scaler::fit_min_max,
+    };
+    let params = hyperparameters(request);
+    params.validate().map_err(Status::invalid_argument)?;
+    let split = split_options(&request.split);
+    split.validate().map_err(Status::invalid_argument)?;
+    Ok((fit, params, split))
+}
+
+impl Session {
+    fn has_upload(&self, upload_id: &str) -> bool {
+        self.uploads.lock().unwrap().contains_key(upload_id)
+    }
+
+    // Bytes of the uploads in progress, not stored yet
+    fn pending(&self) -> u64 {
+        self.uploads
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|upload| !upload.complete)
+            .map(|upload| upload.size)
+            .sum()
+    }
+
+    fn append_chunk(&self, chunk: UploadChunk) -> Result<(), Status> {
+        if chunk.upload_id.is_empty() {
+            return Err(Status::invalid_argument("Missing upload ID"));
+        }
+        let mut uploads = self.uploads.lock().unwrap();
+        if !uploads.contains_key(&chunk.upload_id) {
+            let name = storage::sanitize(&chunk.filename);
+            if !name.ends_with(".csv") && !name.ends_with(".txt") {
+                return Err(Status::invalid_argument(
+                    "Only .csv and .txt files can be uploaded",
+                ));
+            }
+            let key = self.transfer_key(&chunk.upload_id)?;
+            uploads.insert(
+                chunk.upload_id.clone(),
+                Upload {
+                    filename: chunk.filename.clone(),
+                    name,
+                    object_id: storage::object_id(),
+                    train: chunk.train,
+                    coefs: chunk.coefs,
+                    size: chunk.size,
+                    data: Vec::new(),
+                    storing: false,
+                    complete: false,
+                    key,
+                    transcript: Transcript::new(&chunk),
+                },
+            );
+        }
+        let upload = uploads.get_mut(&chunk.upload_id).unwrap();
+
+        if upload.complete || upload.storing {
+            return Err(Status::failed_precondition(format!(
+                "The upload {} is already complete",
+                chunk.upload_id
+            )));
+        }
+        if upload.filename != chunk.filename || upload.size != chunk.size {
+            return Err(Status::invalid_argument(format!(
+                "The chunk does not belong to the upload {}",
+                chunk.upload_id
+            )));
+        }
+        // Chunks are appended in order, a client resuming an upload starts at the
+        // offset returned by QueryUploadStatus
+        let received = upload.data.len() as u64;
+        if chunk.offset != received {
+            return Err(Status::out_of_range(format!(
+                "Expected the chunk at offset {}, got {}",
+                received, chunk.offset
+            )));
+        }
+        if received + chunk.content.len() as u64 > upload.size {
+            return Err(Status::out_of_range(
+                "The chunk exceeds the size of the file",
+            ));
+        }
+
+        upload.transcript.add(&chunk);
+        upload.data.extend(chunk.content);
+        Ok(())
+    }
+
+    // Key of a new upload, under a session key young enough
+    fn transfer_key(&self, upload_id: &str) -> Result<Vec<u8>, Status> {
+        let key = self.key.lock().unwrap();
+        match key.as_ref() {
+            Some(key) if key.age() < KEY_LIFETIME => Ok(key.transfer_key(upload_id)),
+            Some(_) => Err(Status::failed_precondition(
+                "The session key expired, rotate it",
+            )),
+            None => Err(Status::failed_precondition("The session has no key")),
+        }
+    }
+
+    fn upload_status(&self, upload_id: &str) -> Result<UploadStatus, Status> {
+        let uploads = self.uploads.lock().unwrap();
+        let upload = uploads
+            .get(upload_id)
+            .ok_or_else(|| Status::not_found(format!("Unknown upload {}", upload_id)))?;
+        Ok(UploadStatus {
+            upload_id: upload_id.to_string(),
+            received: if upload.complete || upload.storing {
+                upload.size
+            } else {
+                upload.data.len() as u64
+            },
+            size: upload.size,
+            complete: upload.complete,
+            object_id: upload.object_id.clone(),
+        })
+    }
+
+    // Check the HMAC of a fully received upload and store the file. The file is
+    // parsed and written on a blocking thread, without holding the lock of the
+    // uploads. An upload which fails is removed, the client sends it again.
+    async fn finish_upload(
+        &self,
+        upload_id: &str,
+        received_hmac_hash: &[u8],
+        master_key: &MasterKey,
+    ) -> Result<(), Status> {
+        let (data, path, train, coefs) = {
+            let mut uploads = self.uploads.lock().unwrap();
+            let upload = uploads
+                .get_mut(upload_id)
+                .ok_or_else(|| Status::not_found(format!("Unknown upload {}", upload_id)))?;
+            // Stored by another stream of the same upload
+            if upload.complete || upload.storing {
+                return Ok(());
+            }
+            let filename = upload.name.clone();
+
+            println!("{} received", filename);
+            println!("Checking integrity...");
+
+            // HMAC of the transcript of the chunks, under the key of the upload
+            let computed_hmac_hash = upload.transcript.mac(&upload.key);
+
+            // Verify the integrity of the entire file by comparing the computed HMAC
+            // hash with the received HMAC hash, in constant time. The upload restarts
+            // from scratch.
+            if ring::constant_time::verify_slices_are_equal(&computed_hmac_hash, received_hmac_hash)
+                .is_err()
+            {
+                uploads.remove(upload_id);
+                return Err(Status::invalid_argument(
+                    "HMAC hash mismatch, data integrity compromised",
+                ));
+            }
+
+            println!("Integrity OK");
+
+            // Stored under the name the server generated, with the type of the file
+            let extension = if filename.ends_with(".csv") {
+                "csv"
+            } else {
+                "txt"
+            };
+            let path = self
+                .directory
+                .join(format!("{}.{}", upload.object_id, extension))
+                .to_string_lossy()
+                .to_string();
+            upload.storing = true;
+            (
+                std::mem::take(&mut upload.data),
+                path,
+                upload.train,
+                upload.coefs,
+            )
+        };
+
+        let master_key = master_key.clone();
+        let written = path.clone();
+        let stored =
+            tokio::task::spawn_blocking(move || store_upload(&data, &written, &master_key))
+                .await
+                .unwrap_or_else(|e| {
+                    Err(Status::internal(format!("Failed to store the file: {}", e)))
+                });
+        if let Err(e) = stored {
+            self.uploads.lock().unwrap().remove(upload_id);
+            let _ = std::fs::remove_file(&path);
+            return Err(e);
+        }
+
+        // Saving of whatever type the client sends
+        let file = if train {
+            &self.training_file
+        } else if coefs {
+            &self.coefs_path
+        } else {
+            &self.prediction_file
+        };
+        *file.lock().unwrap() = path;
+
+        // Only the status of the upload is kept
+        if let Some(upload) = self.uploads.lock().unwrap().get_mut(upload_id) {
+            upload.storing = false;
+            upload.complete = true;
+        }
+        Ok(())
+    }
+}
+
+// Parse an uploaded file and write it encrypted at `path`: the cells and the
+// schema of a dataset for a .csv file, the coefficients of a model, one column
+// per class, for a .txt file
+fn store_upload(data: &[u8], path: &str, master_key: &MasterKey) -> Result<(), Status> {
+    if path.ends_with(".csv") {
+        let dataset = deserialize::<csv_file::Dataset>(data)
+            .map_err(|e| Status::invalid_argument(format!("Invalid dataset: {}", e)))?;
+        dataset
+            .validate()
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        at_rest::write_encrypted_csv(&dataset, path, master_key)
+            .map_err(|e| Status::internal(format!("Failed to write CSV file: {}", e)))
+    } else {
+        let coefficients = deserialize::<Array2<f64>>(data)
+            .map_err(|e| Status::invalid_argument(format!("Invalid model: {}", e)))?;
+        at_rest::write_encrypted_array2(&coefficients, path, master_key)
+            .map_err(|e| Status::internal(format!("Failed to write txt file: {}", e)))
+    }
+}
+
+impl MyServer {
+    // Refuse a new upload of `size` bytes that would take the files of the client
+    // over its quota, counting its stored models and the uploads in progress in all
+    // its sessions
+    fn check_quota(
+        &self,
+        client: &str,
+        client_directory: &Path,
+        sessions: &[Arc<Session>],
+        size: u64,
+    ) -> Result<(), Status> {
+        let used = storage::usage(client_directory)
+            + self.models.usage(client)
+            + sessions
+                .iter()
+                .map(|session| session.pending())
+                .sum::<u64>();
+        self.storage
+            .check_quota(used, size)
+            .map_err(Status::resource_exhausted)
+    }
+
+    // Backend computing the predictions of a mode in a session
+    fn predictor<'a>(
+        &self,
+        session: &'a Session,
+        mode: file::PredictionMode,
+    ) -> Box<dyn SecurePredictor + 'a> {
+        match mode {
+            file::PredictionMode::Plaintext => Box::new(predictor::PlaintextPredictor {
+                prediction_file: session.prediction_file.lock().unwrap().clone(),
+                master_key: self.master_key.clone(),
+            }),
+            file::PredictionMode::SecretShared => Box::new(predictor::SecretSharedPredictor {
+                dealer: self.dealer.clone(),
+                mpc_channel: &session.mpc_channel,
+            }),
+            file::PredictionMode::Paillier => Box::new(predictor::PaillierPredictor),
+            file::PredictionMode::Ckks => Box::new(predictor::CkksPredictor),
+        }
+    }
+}
+
+// Implement the service function(s) defined in the proto
+// for the File service (SendFile...)
+#[tonic::async_trait]
+impl File for MyServer {
+    async fn open_session(
+        &self,
+        request: Request<file::KeyExchange>,
+    ) -> Result<Response<file::SessionInfo>, Status> {
+        let (key, public_key) = agree_session_key(&request, None)?;
+        let (session_id, session) = self.sessions.open(&request)?;
+        *session.key.lock().unwrap() = Some(key);
+        println!("Session {} opened", session_id);
+        Ok(Response::new(file::SessionInfo {
+            session_id,
+            timeout: self.sessions.timeout().as_secs(),
+            public_key,
+        }))
+    }
+
+    async fn rotate_session_key(
+        &self,
+        request: Request<file::KeyExchange>,
+    ) -> Result<Response<file::KeyExchange>, Status> {
+        let session = self.sessions.get(&request)?;
+        let previous = session.key.lock().unwrap().clone();
+        let (key, public_key) = agree_session_key(&request, previous.as_ref())?;
+        *session.key.lock().unwrap() = Some(key);
+        Ok(Response::new(file::KeyExchange { public_key }))
+    }
+
+    async fn close_session(
+        &self,
+        request: Request<file::SessionRequest>,
+    ) -> Result<Response<FileResponse>, Status> {
+        self.sessions.close(&request)?;
+        Ok(Response::new(FileResponse {
+            message: "OK".to_string(),
+        }))
+    }
+
+    async fn upload_file(
+        &self,
+        request: Request<tonic::Streaming<UploadChunk>>,
+    ) -> Result<Response<UploadStatus>, Status> {
+        let session = self.sessions.get(&request)?;
+        let client = session::client_id(&request);
+        let (client_directory, client_sessions) = self.sessions.client_sessions(&request);
+        let mut stream = request.into_inner();
+        let mut upload_id = String::new();
+        let mut hmac_hash = Vec::new();
+
+        // Every chunk is appended as soon as it is received, so that an interrupted
+        // stream leaves the upload resumable from its last chunk
+        while let Some(chunk) = stream.message().await? {
+            // Compute the hash of the received chunk
+            let mut context = Context::new(&SHA256);
+            context.update(&chunk.content);
+            let computed_hash = context.finish();
+
+            // Verify the integrity of the chunk by comparing the computed hash with the received hash
+            if computed_hash.as_ref() != chunk.hash.as_slice() {
+                return Err(Status::data_loss(
+                    "Hash mismatch, data integrity compromised",
+                ));
+            }
+
+            upload_id = chunk.upload_id.clone();
+            if !chunk.hmac_hash.is_empty() {
+                hmac_hash = chunk.hmac_hash.clone();
+            }
+            if session.has_upload(&chunk.upload_id) {
+                session.append_chunk(chunk)?;
+            } else {
+                // The size of the new upload counts as pending as soon as it is
+                // registered, under the lock of the check
+                let _quota = self.quota.lock().await;
+                self.check_quota(&client, &client_directory, &client_sessions, chunk.size)?;
+                session.append_chunk(chunk)?;
+            }
+        }
+        if upload_id.is_empty() {
+            return Err(Status::invalid_argument("The upload has no chunk"));
+        }
+
+        let status = session.upload_status(&upload_id)?;
+        if !status.complete && status.received == status.size && !hmac_hash.is_empty() {
+            session
+                .finish_upload(&upload_id, &hmac_hash, &self.master_key)
+                .await?;
+        }
+        Ok(Response::new(session.upload_status(&upload_id)?))
+    }
+
+    async fn query_upload_status(
+        &self,
+        request: Request<UploadStatusRequest>,
+    ) -> Result<Response<UploadStatus>, Status> {
+        let session = self.sessions.get(&request)?;
+        let upload_id = request.into_inner().upload_id;
+        Ok(Response::new(session.upload_status(&upload_id)?))
+    }
+
+    async fn launch_training(
+        &self,
+        request: Request<file::RequestTraining>,
+    ) -> Result<Response<file::ResponseAccuracy>, Status> {
+        let session = self.sessions.get(&request)?;
+        let client = session::client_id(&request);
+        let (fit, params, split) = training_options(request.get_ref())?;
+        let mut message = String::from("");
+        let mut metrics = metrics::Metrics::default();
+        let mut model_id = String::new();
+        let mut epochs = 0;
+        if session.training_file.lock().unwrap().is_empty() {
+            message = "The training dataset is missing".to_string();
+        } else {
+            let training_file = session.training_file.lock().unwrap().to_string();
+            let content = at_rest::read_encrypted_csv(&training_file, &self.master_key)
+                .map_err(|e| Status::internal(format!("Failed to read CSV file: {}", e)))?;
+
+            let multiclass = params.multiclass != training::Multiclass::Binary;
+            let data =
+                normalize::clean_dataset(&content, fit, params.intercept, multiclass, &split)
+                    .map_err(|e| Status::invalid_argument(e.to_string()))?;
+            // The gradient descent runs on a blocking thread, not on the runtime
+            let (trained, evaluation, data, params) = tokio::task::spawn_blocking(move || {
+                let trained = training::fit(&data.X_train, &data.y_train, data.classes, &params);
+                let probabilities = trained.class_proba(&data.X_test);
+                let evaluation = metrics::Metrics::evaluate_classes(
+                    &probabilities,
+                    &data.y_test,
+                    trained.threshold,
+                );
+                (trained, evaluation, data, params)
+            })
+            .await
+            .map_err(|e| Status::internal(format!("Training failed: {}", e)))?;
+            epochs = trained.epochs;
+            metrics = evaluation;
+            println!(
+                "Accuracy: {}, F1: {} with the threshold {} after {} epochs",
+                metrics.accuracy, metrics.f1, trained.threshold, epochs
+            );
+
+            // Keep the model for the predictions, with the hash of the decrypted file
+            let dataset = self
+                .master_key
+                .read(std::path::Path::new(&training_file))
+                .map_err(|e| Status::internal(format!("Failed to read CSV file: {}", e)))?;
+            let stored = model_store::StoredModel::new(
+                trained,
+                Some(data.imputer),
+                Some(data.scaler),
+                Some(content.schema),
+                &dataset,
+                params,
+                split,
+                metrics.clone(),
+            );
+            self.models
+                .save(&client, &stored)
+                .map_err(|e| Status::internal(format!("Failed to store the model: {}", e)))?;
+            println!("Model {} stored", stored.id);
+            model_id = stored.id;
+        }
+
+        let response = file::ResponseAccuracy {
+            message,
+            accuracy: metrics.accuracy as f32,
+            model_id,
+            epochs: epochs as u32,
+            metrics: Some((&metrics).into()),
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn cross_validate(
+        &self,
+        request: Request<file::RequestCrossValidation>,
+    ) -> Result<Response<file::ResponseCrossValidation>, Status> {
+        let session = self.sessions.get(&request)?;
+        let request = request.into_inner();
+        let (fit, params, split) = training_options(&request.training.unwrap_or_default())?;
+        let folds = match request.folds {
+            0 => split::FOLDS,
+            folds => folds as usize,
+        };
+        if session.training_file.lock().unwrap().is_empty() {
+            return Ok(Response::new(file::ResponseCrossValidation {
+                message: "The training dataset is missing".to_string(),
+                ..Default::default()
+            }));
+        }
+
+        let training_file = session.training_file.lock().unwrap().to_string();
+        let content = at_rest::read_encrypted_csv(&training_file, &self.master_key)
+            .map_err(|e| Status::internal(format!("Failed to read CSV file: {}", e)))?;
+        let multiclass = params.multiclass != training::Multiclass::Binary;
+        let splits = normalize::cross_validation_splits(
+            &content,
+            fit,
+            params.intercept,
+            multiclass,
+            &split,
+            folds,
+        )
+        .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        // The gradient descents run on a blocking thread, not on the runtime
+        let folds = tokio::task::spawn_blocking(move || {
+            splits
+                .iter()
+                .map(|data| {
+                    let trained =
+                        training::fit(&data.X_train, &data.y_train, data.classes, &params);
+                    let probabilities = trained.class_proba(&data.X_test);
+                    metrics::Fold {
+                        metrics: metrics::Metrics::evaluate_classes(
+                            &probabilities,
+                            &data.y_test,
+                            trained.threshold,
+                        ),
+                        epochs: trained.epochs,
+                        train_rows: data.X_train.nrows(),
+                        test_rows: data.X_test.nrows(),
+                    }
+                })
+                .collect()
+        })
+        .await
+        .map_err(|e| Status::internal(format!("Cross-validation failed: {}", e)))?;
+        let cv = metrics::CrossValidation::new(folds);
+        println!(
+            "Cross-validation on {} folds: accuracy {} ± {}, F1 {} ± {}",
+            cv.folds.len(),
+            cv.mean.accuracy,
+            cv.std.accuracy,
+            cv.mean.f1,
+            cv.std.f1
+        );
+
+        Ok(Response::new((&cv).into()))
+    }
+
+    async fn launch_prediction(
+        &self,
+        request: Request<file::RequestPrediction>,
+    ) -> Result<Response<file::ResponsePrediction>, Status> {
+        let session = self.sessions.get(&request)?;
+        let client = session::client_id(&request);
+        let request_contents = request.into_inner();
+
+        let model = if !request_contents.model_id.is_empty() {
+            let id = &request_contents.model_id;
+            let stored = self
+                .models
+                .load(&client, id)
+                .map_err(|e| Status::not_found(format!("Unknown model {}: {}", id, e)))?;
+            predictor::Model {
+                coefficients: stored
+                    .coefficients()
+                    .map_err(|e| Status::internal(format!("Invalid model {}: {}", id, e)))?,
+                multiclass: stored.hyperparameters.multiclass,
+                schema: stored.schema,
+                imputer: stored.imputer,
+                threshold: stored.threshold,
+            }
+        } else {
+            if session.coefs_path.lock().unwrap().is_empty() {
+                return Ok(Response::new(file::ResponsePrediction {
+                    message: "The model coefficients are missing".to_string(),
+                    ..Default::default()
+                }));
+            }
+            let coefs_path = session.coefs_path.lock().unwrap().clone();
+            let coefficients = at_rest::read_encrypted_array2(&coefs_path, &self.master_key)
+                .map_err(|e| Status::internal(format!("Failed to read txt file: {}", e)))?;
+            // Several columns are the classes of a softmax model
+            let multiclass = if coefficients.ncols() > 1 {
+                training::Multiclass::Softmax
+            } else {
+                training::Multiclass::Binary
+            };
+            predictor::Model {
+                coefficients,
+                multiclass,
+                schema: None,
+                imputer: None,
+                threshold: metrics::THRESHOLD,
+            }
+        };
+
+        if !request_contents.predict {
+            return Ok(Response::new(model.layout()?));
+        }
+
+        let response = self
+            .predictor(&session, request_contents.mode())
+            .predict(request_contents, model)
+            .await?;
+
+        Ok(Response::new(response))
+    }
+
+    async fn mpc_exchange(
+        &self,
+        request: Request<file::MpcMessage>,
+    ) -> Result<Response<file::MpcMessage>, Status> {
+        let session = self.sessions.get(&request)?;
+        let mut guard = session.mpc_channel.lock().await;
+        let channel = guard
+            .as_mut()
+            .ok_or_else(|| Status::failed_precondition("No secure computation is running"))?;
+
+        channel
+            .to_party
+            .send(request.into_inner().values)
+            .await
+            .map_err(|_| Status::aborted("The secure computation has ended"))?;
+        let values = channel
+            .from_party
+            .recv()
+            .await
+            .ok_or_else(|| Status::aborted("The secure computation has ended"))?;
+
+        Ok(Response::new(file::MpcMessage { values }))
+    }
+
+    async fn open_secure_training(
+        &self,
+        request: Request<file::TrainingKey>,
+    ) -> Result<Response<FileResponse>, Status> {
+        let session = self.sessions.get(&request)?;
+        let modulus = request.into_inner().modulus;
+        if !he::is_valid_modulus(&BigUint::from_bytes_be(&modulus)) {
+            return Err(Status::invalid_argument(format!(
+                "The Paillier modulus must be odd, not a square and have at least {} bits",
+                he::MIN_KEY_BITS
+            )));
+        }
+
+        let mut training = self.secure_training.lock().unwrap();
+        if training.as_ref().is_some_and(SecureTraining::is_pending) {
+            return Err(Status::failed_precondition(
+                "Another secure training is already open",
+            ));
+        }
+        *training = Some(SecureTraining {
+            owner: Arc::downgrade(&session),
+            modulus,
+            contributions: Vec::new(),
+        });
+        println!("Secure training opened");
+
+        Ok(Response::new(FileResponse {
+            message: "OK".to_string(),
+        }))
+    }
+
+    async fn get_training_key(
+        &self,
+        request: Request<file::TrainingKeyRequest>,
+    ) -> Result<Response<file::TrainingKey>, Status> {
+        self.sessions.get(&request)?;
+        let training = self.secure_training.lock().unwrap();
+        let training = training
+            .as_ref()
+            .filter(|training| training.is_pending())
+            .ok_or_else(|| Status::failed_precondition("No secure training is open"))?;
+
+        Ok(Response::new(file::TrainingKey {
+            modulus: training.modulus.clone(),
+        }))
+    }
+
+    async fn send_training_shares(
+        &self,
+        request: Request<file::TrainingShares>,
+    ) -> Result<Response<FileResponse>, Status> {
+        self.sessions.get(&request)?;
+        let shares = request.into_inner();
+        let mut training = self.secure_training.lock().unwrap();
+        let training = training
+            .as_mut()
+            .filter(|training| training.is_pending())
+            .ok_or_else(|| Status::failed_precondition("No secure training is open"))?;
+
+        let rows = shares.rows as usize;
+        let features = shares.features as usize;
+        if rows == 0 || shares.shares.len() != rows * (features + 1) {
+            return Err(Status::invalid_argument(
+                "The shares do not match the size of the dataset",
+            ));
+        }
+        if let Some(first) = training.contributions.first() {
+            if first.features != shares.features {
+                return Err(Status::invalid_argument(format!(
+                    "Expected {} features, got {}",
+                    first.features, shares.features
+                )));
+            }
+        }
+        if shares.encrypted_seed.is_empty() {
+            return Err(Status::invalid_argument("The encrypted seed is missing"));
+        }
+
+        println!("Training shares of {} rows received", rows);
+        training.contributions.push(shares);
+
+        Ok(Response::new(FileResponse {
+            message: "OK".to_string(),
+        }))
+    }
+
+    async fn launch_secure_training(
+        &self,
+        request: Request<file::RequestSecureTraining>,
+    ) -> Result<Response<file::ResponseSecureTraining>, Status> {
+        let session = self.sessions.get(&request)?;
+        let request_contents = request.into_inner();
+        let params = secure_hyperparameters(&request_contents)?;
+        let contributions = {
+            let mut training = self.secure_training.lock().unwrap();
+            match training.as_ref().filter(|training| training.is_pending()) {
+                Some(open) if !open.is_owner(&session) => {
+                    return Err(Status::permission_denied(
+                        "The secure training was opened by another session",
+                    ))
+                }
+                Some(open) if open.contributions.is_empty() => {
+                    return Ok(Response::new(file::ResponseSecureTraining {
+                        message: "No training shares were uploaded".to_string(),
+                        ..Default::default()
+                    }))
+                }
+                Some(_) => training.take().unwrap().contributions,
+                None => {
+                    return Ok(Response::new(file::ResponseSecureTraining {
+                        message: "No secure training is open".to_string(),
+                        ..Default::default()
+                    }))
+                }
+            }
+        };
+
+        let features = contributions[0].features as usize;
+        let rows: usize = contributions.iter().map(|c| c.rows as usize).sum();
+        let mut x1 = Vec::with_capacity(rows * features);
+        let mut y1 = Vec::with_capacity(rows);
+        for row in contributions
+            .iter()
+            .flat_map(|c| c.shares.chunks(features + 1))
+        {
+            x1.extend_from_slice(&row[..features]);
+            y1.push(row[features]);
+        }
+
+        let computation_id = computation_id();
+        let triples = triple_provider(&self.dealer, request_contents.triples(), &computation_id)?;
+
+        // The coefficients are revealed to the model owner only
+        let channel = spawn_party(triples, move |mut party| async move {
+            let (theta, _) =
+                mpc::scaled_logistic_regression(&mut party, &x1, &y1, rows, features, &params)
+                    .await?;
+            party.reveal_to(0, &theta).await?;
+            println!("Secure training finished");
+            Ok(())
+        });
+        *session.mpc_channel.lock().await = Some(channel);
+
+        Ok(Response::new(file::ResponseSecureTraining {
+            contributions: contributions
+                .into_iter()
+                .map(|c| file::TrainingContribution {
+                    rows: c.rows,
+                    encrypted_seed: c.encrypted_seed,
+                })
+                .collect(),
+            features: features as u32,
+            computation_id,
+            ..Default::default()
+        }))
+    }
+}
+
+// Runtime to run our server
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Get the args passed to the program
+    let args: Vec<String> = std::env::args().collect();
+
+    if args.len() != 2 && args.len() != 3 {
+        println!("Usage: {} <port> [<dealer host>:<dealer port>]", args[0]);
+        return Ok(());
+    }
+
+    let cert = std::fs::read_to_string("server.crt")?;
+    let key = std::fs::read_to_string("server.key")?;
+    let server_identity = Identity::from_pem(cert, key);
+
+    let client_ca_cert = std::fs::read_to_string("ca.crt")?;
+    let client_ca_cert = Certificate::from_pem(client_ca_cert);
+
+    // The server authenticates to the dealer with its own certificate
+    let mut dealer = None;
+    if args.len() == 3 {
+        let dealer_tls = ClientTlsConfig::new()
+            .domain_name("localhost")
+            .ca_certificate(client_ca_cert.clone())
+            .identity(server_identity.clone());
+        let uri: Uri = format!("http://{}", args[2]).parse()?;
+        dealer = Some(Channel::builder(uri).tls_config(dealer_tls)?.connect_lazy());
+    }
+
+    // Consider implementing certificate pinning with `rustls` by providing
+    // a custom certificate verifier that checks the server's certificate
+    // against a known, trusted copy.
+
+    let addr = "127.0.0.1:".to_owned() + &args[1];
+    let addr = addr.parse()?;
+    let master_key = MasterKey::load()?;
+    let storage = Storage::from_env()?;
+    let server = MyServer {
+        sessions: Arc::new(SessionStore::new(
+            storage.directory(session::SESSION_DIRECTORY)?,
+            session::SESSION_TIMEOUT,
+            session::MAX_CLIENT_SESSIONS,
+        )),
+        models: model_store::ModelStore::new(
+            storage.directory(model_store::MODEL_DIRECTORY)?,
+            master_key.clone(),
+        ),
+        storage,
+        master_key,
+        dealer,
+        secure_training: Default::default(),
+        quota: Default::default(),
+    };
+
+    // Drop the expired sessions and their files
+    let sessions = server.sessions.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            let expired = sessions.expire();
+            if expired > 0 {
+                println!("{} sessions expired", expired);
+            }
+        }
+    });
+
+    let tls = ServerTlsConfig::new()
+        .identity(server_identity)
+        .client_ca_root(client_ca_cert);
+
+    println!("Hosting on port {}, waiting for commands...", args[1]);
+    Server::builder()
+        .tls_config(tls)?
+        .add_service(FileServer::new(server))
+        .serve(addr)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,323 @@
+// Sessions of the clients of the server. A client opens a session with
+// OpenSession and sends its ID in the session-id metadata of every later call.
+// A session is bound to the certificate of the client that opened it, and
+// expires after a period of inactivity, its directory being deleted. The
+// directories of the sessions of a client share the directory of the client,
+// named after its certificate, and a client may only hold a few live sessions.
+
+use crate::key_exchange::certificate_fingerprint;
+use crypi::SESSION_HEADER;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tonic::{Request, Status};
+
+pub const SESSION_DIRECTORY: &str = "sessions";
+pub const SESSION_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+// Live sessions of a client, the clients without a certificate sharing them
+pub const MAX_CLIENT_SESSIONS: usize = 8;
+
+#[derive(Debug)]
+struct Entry<S> {
+    state: Arc<S>,
+    // SHA-256 of the client certificate, if the connection has one
+    certificate: Option<Vec<u8>>,
+    directory: PathBuf,
+    last_used: Instant,
+}
+
+// State of type S of every session, built from the directory of the session
+#[derive(Debug)]
+pub struct SessionStore<S> {
+    directory: PathBuf,
+    timeout: Duration,
+    max_client_sessions: usize,
+    sessions: Mutex<HashMap<String, Entry<S>>>,
+}
+
+// DER of the certificate the client authenticated with
+pub fn client_certificate<T>(request: &Request<T>) -> Option<Vec<u8>> {
+    let certificates = request.peer_certs()?;
+    Some(certificates.first()?.get_ref().to_vec())
+}
+
+fn certificate<T>(request: &Request<T>) -> Option<Vec<u8>> {
+    client_certificate(request).map(|der| certificate_fingerprint(&der))
+}
+
+// Name of the directory of a client, from the fingerprint of its certificate
+fn client_name(certificate: &Option<Vec<u8>>) -> String {
+    match certificate {
+        Some(fingerprint) => fingerprint[..16]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect(),
+        None => "anonymous".to_string(),
+    }
+}
+
+// Name of the client of a request, from its certificate
+pub fn client_id<T>(request: &Request<T>) -> String {
+    client_name(&certificate(request))
+}
+
+impl<S: From<PathBuf>> SessionStore<S> {
+    pub fn new(
+        directory: impl Into<PathBuf>,
+        timeout: Duration,
+        max_client_sessions: usize,
+    ) -> Self {
+        SessionStore {
+            directory: directory.into(),
+            timeout,
+            max_client_sessions,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    // New session for the client of the request, returning its ID and state,
+    // unless the client already holds the maximum number of live sessions
+    pub fn open<T>(&self, request: &Request<T>) -> Result<(String, Arc<S>), Status> {
+        let id: String = crate::mpc::random_u64s(2)
+            .iter()
+            .map(|w| format!("{:016x}", w))
+            .collect();
+        let certificate = certificate(request);
+        let directory = self.directory.join(client_name(&certificate)).join(&id);
+        let state = Arc::new(S::from(directory.clone()));
+
+        // The session is registered before its directory is created, under the same
+        // lock, so that expire never deletes the directory of a live session
+        let mut sessions = self.sessions.lock().unwrap();
+        let live = sessions
+            .values()
+            .filter(|entry| entry.certificate == certificate)
+            .filter(|entry| entry.last_used.elapsed() < self.timeout)
+            .count();
+        if live >= self.max_client_sessions {
+            return Err(Status::resource_exhausted(format!(
+                "The client already has {} open sessions, close one first",
+                live
+            )));
+        }
+        sessions.insert(
+            id.clone(),
+            Entry {
+                state: state.clone(),
+                certificate,
+                directory: directory.clone(),
+                last_used: Instant::now(),
+            },
+        );
+        if let Err(e) = std::fs::create_dir_all(&directory) {
+            sessions.remove(&id);
+            return Err(Status::internal(format!(
+                "Failed to create the session: {}",
+                e
+            )));
+        }
+        Ok((id, state))
+    }
+
+    fn id<T>(request: &Request<T>) -> Result<String, Status> {
+        request
+            .metadata()
+            .get(SESSION_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(|id| id.to_string())
+            .ok_or_else(|| Status::unauthenticated("No session, call OpenSession first"))
+    }
+
+    // State of the session of the request, which is kept alive
+    pub fn get<T>(&self, request: &Request<T>) -> Result<Arc<S>, Status> {
+        let id = Self::id(request)?;
+        let mut sessions = self.sessions.lock().unwrap();
+        let entry = sessions
+            .get_mut(&id)
+            .filter(|entry| entry.last_used.elapsed() < self.timeout)
+            .ok_or_else(|| Status::unauthenticated(format!("Unknown or expired session {}", id)))?;
+        if entry.certificate != certificate(request) {
+            return Err(Status::permission_denied(
+                "The session belongs to another client",
+            ));
+        }
+        entry.last_used = Instant::now();
+        Ok(entry.state.clone())
+    }
+
+    // Directory of the client of the request, shared by its sessions, and the
+    // state of every live session of the client
+    pub fn client_sessions<T>(&self, request: &Request<T>) -> (PathBuf, Vec<Arc<S>>) {
+        let certificate = certificate(request);
+        let sessions = self
+            .sessions
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|entry| entry.certificate == certificate)
+            .filter(|entry| entry.last_used.elapsed() < self.timeout)
+            .map(|entry| entry.state.clone())
+            .collect();
+        (self.directory.join(client_name(&certificate)), sessions)
+    }
+
+    pub fn close<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        self.get(request)?;
+        let id = Self::id(request)?;
+        let entry = self.sessions.lock().unwrap().remove(&id);
+        if let Some(entry) = entry {
+            let _ = std::fs::remove_dir_all(&entry.directory);
+            // Only removed once the client has no other session
+            if let Some(client) = entry.directory.parent() {
+                let _ = std::fs::remove_dir(client);
+            }
+        }
+        Ok(())
+    }
+
+    // Drop the expired sessions, and delete every directory of the store that
+    // belongs to no session, such as the ones of a previous run of the server,
+    // and the directories of the clients left without sessions. Returns the
+    // number of sessions dropped.
+    pub fn expire(&self) -> usize {
+        // The paths are collected under the lock and deleted once it is released: a
+        // session opened meanwhile is not among them
+        let (dropped, stale, clients) = {
+            let mut sessions = self.sessions.lock().unwrap();
+            let before = sessions.len();
+            sessions.retain(|_, entry| entry.last_used.elapsed() < self.timeout);
+            let live: Vec<&PathBuf> = sessions.values().map(|entry| &entry.directory).collect();
+
+            let mut stale = Vec::new();
+            let mut clients = Vec::new();
+            for client in std::fs::read_dir(&self.directory)
+                .into_iter()
+                .flatten()
+                .flatten()
+            {
+                if !client.path().is_dir() {
+                    stale.push(client.path());
+                    continue;
+                }
+                let mut empty = true;
+                for session in std::fs::read_dir(client.path())
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                {
+                    if live.contains(&&session.path()) {
+                        empty = false;
+                    } else {
+                        stale.push(session.path());
+                    }
+                }
+                if empty {
+                    clients.push(client.path());
+                }
+            }
+            (before - sessions.len(), stale, clients)
+        };
+
+        for path in stale {
+            let _ = std::fs::remove_dir_all(&path).or_else(|_| std::fs::remove_file(&path));
+        }
+        // Only removed if still empty, the client may have opened a session since
+        for client in clients {
+            let _ = std::fs::remove_dir(client);
+        }
+        dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::object_id;
+
+    // Store in a fresh directory under the temporary directory, removed by the test
+    fn store(timeout: Duration, max_client_sessions: usize) -> SessionStore<PathBuf> {
+        let directory = std::env::temp_dir().join(format!("crypi-sessions-{}", object_id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        SessionStore::new(directory, timeout, max_client_sessions)
+    }
+
+    fn request(id: &str) -> Request<()> {
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert(SESSION_HEADER, id.parse().unwrap());
+        request
+    }
+
+    #[test]
+    fn open_get_and_close() {
+        let store = store(SESSION_TIMEOUT, MAX_CLIENT_SESSIONS);
+        let (id, directory) = store.open(&Request::new(())).unwrap();
+        assert!(directory.is_dir());
+        assert_eq!(*store.get(&request(&id)).unwrap(), *directory);
+        assert!(store.get(&Request::new(())).is_err());
+        assert!(store.get(&request("unknown")).is_err());
+
+        store.close(&request(&id)).unwrap();
+        assert!(!directory.exists());
+        assert!(store.get(&request(&id)).is_err());
+        std::fs::remove_dir_all(&store.directory).unwrap();
+    }
+
+    #[test]
+    fn sessions_expire() {
+        let store = store(Duration::from_millis(500), MAX_CLIENT_SESSIONS);
+        let (id, directory) = store.open(&Request::new(())).unwrap();
+        // Left over by a previous run of the server
+        let stale = store.directory.join("anonymous").join("previous");
+        std::fs::create_dir_all(&stale).unwrap();
+        assert_eq!(store.expire(), 0);
+        assert!(directory.is_dir());
+        assert!(!stale.exists());
+
+        std::thread::sleep(Duration::from_millis(600));
+        let status = store.get(&request(&id)).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+        assert_eq!(store.expire(), 1);
+        assert!(!directory.exists());
+        assert!(!store.directory.join("anonymous").exists());
+        std::fs::remove_dir_all(&store.directory).unwrap();
+    }
+
+    #[test]
+    fn sessions_are_kept_alive() {
+        let store = store(Duration::from_millis(1000), MAX_CLIENT_SESSIONS);
+        let (id, _) = store.open(&Request::new(())).unwrap();
+        for _ in 0..3 {
+            std::thread::sleep(Duration::from_millis(400));
+            store.get(&request(&id)).unwrap();
+        }
+        assert_eq!(store.expire(), 0);
+        std::fs::remove_dir_all(&store.directory).unwrap();
+    }
+
+    #[test]
+    fn sessions_per_client_are_capped() {
+        let store = store(Duration::from_millis(500), 2);
+        let (first, _) = store.open(&Request::new(())).unwrap();
+        store.open(&Request::new(())).unwrap();
+        let status = store.open(&Request::new(())).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+
+        // A closed session frees its place
+        store.close(&request(&first)).unwrap();
+        store.open(&Request::new(())).unwrap();
+        assert!(store.open(&Request::new(())).is_err());
+
+        // So does an expired one, even before it is dropped
+        std::thread::sleep(Duration::from_millis(600));
+        store.open(&Request::new(())).unwrap();
+        store.open(&Request::new(())).unwrap();
+        std::fs::remove_dir_all(&store.directory).unwrap();
+    }
+}
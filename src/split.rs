@@ -0,0 +1,258 @@
+// Splitting of the rows of a training dataset into train and test sets, for the
+// held-out evaluation of a training and the folds of a cross-validation. The rows
+// are taken in order, or shuffled with a seed; a stratified split keeps the
+// proportion of every class of the target in every set.
+
+use ndarray::Array1;
+use serde::{Deserialize, Serialize};
+
+// Fraction of the rows held out by default: every fifth row
+pub const TEST_FRACTION: f64 = 0.2;
+pub const FOLDS: usize = 5;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SplitOptions {
+    // Fraction of the rows in the test set of a held-out split
+    pub test_fraction: f64,
+    pub shuffle: bool,
+    pub stratify: bool,
+    // Seed of the shuffle
+    pub seed: u64,
+}
+
+impl Default for SplitOptions {
+    fn default() -> Self {
+        SplitOptions {
+            test_fraction: TEST_FRACTION,
+            shuffle: false,
+            stratify: false,
+            seed: 0,
+        }
+    }
+}
+
+// Row indices of a train set and of its test set
+#[derive(Debug, Clone, PartialEq)]
+pub struct Split {
+    pub train: Vec<usize>,
+    pub test: Vec<usize>,
+}
+
+// SplitMix64
+pub fn next_random(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// Fisher-Yates shuffle
+pub fn shuffle(indices: &mut [usize], state: &mut u64) {
+    for i in (1..indices.len()).rev() {
+        let j = (next_random(state) % (i as u64 + 1)) as usize;
+        indices.swap(i, j);
+    }
+}
+
+impl SplitOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.test_fraction > 0.0 && self.test_fraction < 1.0) {
+            return Err("The test fraction must be between 0 and 1".to_string());
+        }
+        Ok(())
+    }
+
+    // Groups of rows split separately, in the order they are dealt: the rows of
+    // every class if stratified, all the rows otherwise
+    fn groups(&self, y: &Array1<f64>) -> Vec<Vec<usize>> {
+        let mut groups: Vec<(f64, Vec<usize>)> = Vec::new();
+        for (i, &label) in y.iter().enumerate() {
+            let label = if self.stratify { label } else { 0.0 };
+            match groups.iter_mut().find(|(l, _)| *l == label) {
+                Some((_, rows)) => rows.push(i),
+                None => groups.push((label, vec![i])),
+            }
+        }
+        let mut state = self.seed;
+        groups
+            .into_iter()
+            .map(|(_, mut rows)| {
+                if self.shuffle {
+                    shuffle(&mut rows, &mut state);
+                }
+                rows
+            })
+            .collect()
+    }
+
+    // Train and test sets, the test rows spread evenly over every group. Unshuffled
+    // with the default fraction, the test set is every fifth row.
+    pub fn holdout(&self, y: &Array1<f64>) -> Result<Split, String> {
+        self.validate()?;
+        if y.len() < 2 {
+            return Err("The dataset needs at least two rows to be split".to_string());
+        }
+        let mut split = Split {
+            train: Vec::new(),
+            test: Vec::new(),
+        };
+        for rows in self.groups(y) {
+            let n = rows.len();
+            let tests = ((n as f64 * self.test_fraction).round() as usize).min(n);
+            // Position k is a test row when [k, k + 1) * tests / n holds an integer
+            for (k, row) in rows.into_iter().enumerate() {
+                if (k * tests).div_ceil(n) < ((k + 1) * tests).div_ceil(n) {
+                    split.test.push(row);
+                } else {
+                    split.train.push(row);
+                }
+            }
+        }
+        if split.train.is_empty() || split.test.is_empty() {
+            return Err("The split leaves the train or the test set empty".to_string());
+        }
+        split.train.sort_unstable();
+        split.test.sort_unstable();
+        Ok(split)
+    }
+
+    // Folds of a k-fold cross-validation, the rows dealt in turn to every fold. The
+    // test fraction is not used.
+    pub fn k_fold(&self, y: &Array1<f64>, folds: usize) -> Result<Vec<Split>, String> {
+        if folds < 2 || folds > y.len() {
+            return Err(format!(
+                "The cross-validation needs between 2 and {} folds",
+                y.len()
+            ));
+        }
+        let mut fold_of = vec![0; y.len()];
+        // The count runs across the groups, so that the folds have even sizes
+        let mut k = 0;
+        for rows in self.groups(y) {
+            for row in rows {
+                fold_of[row] = k % folds;
+                k += 1;
+            }
+        }
+        Ok((0..folds)
+            .map(|fold| {
+                let (test, train) = (0..y.len()).partition(|&i| fold_of[i] == fold);
+                Split { train, test }
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 12 rows of class 0 and 6 of class 1, the class 1 rows at the end
+    fn imbalanced() -> Array1<f64> {
+        Array1::from_iter((0..18).map(|i| (i >= 12) as u32 as f64))
+    }
+
+    fn positives(rows: &[usize], y: &Array1<f64>) -> usize {
+        rows.iter().filter(|&&i| y[i] == 1.0).count()
+    }
+
+    // Every row in exactly one of the two sets
+    fn assert_partition(split: &Split, n: usize) {
+        let mut rows: Vec<usize> = split
+            .train
+            .iter()
+            .chain(split.test.iter())
+            .copied()
+            .collect();
+        rows.sort_unstable();
+        assert_eq!(rows, (0..n).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn default_holdout_is_every_fifth_row() {
+        let y = Array1::zeros(10);
+        let split = SplitOptions::default().holdout(&y).unwrap();
+        assert_eq!(split.test, vec![0, 5]);
+        assert_partition(&split, 10);
+    }
+
+    #[test]
+    fn holdout_sizes() {
+        let y = imbalanced();
+        for (fraction, tests) in [(0.2, 4), (0.5, 9), (0.75, 14)] {
+            let options = SplitOptions {
+                test_fraction: fraction,
+                shuffle: true,
+                seed: 7,
+                ..SplitOptions::default()
+            };
+            let split = options.holdout(&y).unwrap();
+            assert_eq!(split.test.len(), tests);
+            assert_partition(&split, y.len());
+            // The same seed gives the same split
+            assert_eq!(options.holdout(&y).unwrap(), split);
+        }
+    }
+
+    #[test]
+    fn stratified_holdout_keeps_the_proportions() {
+        let y = imbalanced();
+        let options = SplitOptions {
+            test_fraction: 1.0 / 3.0,
+            shuffle: true,
+            stratify: true,
+            seed: 42,
+        };
+        let split = options.holdout(&y).unwrap();
+        assert_partition(&split, y.len());
+        assert_eq!(split.test.len(), 6);
+        assert_eq!(positives(&split.test, &y), 2);
+        assert_eq!(positives(&split.train, &y), 4);
+    }
+
+    #[test]
+    fn invalid_holdouts() {
+        let options = SplitOptions {
+            test_fraction: 1.0,
+            ..SplitOptions::default()
+        };
+        assert!(options.holdout(&imbalanced()).is_err());
+        assert!(SplitOptions::default().holdout(&Array1::zeros(1)).is_err());
+        // A test set rounded down to no row
+        assert!(SplitOptions::default().holdout(&Array1::zeros(2)).is_err());
+    }
+
+    #[test]
+    fn k_fold_sizes() {
+        let y = imbalanced();
+        let folds = SplitOptions::default().k_fold(&y, 4).unwrap();
+        assert_eq!(folds.len(), 4);
+        let mut tested = Vec::new();
+        for fold in folds.iter() {
+            assert_partition(fold, y.len());
+            assert!(fold.test.len() == 4 || fold.test.len() == 5);
+            tested.extend(fold.test.iter().copied());
+        }
+        tested.sort_unstable();
+        assert_eq!(tested, (0..y.len()).collect::<Vec<usize>>());
+
+        assert!(SplitOptions::default().k_fold(&y, 1).is_err());
+        assert!(SplitOptions::default().k_fold(&y, 19).is_err());
+    }
+
+    #[test]
+    fn stratified_k_fold_keeps_the_proportions() {
+        let y = imbalanced();
+        let options = SplitOptions {
+            shuffle: true,
+            stratify: true,
+            seed: 3,
+            ..SplitOptions::default()
+        };
+        for fold in options.k_fold(&y, 3).unwrap() {
+            assert_eq!(fold.test.len(), 6);
+            assert_eq!(positives(&fold.test, &y), 2);
+        }
+    }
+}
@@ -0,0 +1,203 @@
+// Storage of the server, confined to a root directory: CRYPI_STORAGE_DIR, storage
+// by default. The files uploaded by a client are kept in its own subdirectory,
+// sessions/<client>/<session ID>/, under names the server generates, and their
+// total size is limited by a quota per client, CRYPI_CLIENT_QUOTA bytes.
+
+use std::error::Error;
+use std::path::{Component, Path, PathBuf};
+
+pub const STORAGE_DIR_ENV: &str = "CRYPI_STORAGE_DIR";
+pub const STORAGE_DIR: &str = "storage";
+pub const CLIENT_QUOTA_ENV: &str = "CRYPI_CLIENT_QUOTA";
+pub const CLIENT_QUOTA: u64 = 256 * 1024 * 1024;
+
+// Longest name of an uploaded file the server keeps
+const MAX_NAME_LENGTH: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct Storage {
+    root: PathBuf,
+    // Bytes every client may store
+    pub quota: u64,
+}
+
+impl Storage {
+    pub fn from_env() -> Result<Storage, Box<dyn Error>> {
+        let root = std::env::var(STORAGE_DIR_ENV).unwrap_or(STORAGE_DIR.to_string());
+        let quota = match std::env::var(CLIENT_QUOTA_ENV) {
+            Ok(quota) => quota
+                .trim()
+                .parse()
+                .map_err(|e| format!("Invalid {}: {}", CLIENT_QUOTA_ENV, e))?,
+            Err(_) => CLIENT_QUOTA,
+        };
+        std::fs::create_dir_all(&root)?;
+        Ok(Storage {
+            root: Path::new(&root).canonicalize()?,
+            quota,
+        })
+    }
+
+    // Subdirectory of the root
+    pub fn directory(&self, name: &str) -> Result<PathBuf, Box<dyn Error>> {
+        confine(&self.root, name)
+    }
+
+    // Refuse `size` more bytes to a client which already uses `used` bytes
+    pub fn check_quota(&self, used: u64, size: u64) -> Result<(), String> {
+        if used.saturating_add(size) > self.quota {
+            return Err(format!(
+                "The upload of {} bytes exceeds the quota of the client, {} of {} bytes used",
+                size, used, self.quota
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Random name of a stored object
+pub fn object_id() -> String {
+    crate::mpc::random_u64s(2)
+        .iter()
+        .map(|w| format!("{:016x}", w))
+        .collect()
+}
+
+// Path of `name` in `directory`, which must be a single plain file name and not
+// a symbolic link, which could lead out of the directory
+pub fn confine(directory: &Path, name: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let mut components = Path::new(name).components();
+    let path = match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => directory.join(name),
+        _ => return Err(format!("Invalid file name {:?}", name).into()),
+    };
+    match std::fs::symlink_metadata(&path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            Err(format!("{:?} is a symbolic link", name).into())
+        }
+        _ => Ok(path),
+    }
+}
+
+// Base name of a file name sent by a client, fit for the logs: only ASCII
+// letters, digits, '.', '-' and '_', and no leading dot
+pub fn sanitize(name: &str) -> String {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let clean: String = base
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "._-".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(MAX_NAME_LENGTH)
+        .collect();
+    match clean.trim_start_matches('.') {
+        "" => "unnamed".to_string(),
+        clean => clean.to_string(),
+    }
+}
+
+// Total size of the files under a directory
+pub fn usage(directory: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(directory) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.file_type() {
+            Ok(kind) if kind.is_dir() => usage(&entry.path()),
+            Ok(kind) if kind.is_file() => entry.metadata().map(|m| m.len()).unwrap_or(0),
+            _ => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Fresh directory under the temporary directory, removed by the test
+    fn scratch() -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("crypi-storage-{}", object_id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn confined_names() {
+        let root = Path::new("/srv/storage");
+        assert_eq!(
+            confine(root, "sessions").unwrap(),
+            Path::new("/srv/storage/sessions")
+        );
+        assert_eq!(
+            confine(root, ".hidden").unwrap(),
+            Path::new("/srv/storage/.hidden")
+        );
+        for name in [
+            "",
+            ".",
+            "..",
+            "../etc",
+            "a/../..",
+            "/etc/passwd",
+            "a/b",
+            "/",
+        ] {
+            assert!(confine(root, name).is_err(), "{:?} was accepted", name);
+        }
+    }
+
+    #[test]
+    fn symbolic_links_are_refused() {
+        let directory = scratch();
+        std::fs::create_dir(directory.join("plain")).unwrap();
+        std::os::unix::fs::symlink("/etc", directory.join("link")).unwrap();
+        assert!(confine(&directory, "plain").is_ok());
+        assert!(confine(&directory, "link").is_err());
+
+        // Nor counted in the usage of the directory
+        std::fs::write(directory.join("plain/data.csv"), [0u8; 10]).unwrap();
+        assert_eq!(usage(&directory), 10);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn sanitized_names() {
+        assert_eq!(sanitize("train.csv"), "train.csv");
+        assert_eq!(sanitize("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize("C:\\data\\test.csv"), "test.csv");
+        assert_eq!(sanitize("/absolute/model.txt"), "model.txt");
+        assert_eq!(sanitize(".bashrc"), "bashrc");
+        assert_eq!(sanitize("..."), "unnamed");
+        assert_eq!(sanitize(""), "unnamed");
+        assert_eq!(sanitize("dir/"), "unnamed");
+        assert_eq!(sanitize("a b;c.csv"), "a_b_c.csv");
+        assert_eq!(sanitize(&"x".repeat(100)).len(), MAX_NAME_LENGTH);
+    }
+
+    #[test]
+    fn quota() {
+        let directory = scratch();
+        let storage = Storage {
+            root: directory.clone(),
+            quota: 100,
+        };
+        let client = storage.directory("client").unwrap();
+        std::fs::create_dir_all(client.join("session")).unwrap();
+        std::fs::write(client.join("session/a.csv"), [0u8; 40]).unwrap();
+        std::fs::write(client.join("b.txt"), [0u8; 20]).unwrap();
+
+        let used = usage(&client);
+        assert_eq!(used, 60);
+        assert!(storage.check_quota(used, 40).is_ok());
+        assert!(storage.check_quota(used, 41).is_err());
+        assert!(storage.check_quota(used, u64::MAX).is_err());
+        assert!(storage.check_quota(100, 0).is_ok());
+        assert!(storage.check_quota(100, 1).is_err());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
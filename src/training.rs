@@ -1,43 +1,762 @@
-use ndarray::{Array, Array1, Array2};
-
-pub fn sigmoid(z: &Array1<f64>) -> Array1<f64> {
-    let one: f64 = 1.0;
-    one / (one + (-z).mapv(f64::exp))
-}
-
-pub fn logistic_regression(
-    X: &Array2<f64>,
-    y: &Array1<f64>,
-    alpha: f64,
-    iterations: usize,
-) -> Array1<f64> {
-    let m = X.nrows() as f64;
-    let mut theta = Array::zeros(X.ncols());
-
-    for _ in 0..iterations {
-        let h = sigmoid(&(X.dot(&theta)));
-        let gradient = (X.t().dot(&(h - y))) / m;
-        theta -= &(gradient * alpha);
-    }
-
-    theta
-}
-
-pub fn train_log_reg(X_train: &Array2<f64>, y_train: &Array1<f64>) -> Array1<f64> {
-    // Implement training a logistic regression model
-    let alpha = 0.01; // Learning rate
-    let iterations = 1000; // Number of iterations for gradient descent
-    let model = logistic_regression(&X_train, &y_train, alpha, iterations);
-    model
-}
-
-pub fn predict(model: &Array1<f64>, X: &Array2<f64>) -> Array1<f64> {
-    let preds = sigmoid(&(X.dot(&model.t())));
-    preds.mapv(|p| if p >= 0.5 { 1.0 } else { 0.0 })
-}
-
-pub fn model_accuracy(model: &Array1<f64>, X_test: &Array2<f64>, y_test: &Array1<f64>) -> f64 {
-    let y_pred = predict(model, X_test);
-    let correct_preds = (y_pred - y_test).mapv(|x| (x == 0.0) as u32).sum();
-    correct_preds as f64 / y_test.len() as f64
-}
+use crate::metrics::{self, ThresholdMetric};
+use crate::resample::Resampling;
+use crate::split;
+use ndarray::{Array, Array1, Array2, ArrayView2, Axis};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+pub fn sigmoid(z: &Array1<f64>) -> Array1<f64> {
+    let one: f64 = 1.0;
+    one / (one + (-z).mapv(f64::exp))
+}
+
+// Softmax of every row of the logits
+pub fn softmax(Z: &Array2<f64>) -> Array2<f64> {
+    let mut P = Z.to_owned();
+    for mut row in P.rows_mut() {
+        let max = row.fold(f64::NEG_INFINITY, |m, &v| m.max(v));
+        row.mapv_inplace(|v| (v - max).exp());
+        let sum = row.sum();
+        row /= sum;
+    }
+    P
+}
+
+// Learning rate and number of iterations of the gradient descent, shared with
+// the secure training
+pub const ALPHA: f64 = 0.01;
+pub const ITERATIONS: usize = 1000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Optimizer {
+    // Gradient descent on mini-batches, or on the whole training set
+    Sgd,
+    // Gradient descent with heavy-ball momentum
+    Momentum,
+    Adam,
+    // Quasi-Newton on the whole training set, with a backtracking line search
+    Lbfgs,
+}
+
+// Model of the probabilities of the classes of the target
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Multiclass {
+    // Sigmoid of one linear model, the target being 0 or 1
+    #[default]
+    Binary,
+    // Multinomial logistic regression, one linear model per class
+    Softmax,
+    // One binary model per class against the others, the probabilities being
+    // normalized to sum to 1
+    OneVsRest,
+}
+
+// Weights of the rows of every class in the loss
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ClassWeights {
+    #[default]
+    Uniform,
+    // Inversely proportional to the frequency of the class in the train set
+    Balanced,
+    Custom {
+        negative: f64,
+        positive: f64,
+    },
+}
+
+// Hyperparameters of the training of a logistic regression
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Hyperparameters {
+    pub learning_rate: f64,
+    // Passes over the training set, iterations of L-BFGS
+    pub epochs: usize,
+    // Rows of every gradient step, 0 for the whole training set
+    pub batch_size: usize,
+    pub optimizer: Optimizer,
+    // Strengths of the L1 and L2 penalties, both for an elastic net. The intercept
+    // is not penalized.
+    pub l1: f64,
+    pub l2: f64,
+    // Whether the first feature is a column of ones, whose coefficient is the intercept
+    pub intercept: bool,
+    // The training stops once an epoch improves the loss by less, never if 0
+    pub tolerance: f64,
+    // Seed of the order of the rows in the mini-batches
+    pub seed: u64,
+    pub class_weights: ClassWeights,
+    // Resampling of the train set before the training
+    pub resampling: Resampling,
+    // Metric the decision threshold maximizes on the train set, 0.5 if none
+    pub threshold_metric: Option<ThresholdMetric>,
+    pub multiclass: Multiclass,
+}
+
+impl Default for Hyperparameters {
+    fn default() -> Self {
+        Hyperparameters {
+            learning_rate: ALPHA,
+            epochs: ITERATIONS,
+            batch_size: 0,
+            optimizer: Optimizer::Sgd,
+            l1: 0.0,
+            l2: 0.0,
+            intercept: true,
+            tolerance: 0.0,
+            seed: 0,
+            class_weights: ClassWeights::Uniform,
+            resampling: Resampling::default(),
+            threshold_metric: None,
+            multiclass: Multiclass::Binary,
+        }
+    }
+}
+
+impl Hyperparameters {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.learning_rate > 0.0 && self.learning_rate.is_finite()) {
+            return Err("The learning rate must be positive".to_string());
+        }
+        if self.epochs == 0 {
+            return Err("The training needs at least one epoch".to_string());
+        }
+        if !(self.l1 >= 0.0 && self.l2 >= 0.0 && self.tolerance >= 0.0) {
+            return Err("The penalties and the tolerance must be non-negative".to_string());
+        }
+        // The L1 penalty has no gradient at 0, which the line search relies on
+        if self.optimizer == Optimizer::Lbfgs && self.l1 > 0.0 {
+            return Err("L-BFGS only supports the L2 penalty".to_string());
+        }
+        if let ClassWeights::Custom { negative, positive } = self.class_weights {
+            if !(negative > 0.0 && positive > 0.0) {
+                return Err("The class weights must be positive".to_string());
+            }
+        }
+        if self.multiclass != Multiclass::Binary && self.threshold_metric.is_some() {
+            return Err("The decision threshold can only be tuned for a binary model".to_string());
+        }
+        // A softmax model has no single positive class
+        if self.multiclass == Multiclass::Softmax
+            && matches!(self.class_weights, ClassWeights::Custom { .. })
+        {
+            return Err("Custom class weights need a binary or one-vs-rest model".to_string());
+        }
+        self.resampling.validate()
+    }
+}
+
+// Coefficients of a trained model, one column for a binary model and one per class
+// otherwise, the epochs the training ran before it converged, and the decision
+// threshold on the probability of the positive class
+#[derive(Debug, Clone)]
+pub struct Fit {
+    pub theta: Array2<f64>,
+    pub multiclass: Multiclass,
+    pub epochs: usize,
+    pub threshold: f64,
+}
+
+impl Fit {
+    // Probability of every class for every row of X
+    pub fn class_proba(&self, X: &Array2<f64>) -> Array2<f64> {
+        class_proba(&self.theta, X, self.multiclass)
+    }
+}
+
+// Weight of every row of the train set in the loss, the labels being the indices
+// of `classes` classes. The custom weights are the ones of the classes 0 and 1.
+fn row_weights(y: &Array1<f64>, class_weights: ClassWeights, classes: usize) -> Array1<f64> {
+    match class_weights {
+        ClassWeights::Uniform => Array::ones(y.len()),
+        ClassWeights::Custom { negative, positive } => {
+            y.mapv(|label| if label == 1.0 { positive } else { negative })
+        }
+        // n / (k n_c), 1 for a class missing from the train set
+        ClassWeights::Balanced => {
+            let mut counts = vec![0.0; classes];
+            for &label in y.iter() {
+                counts[label as usize] += 1.0;
+            }
+            let weights: Vec<f64> = counts
+                .iter()
+                .map(|&count| {
+                    if count > 0.0 {
+                        y.len() as f64 / (classes as f64 * count)
+                    } else {
+                        1.0
+                    }
+                })
+                .collect();
+            y.mapv(|label| weights[label as usize])
+        }
+    }
+}
+
+// Mask of the penalized coefficients: all but the intercepts, which are the first
+// `outputs` coefficients
+fn penalized(params: &Hyperparameters, n: usize, outputs: usize) -> Array1<f64> {
+    let mut mask = Array::ones(n);
+    if params.intercept {
+        for value in mask.iter_mut().take(outputs) {
+            *value = 0.0;
+        }
+    }
+    mask
+}
+
+// Training set with the weight of every row, for a model of `outputs` columns: 1
+// for a sigmoid, one per class for a softmax
+struct Rows<'a> {
+    X: &'a Array2<f64>,
+    y: &'a Array1<f64>,
+    w: &'a Array1<f64>,
+    outputs: usize,
+}
+
+impl Rows<'_> {
+    // Coefficients flattened by the optimizers, as a (features x outputs) matrix
+    fn coefficients<'t>(&self, theta: &'t Array1<f64>) -> ArrayView2<'t, f64> {
+        theta
+            .view()
+            .into_shape((self.X.ncols(), self.outputs))
+            .expect("The coefficients have one row per feature")
+    }
+}
+
+// One-hot encoding of the labels, one column per class
+fn one_hot(y: &Array1<f64>, classes: usize) -> Array2<f64> {
+    let mut Y = Array2::zeros((y.len(), classes));
+    for (i, &label) in y.iter().enumerate() {
+        Y[[i, label as usize]] = 1.0;
+    }
+    Y
+}
+
+// Mean weighted log-loss of the model on the rows, with the L2 penalty and, if
+// `l1`, the L1 penalty
+fn loss(theta: &Array1<f64>, rows: &Rows, params: &Hyperparameters) -> f64 {
+    let mask = penalized(params, theta.len(), rows.outputs);
+    let weights = theta * &mask;
+    let losses = if rows.outputs == 1 {
+        row_losses(&predict_proba(theta, rows.X), rows.y)
+    } else {
+        class_losses(&softmax(&rows.X.dot(&rows.coefficients(theta))), rows.y)
+    };
+    (losses * rows.w).sum() / rows.y.len().max(1) as f64
+        + params.l1 * weights.mapv(f64::abs).sum()
+        + params.l2 / 2.0 * weights.dot(&weights)
+}
+
+// Gradient of the mean weighted log-loss with the L2 penalty, the L1 penalty being
+// applied by the optimizers
+fn gradient(theta: &Array1<f64>, rows: &Rows, params: &Hyperparameters) -> Array1<f64> {
+    let m = rows.X.nrows().max(1) as f64;
+    let residuals = if rows.outputs == 1 {
+        (sigmoid(&rows.X.dot(theta)) - rows.y).insert_axis(Axis(1))
+    } else {
+        softmax(&rows.X.dot(&rows.coefficients(theta))) - one_hot(rows.y, rows.outputs)
+    };
+    let w = rows.w.view().insert_axis(Axis(1));
+    let g = rows.X.t().dot(&(residuals * w)) / m;
+    Array1::from_iter(g.iter().copied())
+        + theta * &penalized(params, theta.len(), rows.outputs) * params.l2
+}
+
+// Proximal step of the L1 penalty: the penalized coefficients shrink towards 0
+fn shrink(theta: &mut Array1<f64>, step: f64, params: &Hyperparameters, outputs: usize) {
+    if params.l1 == 0.0 {
+        return;
+    }
+    let mask = penalized(params, theta.len(), outputs);
+    let threshold = step * params.l1;
+    for (value, &penalized) in theta.iter_mut().zip(mask.iter()) {
+        if penalized > 0.0 {
+            *value = value.signum() * (value.abs() - threshold).max(0.0);
+        }
+    }
+}
+
+// Row indices of the mini-batches of an epoch, shuffled unless the batch is the
+// whole training set
+fn batches(rows: usize, batch_size: usize, state: &mut u64) -> Vec<Vec<usize>> {
+    let mut order: Vec<usize> = (0..rows).collect();
+    if batch_size == 0 || batch_size >= rows {
+        return vec![order];
+    }
+    split::shuffle(&mut order, state);
+    order.chunks(batch_size).map(|c| c.to_vec()).collect()
+}
+
+// First-order optimizers, one step per mini-batch. Coefficients and epochs run.
+fn first_order(rows: &Rows, params: &Hyperparameters) -> (Array1<f64>, usize) {
+    const BETA1: f64 = 0.9;
+    const BETA2: f64 = 0.999;
+    const EPSILON: f64 = 1e-8;
+
+    let n = rows.X.ncols() * rows.outputs;
+    let rate = params.learning_rate;
+    let mut theta = Array::zeros(n);
+    // Velocity of the momentum, first moment of Adam
+    let mut velocity: Array1<f64> = Array::zeros(n);
+    // Second moment of Adam
+    let mut second: Array1<f64> = Array::zeros(n);
+    let mut steps = 0;
+    let mut state = params.seed;
+    let mut previous = loss(&theta, rows, params);
+
+    for epoch in 1..=params.epochs {
+        for batch in batches(rows.X.nrows(), params.batch_size, &mut state) {
+            let g = if batch.len() == rows.X.nrows() {
+                gradient(&theta, rows, params)
+            } else {
+                let (X, y, w) = (
+                    rows.X.select(Axis(0), &batch),
+                    rows.y.select(Axis(0), &batch),
+                    rows.w.select(Axis(0), &batch),
+                );
+                gradient(
+                    &theta,
+                    &Rows {
+                        X: &X,
+                        y: &y,
+                        w: &w,
+                        outputs: rows.outputs,
+                    },
+                    params,
+                )
+            };
+            steps += 1;
+            match params.optimizer {
+                Optimizer::Momentum => {
+                    velocity = velocity * BETA1 + &g;
+                    theta -= &(&velocity * rate);
+                }
+                Optimizer::Adam => {
+                    velocity = velocity * BETA1 + &g * (1.0 - BETA1);
+                    second = second * BETA2 + g.mapv(|v| v * v) * (1.0 - BETA2);
+                    let m_hat = &velocity / (1.0 - BETA1.powi(steps));
+                    let v_hat = &second / (1.0 - BETA2.powi(steps));
+                    theta -= &(m_hat / (v_hat.mapv(f64::sqrt) + EPSILON) * rate);
+                }
+                _ => theta -= &(g * rate),
+            }
+            shrink(&mut theta, rate, params, rows.outputs);
+        }
+
+        if params.tolerance > 0.0 {
+            let current = loss(&theta, rows, params);
+            if (previous - current).abs() < params.tolerance {
+                return (theta, epoch);
+            }
+            previous = current;
+        }
+    }
+    (theta, params.epochs)
+}
+
+// L-BFGS with the last 10 corrections, and an Armijo backtracking line search
+fn lbfgs(rows: &Rows, params: &Hyperparameters) -> (Array1<f64>, usize) {
+    const HISTORY: usize = 10;
+
+    let mut theta: Array1<f64> = Array::zeros(rows.X.ncols() * rows.outputs);
+    let mut value = loss(&theta, rows, params);
+    let mut g = gradient(&theta, rows, params);
+    // Corrections (s, y, 1 / y.s), the oldest first
+    let mut corrections: VecDeque<(Array1<f64>, Array1<f64>, f64)> = VecDeque::new();
+
+    for epoch in 1..=params.epochs {
+        // Two-loop recursion: direction = -H g
+        let mut q = g.clone();
+        let mut alphas = Vec::with_capacity(corrections.len());
+        for (s, yk, rho) in corrections.iter().rev() {
+            let alpha = rho * s.dot(&q);
+            q -= &(yk * alpha);
+            alphas.push(alpha);
+        }
+        let gamma = match corrections.back() {
+            Some((s, yk, _)) => s.dot(yk) / yk.dot(yk),
+            None => params.learning_rate,
+        };
+        let mut r = q * gamma;
+        for ((s, yk, rho), alpha) in corrections.iter().zip(alphas.iter().rev()) {
+            let beta = rho * yk.dot(&r);
+            r += &(s * (alpha - beta));
+        }
+        let direction = -r;
+
+        let slope = g.dot(&direction);
+        if slope >= 0.0 {
+            break;
+        }
+        let mut step = 1.0;
+        let mut next = &theta + &(&direction * step);
+        let mut next_value = loss(&next, rows, params);
+        while next_value > value + 1e-4 * step * slope && step > 1e-10 {
+            step /= 2.0;
+            next = &theta + &(&direction * step);
+            next_value = loss(&next, rows, params);
+        }
+
+        let next_g = gradient(&next, rows, params);
+        let s = &next - &theta;
+        let yk = &next_g - &g;
+        let curvature = s.dot(&yk);
+        if curvature > 1e-10 {
+            corrections.push_back((s, yk, 1.0 / curvature));
+            if corrections.len() > HISTORY {
+                corrections.pop_front();
+            }
+        }
+
+        let improvement = value - next_value;
+        theta = next;
+        value = next_value;
+        g = next_g;
+        if params.tolerance > 0.0 && improvement.abs() < params.tolerance {
+            return (theta, epoch);
+        }
+    }
+    (theta, params.epochs)
+}
+
+// Coefficients of a model of `outputs` columns fitted on (X, y) with the
+// optimizer, as a (features x outputs) matrix, and the epochs run. The labels are
+// 0 or 1 for a sigmoid, the indices of `outputs` classes for a softmax.
+fn optimize(
+    X: &Array2<f64>,
+    y: &Array1<f64>,
+    outputs: usize,
+    params: &Hyperparameters,
+) -> (Array2<f64>, usize) {
+    // A sigmoid separates two classes
+    let w = row_weights(y, params.class_weights, outputs.max(2));
+    let rows = Rows {
+        X,
+        y,
+        w: &w,
+        outputs,
+    };
+    let (theta, epochs) = match params.optimizer {
+        Optimizer::Lbfgs => lbfgs(&rows, params),
+        _ => first_order(&rows, params),
+    };
+    let theta = theta
+        .into_shape((X.ncols(), outputs))
+        .expect("The coefficients have one row per feature");
+    (theta, epochs)
+}
+
+// Train a logistic regression on (X, y), X having a column of ones first if the
+// hyperparameters ask for an intercept and y holding the indices of `classes`
+// classes: the train set is resampled, the model is fitted on it with the class
+// weights, and the decision threshold of a binary model is tuned on the train set
+// as it was before the resampling. A one-vs-rest model runs the epochs of its
+// slowest class.
+pub fn fit(X: &Array2<f64>, y: &Array1<f64>, classes: usize, params: &Hyperparameters) -> Fit {
+    let (X_fit, y_fit) = params.resampling.apply(X, y);
+    let (theta, epochs) = match params.multiclass {
+        Multiclass::Binary => optimize(&X_fit, &y_fit, 1, params),
+        Multiclass::Softmax => optimize(&X_fit, &y_fit, classes, params),
+        Multiclass::OneVsRest => {
+            let mut theta = Array2::zeros((X.ncols(), classes));
+            let mut epochs = 0;
+            for (k, mut column) in theta.columns_mut().into_iter().enumerate() {
+                let y_k = y_fit.mapv(|label| (label == k as f64) as u32 as f64);
+                let (theta_k, epochs_k) = optimize(&X_fit, &y_k, 1, params);
+                column.assign(&theta_k.column(0));
+                epochs = epochs.max(epochs_k);
+            }
+            (theta, epochs)
+        }
+    };
+    let threshold = match params.threshold_metric {
+        Some(metric) => {
+            let probabilities = predict_proba(&theta.column(0).to_owned(), X);
+            metrics::tune_threshold(&probabilities, y, metric)
+        }
+        None => metrics::THRESHOLD,
+    };
+    Fit {
+        theta,
+        multiclass: params.multiclass,
+        epochs,
+        threshold,
+    }
+}
+
+// Cross-entropy of the probability of the positive class of every row
+fn row_losses(probabilities: &Array1<f64>, y: &Array1<f64>) -> Array1<f64> {
+    const EPSILON: f64 = 1e-15;
+    probabilities
+        .iter()
+        .zip(y.iter())
+        .map(|(&p, &label)| {
+            let p = p.clamp(EPSILON, 1.0 - EPSILON);
+            -(label * p.ln() + (1.0 - label) * (1.0 - p).ln())
+        })
+        .collect()
+}
+
+// Cross-entropy of the probability of the class of every row
+fn class_losses(probabilities: &Array2<f64>, y: &Array1<f64>) -> Array1<f64> {
+    const EPSILON: f64 = 1e-15;
+    y.iter()
+        .enumerate()
+        .map(|(i, &label)| -probabilities[[i, label as usize]].max(EPSILON).ln())
+        .collect()
+}
+
+// Mean cross-entropy of the probabilities of every class, one column per class
+pub fn class_log_loss(probabilities: &Array2<f64>, y: &Array1<f64>) -> f64 {
+    if y.is_empty() {
+        return 0.0;
+    }
+    class_losses(probabilities, y).sum() / y.len() as f64
+}
+
+// Mean cross-entropy of the probabilities of the positive class
+pub fn log_loss(probabilities: &Array1<f64>, y: &Array1<f64>) -> f64 {
+    if y.is_empty() {
+        return 0.0;
+    }
+    row_losses(probabilities, y).sum() / y.len() as f64
+}
+
+// Probability of the positive class for every row of X
+pub fn predict_proba(model: &Array1<f64>, X: &Array2<f64>) -> Array1<f64> {
+    sigmoid(&(X.dot(&model.t())))
+}
+
+// Probability of every class for every row of X, one column per class, with the
+// coefficients of a model of the given kind. A binary model gives the columns of
+// the classes 0 and 1.
+pub fn class_proba(theta: &Array2<f64>, X: &Array2<f64>, multiclass: Multiclass) -> Array2<f64> {
+    let Z = X.dot(theta);
+    match multiclass {
+        Multiclass::Binary => {
+            let p = sigmoid(&Z.column(0).to_owned());
+            ndarray::stack(Axis(1), &[(1.0 - &p).view(), p.view()])
+                .expect("The columns have one value per row")
+        }
+        Multiclass::Softmax => softmax(&Z),
+        Multiclass::OneVsRest => {
+            let mut P = Z.mapv(|z| 1.0 / (1.0 + (-z).exp()));
+            for mut row in P.rows_mut() {
+                let sum = row.sum();
+                if sum > 0.0 {
+                    row /= sum;
+                }
+            }
+            P
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    // Points of a grid with a column of ones first, labeled by the side of the line
+    // x1 + x2 = 0 they are on, the points of the line left out
+    fn separable() -> (Array2<f64>, Array1<f64>) {
+        let mut rows = Vec::new();
+        let mut labels = Vec::new();
+        for i in -5..=5 {
+            for j in -5..=5 {
+                if i + j == 0 {
+                    continue;
+                }
+                rows.extend([1.0, i as f64 / 5.0, j as f64 / 5.0]);
+                labels.push((i + j > 0) as u32 as f64);
+            }
+        }
+        (
+            Array2::from_shape_vec((labels.len(), 3), rows).unwrap(),
+            Array1::from(labels),
+        )
+    }
+
+    // Three clusters around (2, 0), (-2, 2) and (-2, -2), with a column of ones first
+    fn clusters() -> (Array2<f64>, Array1<f64>) {
+        let centers = [(2.0, 0.0), (-2.0, 2.0), (-2.0, -2.0)];
+        let offsets = [
+            (0.0, 0.0),
+            (0.3, 0.2),
+            (-0.2, 0.3),
+            (0.1, -0.3),
+            (-0.3, -0.1),
+        ];
+        let mut rows = Vec::new();
+        let mut labels = Vec::new();
+        for (class, (cx, cy)) in centers.iter().enumerate() {
+            for (dx, dy) in offsets.iter() {
+                rows.extend([1.0, cx + dx, cy + dy]);
+                labels.push(class as f64);
+            }
+        }
+        (
+            Array2::from_shape_vec((labels.len(), 3), rows).unwrap(),
+            Array1::from(labels),
+        )
+    }
+
+    fn argmax(row: ndarray::ArrayView1<f64>) -> usize {
+        row.iter()
+            .enumerate()
+            .fold((0, f64::NEG_INFINITY), |best, (k, &p)| {
+                if p > best.1 {
+                    (k, p)
+                } else {
+                    best
+                }
+            })
+            .0
+    }
+
+    #[test]
+    fn optimizers_converge() {
+        let (X, y) = separable();
+        for (optimizer, learning_rate, batch_size) in [
+            (Optimizer::Sgd, 0.5, 0),
+            (Optimizer::Sgd, 0.1, 8),
+            (Optimizer::Momentum, 0.1, 0),
+            (Optimizer::Adam, 0.05, 0),
+            (Optimizer::Lbfgs, 1.0, 0),
+        ] {
+            let params = Hyperparameters {
+                optimizer,
+                learning_rate,
+                batch_size,
+                epochs: 300,
+                ..Default::default()
+            };
+            params.validate().unwrap();
+            let trained = fit(&X, &y, 2, &params);
+            let probabilities = trained.class_proba(&X).column(1).to_owned();
+            let labels = probabilities.mapv(|p| (p >= trained.threshold) as u32 as f64);
+            assert_eq!(labels, y, "{:?} misclassifies", optimizer);
+            assert!(
+                log_loss(&probabilities, &y) < 0.3,
+                "{:?} did not converge",
+                optimizer
+            );
+        }
+    }
+
+    #[test]
+    fn tolerance_stops_early() {
+        let (X, y) = separable();
+        let params = Hyperparameters {
+            learning_rate: 0.5,
+            epochs: 10_000,
+            tolerance: 1e-4,
+            ..Default::default()
+        };
+        let trained = fit(&X, &y, 2, &params);
+        assert!(trained.epochs < params.epochs);
+    }
+
+    #[test]
+    fn probabilities_sum_to_one() {
+        let Z = array![[1.0, 2.0, 3.0], [-1000.0, 0.0, 1000.0], [0.0, 0.0, 0.0]];
+        let P = softmax(&Z);
+        for row in P.rows() {
+            assert!((row.sum() - 1.0).abs() < 1e-12);
+            assert!(row.iter().all(|p| p.is_finite() && *p >= 0.0));
+        }
+        assert!((P[[2, 0]] - 1.0 / 3.0).abs() < 1e-12);
+
+        let (X, _) = clusters();
+        let theta = array![[0.1, -0.2, 0.3], [1.0, 0.5, -1.0], [-0.5, 2.0, 0.0]];
+        for multiclass in [
+            Multiclass::Binary,
+            Multiclass::Softmax,
+            Multiclass::OneVsRest,
+        ] {
+            let theta = if multiclass == Multiclass::Binary {
+                theta.slice(ndarray::s![.., ..1]).to_owned()
+            } else {
+                theta.clone()
+            };
+            for row in class_proba(&theta, &X, multiclass).rows() {
+                assert!((row.sum() - 1.0).abs() < 1e-12, "{:?}", multiclass);
+            }
+        }
+    }
+
+    #[test]
+    fn multiclass_models_pick_the_class() {
+        let (X, y) = clusters();
+        for multiclass in [Multiclass::OneVsRest, Multiclass::Softmax] {
+            let params = Hyperparameters {
+                multiclass,
+                optimizer: Optimizer::Lbfgs,
+                learning_rate: 1.0,
+                epochs: 200,
+                ..Default::default()
+            };
+            params.validate().unwrap();
+            let trained = fit(&X, &y, 3, &params);
+            assert_eq!(trained.theta.dim(), (3, 3));
+            let probabilities = trained.class_proba(&X);
+            for (row, &label) in probabilities.rows().into_iter().zip(y.iter()) {
+                assert_eq!(argmax(row), label as usize, "{:?}", multiclass);
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_hyperparameters() {
+        assert!(Hyperparameters::default().validate().is_ok());
+        let invalid = [
+            Hyperparameters {
+                learning_rate: 0.0,
+                ..Default::default()
+            },
+            Hyperparameters {
+                learning_rate: f64::NAN,
+                ..Default::default()
+            },
+            Hyperparameters {
+                epochs: 0,
+                ..Default::default()
+            },
+            Hyperparameters {
+                l2: -1.0,
+                ..Default::default()
+            },
+            Hyperparameters {
+                optimizer: Optimizer::Lbfgs,
+                l1: 0.1,
+                ..Default::default()
+            },
+            Hyperparameters {
+                class_weights: ClassWeights::Custom {
+                    negative: 1.0,
+                    positive: 0.0,
+                },
+                ..Default::default()
+            },
+            Hyperparameters {
+                multiclass: Multiclass::Softmax,
+                threshold_metric: Some(ThresholdMetric::F1),
+                ..Default::default()
+            },
+            Hyperparameters {
+                multiclass: Multiclass::Softmax,
+                class_weights: ClassWeights::Custom {
+                    negative: 1.0,
+                    positive: 2.0,
+                },
+                ..Default::default()
+            },
+        ];
+        for params in invalid.iter() {
+            assert!(params.validate().is_err(), "{:?} was accepted", params);
+        }
+    }
+}
@@ -0,0 +1,274 @@
+// Sources of the Beaver triples consumed by the secure computations
+
+use crate::file::dealer_client::DealerClient;
+use crate::file::TripleKind as ProtoTripleKind;
+use crate::file::TripleRequest;
+use crate::mpc::{mat_vec, random_u64s, MpcResult, Transport, Triple, TripleKind, TripleProvider};
+use crate::ot::OtExtension;
+use tonic::transport::Channel;
+
+// Triples fetched from a trusted dealer process. Both parties of a computation
+// ask the dealer for the same sequence of triples under the same computation ID
+// and each of them receives one share of every triple.
+pub struct DealerProvider {
+    client: DealerClient<Channel>,
+    computation_id: String,
+    sequence: u64,
+}
+
+impl DealerProvider {
+    pub fn new(channel: Channel, computation_id: String) -> Self {
+        DealerProvider {
+            client: DealerClient::new(channel),
+            computation_id,
+            sequence: 0,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl TripleProvider for DealerProvider {
+    async fn triple(
+        &mut self,
+        id: usize,
+        _transport: &mut dyn Transport,
+        kind: TripleKind,
+    ) -> MpcResult<Triple> {
+        let (proto_kind, rows, cols) = match kind {
+            TripleKind::Matrix { rows, cols } => (ProtoTripleKind::Matrix, rows, cols),
+            TripleKind::Arithmetic(n) => (ProtoTripleKind::Arithmetic, n, 0),
+            TripleKind::Boolean(n) => (ProtoTripleKind::Boolean, n, 0),
+        };
+        let request = tonic::Request::new(TripleRequest {
+            computation_id: self.computation_id.clone(),
+            party: id as u32,
+            sequence: self.sequence,
+            rows: rows as u32,
+            cols: cols as u32,
+            kind: proto_kind as i32,
+        });
+        self.sequence += 1;
+
+        let shares = self.client.request_triples(request).await?.into_inner();
+        if (shares.a.len(), shares.b.len(), shares.c.len()) != kind.lengths() {
+            return Err("The dealer sent a triple of the wrong size".into());
+        }
+
+        Ok(Triple {
+            a: shares.a,
+            b: shares.b,
+            c: shares.c,
+        })
+    }
+}
+
+// Dealer-free triples generated by the two parties themselves with correlated
+// OTs (Gilboa multiplication): each cross term a0 * b1 or a1 * b0 of a product
+// is split into one OT per bit of the data owner's operand.
+#[derive(Default)]
+pub struct OtProvider {
+    ot: Option<OtExtension>,
+}
+
+impl OtProvider {
+    // Run the correlated OTs, the data owner choosing with `choices`
+    async fn correlated(
+        &mut self,
+        id: usize,
+        transport: &mut dyn Transport,
+        choices: &[bool],
+        correlations: &[u64],
+        lengths: &[usize],
+    ) -> MpcResult<Vec<u64>> {
+        if self.ot.is_none() {
+            self.ot = Some(OtExtension::setup(id, transport).await?);
+        }
+        let ot = self.ot.as_mut().unwrap();
+        if id == 0 {
+            ot.receive_correlated(transport, choices, lengths).await
+        } else {
+            ot.send_correlated(transport, correlations, lengths).await
+        }
+    }
+
+    // Bits of the values, lowest first
+    fn bits(values: &[u64]) -> Vec<bool> {
+        values
+            .iter()
+            .flat_map(|v| (0..64).map(move |k| (v >> k) & 1 == 1))
+            .collect()
+    }
+}
+
+#[tonic::async_trait]
+impl TripleProvider for OtProvider {
+    async fn triple(
+        &mut self,
+        id: usize,
+        transport: &mut dyn Transport,
+        kind: TripleKind,
+    ) -> MpcResult<Triple> {
+        let (a_len, b_len, _) = kind.lengths();
+        let a = random_u64s(a_len);
+        let b = random_u64s(b_len);
+
+        match kind {
+            TripleKind::Matrix { rows, cols } => {
+                // Cross terms: A0 . B1, one OT per bit of A0, and A1 . B0, one OT
+                // per bit of B0 carrying a whole column of A1
+                let mut lengths = vec![1; rows * cols * 64];
+                lengths.extend(vec![rows; cols * 64]);
+
+                let mut choices = Vec::new();
+                let mut correlations = Vec::new();
+                if id == 0 {
+                    choices = Self::bits(&a);
+                    choices.extend(Self::bits(&b));
+                } else {
+                    for _ in 0..rows {
+                        for &b_j in b.iter() {
+                            correlations.extend((0..64).map(|k| b_j << k));
+                        }
+                    }
+                    for j in 0..cols {
+                        for k in 0..64 {
+                            correlations.extend((0..rows).map(|i| a[i * cols + j] << k));
+                        }
+                    }
+                }
+                let output = self
+                    .correlated(id, transport, &choices, &correlations, &lengths)
+                    .await?;
+
+                // Fold the OT outputs into the shares of C
+                let mut c = mat_vec(&a, rows, cols, &b);
+                let (first, second) = output.split_at(rows * cols * 64);
+                for (c_i, row) in c.iter_mut().zip(first.chunks_exact(cols * 64)) {
+                    *c_i = row.iter().fold(*c_i, |acc, x| acc.wrapping_add(*x));
+                }
+                for block in second.chunks_exact(rows) {
+                    for (c_i, x) in c.iter_mut().zip(block.iter()) {
+                        *c_i = c_i.wrapping_add(*x);
+                    }
+                }
+                Ok(Triple { a, b, c })
+            }
+            TripleKind::Arithmetic(n) => {
+                // Cross terms a0 * b1 and a1 * b0, one OT per bit of a0 and b0
+                let lengths = vec![1; 2 * n * 64];
+                let mut choices = Vec::new();
+                let mut correlations = Vec::new();
+                if id == 0 {
+                    choices = Self::bits(&a);
+                    choices.extend(Self::bits(&b));
+                } else {
+                    for &b_i in b.iter() {
+                        correlations.extend((0..64).map(|k| b_i << k));
+                    }
+                    for &a_i in a.iter() {
+                        correlations.extend((0..64).map(|k| a_i << k));
+                    }
+                }
+                let output = self
+                    .correlated(id, transport, &choices, &correlations, &lengths)
+                    .await?;
+
+                let (first, second) = output.split_at(n * 64);
+                let c = (0..n)
+                    .map(|i| {
+                        first[i * 64..(i + 1) * 64]
+                            .iter()
+                            .chain(second[i * 64..(i + 1) * 64].iter())
+                            .fold(a[i].wrapping_mul(b[i]), |acc, x| acc.wrapping_add(*x))
+                    })
+                    .collect();
+                Ok(Triple { a, b, c })
+            }
+            TripleKind::Boolean(n) => {
+                // Cross terms a0 & b1 and a1 & b0, one OT per bit: the lowest bit
+                // of the arithmetic shares of a product of bits is its XOR share
+                let lengths = vec![1; 2 * n * 64];
+                let mut choices = Vec::new();
+                let mut correlations = Vec::new();
+                if id == 0 {
+                    choices = Self::bits(&a);
+                    choices.extend(Self::bits(&b));
+                } else {
+                    for &b_i in b.iter() {
+                        correlations.extend((0..64).map(|k| (b_i >> k) & 1));
+                    }
+                    for &a_i in a.iter() {
+                        correlations.extend((0..64).map(|k| (a_i >> k) & 1));
+                    }
+                }
+                let output = self
+                    .correlated(id, transport, &choices, &correlations, &lengths)
+                    .await?;
+
+                let (first, second) = output.split_at(n * 64);
+                let c = (0..n)
+                    .map(|i| {
+                        (0..64).fold(a[i] & b[i], |acc, k| {
+                            let bit = (first[i * 64 + k] ^ second[i * 64 + k]) & 1;
+                            acc ^ (bit << k)
+                        })
+                    })
+                    .collect();
+                Ok(Triple { a, b, c })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mpc::add;
+    use crate::mpc::testing::transports;
+
+    // Both shares of an OT triple of the given kind
+    async fn ot_triple(kind: TripleKind) -> (Triple, Triple) {
+        let (mut transport0, mut transport1) = transports();
+        let (mut provider0, mut provider1) = (OtProvider::default(), OtProvider::default());
+        let (triple0, triple1) = tokio::join!(
+            provider0.triple(0, &mut transport0, kind),
+            provider1.triple(1, &mut transport1, kind)
+        );
+        (triple0.unwrap(), triple1.unwrap())
+    }
+
+    #[tokio::test]
+    async fn arithmetic_ot_triples_are_consistent() {
+        let (triple0, triple1) = ot_triple(TripleKind::Arithmetic(20)).await;
+        let a = add(&triple0.a, &triple1.a);
+        let b = add(&triple0.b, &triple1.b);
+        let c = add(&triple0.c, &triple1.c);
+        for i in 0..20 {
+            assert_eq!(c[i], a[i].wrapping_mul(b[i]));
+        }
+    }
+
+    #[tokio::test]
+    async fn matrix_ot_triples_are_consistent() {
+        let (rows, cols) = (3, 4);
+        let (triple0, triple1) = ot_triple(TripleKind::Matrix { rows, cols }).await;
+        assert_eq!(triple0.a.len(), rows * cols);
+        assert_eq!(triple0.b.len(), cols);
+        assert_eq!(triple0.c.len(), rows);
+
+        let a = add(&triple0.a, &triple1.a);
+        let b = add(&triple0.b, &triple1.b);
+        let c = add(&triple0.c, &triple1.c);
+        assert_eq!(c, mat_vec(&a, rows, cols, &b));
+    }
+
+    #[tokio::test]
+    async fn boolean_ot_triples_are_consistent() {
+        let (triple0, triple1) = ot_triple(TripleKind::Boolean(20)).await;
+        for i in 0..20 {
+            let a = triple0.a[i] ^ triple1.a[i];
+            let b = triple0.b[i] ^ triple1.b[i];
+            assert_eq!(triple0.c[i] ^ triple1.c[i], a & b);
+        }
+    }
+}